@@ -5,20 +5,27 @@ import {
   type DeviceHard,
   type DeviceSoft,
   type Firm,
+  type FirmAction,
+  type FirmPage,
+  type FirmQuery,
+  type SearchHit,
   type InAddFirm,
   type InAddHardType,
   type InAddSoftType,
   type InUpdatePass,
   type Login,
+  type LoginChallenge,
   type Token,
   type Upload,
 } from ".";
 
 export class Store {
   static token: string = "";
+  static refreshToken: string = "";
   static info: BaseInfo | null = null;
-  static updateToken(newToken: string) {
+  static updateToken(newToken: string, refreshToken: string) {
     this.token = newToken;
+    this.refreshToken = refreshToken;
   }
 
   static baseInfo(info: BaseInfo) {
@@ -27,11 +34,45 @@ export class Store {
 }
 const BASE_URL = "/api";
 
+async function refresh(): Promise<boolean> {
+  if (!Store.refreshToken) {
+    return false;
+  }
+  const response = await fetch(`${BASE_URL}/token/refresh`, {
+    method: "POST",
+    headers: {
+      accept: "application/json",
+      "Content-Type": "application/json",
+    },
+    body: JSON.stringify({ refresh_token: Store.refreshToken }),
+  });
+  if (!response.ok) {
+    Store.updateToken("", "");
+    return false;
+  }
+  const token: Token = await response.json();
+  Store.updateToken(token.access_token, token.refresh_token);
+  return true;
+}
+
+// 访问令牌过期时用刷新令牌换取新令牌后重试一次
+async function send(path: string, init: RequestInit): Promise<Response> {
+  const request = () =>
+    fetch(`${BASE_URL}${path}`, {
+      ...init,
+      headers: { ...init.headers, token: Store.token },
+    });
+  const response = await request();
+  if (response.status === 401 && (await refresh())) {
+    return request();
+  }
+  return response;
+}
+
 async function post<T>(path: string, data: any): Promise<T> {
-  const response = await fetch(`${BASE_URL}${path}`, {
+  const response = await send(path, {
     method: "POST",
     headers: {
-      token: Store.token,
       accept: "application/json",
       "Content-Type": "application/json",
     },
@@ -44,10 +85,9 @@ async function post<T>(path: string, data: any): Promise<T> {
 }
 
 async function put<T>(path: string, data: any): Promise<T> {
-  const response = await fetch(`${BASE_URL}${path}`, {
+  const response = await send(path, {
     method: "PUT",
     headers: {
-      token: Store.token,
       accept: "application/json",
       "Content-Type": "application/json",
     },
@@ -60,10 +100,9 @@ async function put<T>(path: string, data: any): Promise<T> {
 }
 
 async function get<T>(path: string): Promise<T> {
-  const response = await fetch(`${BASE_URL}${path}`, {
+  const response = await send(path, {
     method: "GET",
     headers: {
-      token: Store.token,
       accept: "application/json",
     },
   });
@@ -74,10 +113,9 @@ async function get<T>(path: string): Promise<T> {
 }
 
 async function del<T>(path: string): Promise<T> {
-  const response = await fetch(`${BASE_URL}${path}`, {
+  const response = await send(path, {
     method: "DELETE",
     headers: {
-      token: Store.token,
       accept: "application/json",
     },
   });
@@ -88,7 +126,8 @@ async function del<T>(path: string): Promise<T> {
 }
 
 export class Api {
-  static async login(data: Login): Promise<Token> {
+  // 开启两步验证时返回挑战令牌, 需再调用 loginTotp
+  static async login(data: Login): Promise<Token | LoginChallenge> {
     const response = await fetch(`${BASE_URL}/login`, {
       method: "POST",
       headers: {
@@ -100,13 +139,37 @@ export class Api {
     if (!response.ok) {
       return Promise.reject(new Error(await response.text()));
     }
+    if (response.status === 202) {
+      const challenge: LoginChallenge = await response.json();
+      return Promise.resolve(challenge);
+    }
+    const token: Token = await response.json();
+    Store.updateToken(token.access_token, token.refresh_token);
+    return Promise.resolve(token);
+  }
+
+  static async loginTotp(challenge: LoginChallenge, code: string): Promise<Token> {
+    const response = await fetch(`${BASE_URL}/login/totp`, {
+      method: "POST",
+      headers: {
+        accept: "application/json",
+        "Content-Type": "application/json",
+      },
+      body: JSON.stringify({ challenge_token: challenge.challenge_token, code }),
+    });
+    if (!response.ok) {
+      return Promise.reject(new Error(await response.text()));
+    }
     const token: Token = await response.json();
-    Store.updateToken(token.access_token);
+    Store.updateToken(token.access_token, token.refresh_token);
     return Promise.resolve(token);
   }
 
-  static async updatePass(data: InUpdatePass): Promise<ApiResponse> {
-    return post("/user/updatePass", data);
+  // 其他登录会失效, 当前会话换用返回的新令牌
+  static async updatePass(data: InUpdatePass): Promise<Token> {
+    const token: Token = await post("/user/updatePass", data);
+    Store.updateToken(token.access_token, token.refresh_token);
+    return token;
   }
 
   static async deviceHard(): Promise<Array<DeviceHard>> {
@@ -137,8 +200,15 @@ export class Api {
     return put("/softTypes", data);
   }
 
-  static async firms(): Promise<Array<Firm>> {
-    return get("/firms");
+  static async firms(query: FirmQuery = {}): Promise<FirmPage> {
+    const params = new URLSearchParams();
+    for (const [key, value] of Object.entries(query)) {
+      if (value !== undefined && value !== "") {
+        params.append(key, `${value}`);
+      }
+    }
+    const search = params.toString();
+    return get(search ? `/firms?${search}` : "/firms");
   }
 
   static async updateFirm(data: Firm): Promise<ApiResponse> {
@@ -153,28 +223,30 @@ export class Api {
     return post("/firms", data);
   }
 
+  static async releaseFirm(firm: Firm, action: FirmAction): Promise<ApiResponse> {
+    return post(`/release/${firm.id}/${action}`, {});
+  }
+
+  static async search(q: string): Promise<Array<SearchHit>> {
+    return get(`/search?${new URLSearchParams({ q })}`);
+  }
+
   static async deviceFirms(deviceId: number): Promise<Array<Firm>> {
     return get(`/firms/${deviceId}`);
   }
 
-  static async uploadFirm<T>(file: File): Promise<Upload> {
+  static async uploadFirm(file: File): Promise<Upload> {
     const form = new FormData();
     form.append("file", file);
-    form.append(
-      "upload_preset",
-      import.meta.env.VITE_CLOUDINARY_PRESET || "**"
-    );
-    form.append("api_key", "");
-    form.append("source", "ml");
-    const response = await fetch(
-      "https://api.cloudinary.com/v1_1/xiaolong/upload",
-      {
-        method: "POST",
-        body: form,
-      }
-    );
+    const response = await send("/firms/upload", {
+      method: "POST",
+      headers: {
+        accept: "application/json",
+      },
+      body: form,
+    });
     if (!response.ok) {
-      return Promise.reject(new Error(await response.text()));
+      return Promise.reject(new ApiError(response.status, await response.text()));
     }
     return response.json();
   }
@@ -15,36 +15,53 @@ export interface DeviceSoft {
   readonly name: string;
 }
 
+export interface Channel {
+  readonly id: number;
+  readonly name: string;
+  readonly level: number;
+  readonly desc: string;
+}
+
 export interface BaseInfo {
   readonly hard: Array<DeviceHard>;
   readonly soft: Array<DeviceSoft>;
+  readonly channels: Array<Channel>;
 }
 
+export type Role = "Viewer" | "ReleaseEngineer" | "Admin";
+
 export interface User {
   readonly name: string;
   readonly ticker: number;
+  readonly role: Role;
 }
 
 export interface Token {
   readonly access_token: string;
+  readonly refresh_token: string;
   readonly user: User;
 }
 
+// locale 为 BCP-47 语言标签, 如 en、zh-Hant、es-419
+export interface FirmNote {
+  readonly locale: string;
+  readonly text: string;
+}
+
 export interface InAddFirm {
   readonly hard_version: number;
   readonly version_name: string;
   readonly version_format: string;
   readonly version_type: number;
   readonly finger_level: number;
-  readonly url: string;
+  readonly url?: string;
+  readonly blob_key?: string;
   readonly desc: string;
   readonly update_time: number;
   readonly rely_version_type?: number;
   readonly min?: string;
   readonly max?: string;
-  readonly des_en: string;
-  readonly des_ko: string;
-  readonly des_sp: string;
+  readonly notes: Array<FirmNote>;
 }
 
 export interface InAddHardType {
@@ -61,6 +78,25 @@ export interface InAddSoftType {
   readonly name: string;
 }
 
+export type FirmStatus = "Draft" | "Testing" | "Published" | "Deprecated" | "Revoked";
+export type FirmAction = "draft" | "test" | "publish" | "deprecate" | "revoke";
+
+export interface Rollout {
+  readonly percent: number;
+  readonly allowlist: Array<string>;
+  readonly start_time?: number;
+  readonly paused: boolean;
+}
+
+export interface InstallStats {
+  readonly downloads: number;
+  readonly successes: number;
+  readonly failures: number;
+  readonly failure_rate: number;
+}
+
+export type ChecksumState = "Unverified" | "Valid" | "Mismatch" | "Missing";
+
 export interface Firm {
   readonly id: number;
   readonly hard_version: number;
@@ -68,15 +104,73 @@ export interface Firm {
   readonly version_format: string;
   readonly version_type: number;
   readonly finger_level: number;
-  readonly url: string;
+  readonly url?: string;
+  readonly blob_key?: string;
+  readonly size?: number;
+  readonly content_type?: string;
+  readonly sha256?: string;
+  readonly crc32?: string;
+  readonly md5?: string;
+  readonly checksum_state?: ChecksumState;
+  readonly verify_time?: number;
+  readonly status?: FirmStatus;
+  readonly rollout?: Rollout;
+  readonly channel_id?: number;
+  readonly installs?: InstallStats;
   readonly desc: string;
   readonly update_time: number;
   readonly rely_version_type?: number;
   readonly min?: string;
   readonly max?: string;
-  readonly des_en: string;
-  readonly des_ko: string;
-  readonly des_sp: string;
+  readonly notes: Array<FirmNote>;
+  // 按 Accept-Language 协商的说明, 没有匹配的语言时为 desc
+  readonly note?: string;
+  readonly note_locale?: string;
+}
+
+export type FirmSort =
+  | "version"
+  | "update_time"
+  | "-update_time"
+  | "version_name"
+  | "-version_name"
+  | "id"
+  | "-id";
+
+export interface FirmQuery {
+  readonly hard_version?: number;
+  readonly version_type?: number;
+  readonly finger_level?: number;
+  readonly channel?: string;
+  // 更新时间范围, 秒级时间戳
+  readonly from?: number;
+  readonly to?: number;
+  readonly q?: string;
+  readonly sort?: FirmSort;
+  readonly page?: number;
+  readonly page_size?: number;
+  readonly cursor?: string;
+}
+
+export interface FirmPage {
+  readonly total: number;
+  readonly page: number;
+  readonly page_size: number;
+  readonly next_cursor?: string;
+  readonly items: Array<Firm>;
+}
+
+export interface Snippet {
+  // version_name、desc 或 note
+  readonly field: string;
+  readonly locale?: string;
+  // 已做 HTML 转义, 关键词以 <mark></mark> 标出
+  readonly text: string;
+}
+
+export interface SearchHit {
+  readonly firm: Firm;
+  readonly snippets: Array<Snippet>;
 }
 
 export interface Login {
@@ -84,14 +178,25 @@ export interface Login {
   readonly password: string;
 }
 
+// 开启两步验证的账号登录时返回
+export interface LoginChallenge {
+  readonly challenge_token: string;
+  readonly expires_in: number;
+}
+
 export interface InUpdatePass {
   readonly old_pass: string;
   readonly new_pass: string;
 }
 
 export interface Upload {
-  readonly public_id: string;
-  readonly secure_url: string;
+  readonly key: string;
+  readonly file_name: string;
+  readonly size: number;
+  readonly content_type: string;
+  readonly sha256: string;
+  readonly crc32: string;
+  readonly md5: string;
 }
 
 export interface ApiResponse {
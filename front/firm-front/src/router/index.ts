@@ -15,6 +15,10 @@ const router = createRouter({
     {
       path: '/soft',
       component: () => import('../views/DeviceSoft.vue')
+    },
+    {
+      path: '/search',
+      component: () => import('../views/SearchView.vue')
     }
   ]
 })
@@ -0,0 +1,77 @@
+-- 初始表结构, 与迁移前手工执行 init.sql 建立的数据库一致
+CREATE TABLE IF NOT EXISTS "device_type" (
+	"id"	INTEGER,
+	"hard_version"	TEXT NOT NULL UNIQUE,
+	"name"	TEXT NOT NULL UNIQUE,
+	"category"	INTEGER NOT NULL,
+	"has_ble"	INTEGER NOT NULL DEFAULT 1,
+	"has_finger"	INTEGER NOT NULL DEFAULT 1,
+	"has_stm32"	INTEGER NOT NULL DEFAULT 0,
+	"desc"	TEXT,
+	PRIMARY KEY("id" AUTOINCREMENT)
+);
+
+CREATE TABLE IF NOT EXISTS "firm" (
+	"id"	INTEGER,
+	"hard_version"	INTEGER NOT NULL,
+	"version_name"	TEXT NOT NULL,
+	"version_format"	TEXT,
+	"version_type"	INTEGER NOT NULL,
+	"finger_level"	INTEGER NOT NULL DEFAULT 0,
+	"url"	TEXT NOT NULL,
+	"desc"	TEXT NOT NULL DEFAULT '',
+	"update_time"	datetime DEFAULT current_timestamp,
+	"rely_version_type"	INTEGER,
+	"min"	TEXT,
+	"max"	TEXT,
+	"des_en"	TEXT DEFAULT '',
+	"des_ko"	TEXT DEFAULT '',
+	"des_sp"	TEXT DEFAULT '',
+	PRIMARY KEY("id" AUTOINCREMENT)
+);
+
+CREATE TABLE IF NOT EXISTS "user" (
+	"id"	INTEGER,
+	"name"	TEXT,
+	"mail"	TEXT UNIQUE,
+	"password"	TEXT,
+	"update_time"	datetime DEFAULT current_timestamp,
+	PRIMARY KEY("id" AUTOINCREMENT)
+);
+
+CREATE TABLE IF NOT EXISTS "version_type" (
+	"id"	INTEGER,
+	"name"	TEXT,
+	PRIMARY KEY("id" AUTOINCREMENT)
+);
+
+-- 旧库中 firm.url 声明为 INTEGER, 重建 firm 表改为 TEXT
+CREATE TABLE "firm_0001" (
+	"id"	INTEGER,
+	"hard_version"	INTEGER NOT NULL,
+	"version_name"	TEXT NOT NULL,
+	"version_format"	TEXT,
+	"version_type"	INTEGER NOT NULL,
+	"finger_level"	INTEGER NOT NULL DEFAULT 0,
+	"url"	TEXT NOT NULL,
+	"desc"	TEXT NOT NULL DEFAULT '',
+	"update_time"	datetime DEFAULT current_timestamp,
+	"rely_version_type"	INTEGER,
+	"min"	TEXT,
+	"max"	TEXT,
+	"des_en"	TEXT DEFAULT '',
+	"des_ko"	TEXT DEFAULT '',
+	"des_sp"	TEXT DEFAULT '',
+	PRIMARY KEY("id" AUTOINCREMENT)
+);
+
+INSERT INTO "firm_0001" ("id", "hard_version", "version_name", "version_format", "version_type", "finger_level", "url", "desc", "update_time", "rely_version_type", "min", "max", "des_en", "des_ko", "des_sp")
+	SELECT "id", "hard_version", "version_name", "version_format", "version_type", "finger_level", CAST("url" AS TEXT), "desc", "update_time", "rely_version_type", "min", "max", "des_en", "des_ko", "des_sp" FROM "firm";
+
+-- 保留自增序号, 避免复用已删除固件的 id
+DELETE FROM "sqlite_sequence" WHERE "name" = 'firm_0001';
+INSERT INTO "sqlite_sequence" ("name", "seq")
+	SELECT 'firm_0001', "seq" FROM "sqlite_sequence" WHERE "name" = 'firm';
+
+DROP TABLE "firm";
+ALTER TABLE "firm_0001" RENAME TO "firm";
@@ -0,0 +1,145 @@
+-- 发布管理: 文件存储与校验、签名、发布状态、分阶段发布、渠道、设备登记、安装上报,
+-- 以及用户角色、邀请、两步验证、审计和 API key
+
+ALTER TABLE "firm" ADD COLUMN "size"	INTEGER;
+ALTER TABLE "firm" ADD COLUMN "content_type"	TEXT;
+ALTER TABLE "firm" ADD COLUMN "blob_key"	TEXT;
+ALTER TABLE "firm" ADD COLUMN "sha256"	TEXT;
+ALTER TABLE "firm" ADD COLUMN "crc32"	TEXT;
+ALTER TABLE "firm" ADD COLUMN "md5"	TEXT;
+ALTER TABLE "firm" ADD COLUMN "checksum_state"	INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE "firm" ADD COLUMN "verify_time"	datetime;
+ALTER TABLE "firm" ADD COLUMN "signature"	TEXT;
+ALTER TABLE "firm" ADD COLUMN "signature_key_id"	TEXT;
+-- 已有固件视为已发布
+ALTER TABLE "firm" ADD COLUMN "status"	INTEGER NOT NULL DEFAULT 2;
+ALTER TABLE "firm" ADD COLUMN "status_time"	datetime;
+ALTER TABLE "firm" ADD COLUMN "rollout_percent"	INTEGER NOT NULL DEFAULT 100;
+ALTER TABLE "firm" ADD COLUMN "rollout_allowlist"	TEXT NOT NULL DEFAULT '[]';
+ALTER TABLE "firm" ADD COLUMN "rollout_start"	datetime;
+ALTER TABLE "firm" ADD COLUMN "rollout_paused"	INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE "firm" ADD COLUMN "channel_id"	INTEGER NOT NULL DEFAULT 1;
+
+ALTER TABLE "user" ADD COLUMN "role"	INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE "user" ADD COLUMN "disabled"	INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE "user" ADD COLUMN "invite_hash"	TEXT;
+ALTER TABLE "user" ADD COLUMN "invite_expire"	datetime;
+ALTER TABLE "user" ADD COLUMN "totp_secret"	TEXT;
+ALTER TABLE "user" ADD COLUMN "totp_enabled"	INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE "user" ADD COLUMN "totp_step"	INTEGER NOT NULL DEFAULT 0;
+
+-- 引入角色前所有账号都有全部权限, 保留为管理员
+UPDATE "user" SET "role" = 2;
+
+CREATE TABLE IF NOT EXISTS "blob" (
+	"key"	TEXT NOT NULL,
+	"file_name"	TEXT NOT NULL DEFAULT '',
+	"size"	INTEGER NOT NULL,
+	"content_type"	TEXT NOT NULL,
+	"sha256"	TEXT NOT NULL,
+	"crc32"	TEXT NOT NULL,
+	"md5"	TEXT NOT NULL,
+	"update_time"	datetime DEFAULT current_timestamp,
+	PRIMARY KEY("key")
+);
+
+CREATE TABLE IF NOT EXISTS "channel" (
+	"id"	INTEGER,
+	"name"	TEXT NOT NULL UNIQUE,
+	"level"	INTEGER NOT NULL DEFAULT 0,
+	"desc"	TEXT NOT NULL DEFAULT '',
+	PRIMARY KEY("id" AUTOINCREMENT)
+);
+
+INSERT OR IGNORE INTO "channel" ("id", "name", "level", "desc") VALUES
+	(1, 'stable', 0, ''),
+	(2, 'beta', 1, ''),
+	(3, 'internal', 2, '');
+
+CREATE TABLE IF NOT EXISTS "channel_assign" (
+	"id"	INTEGER,
+	"target_type"	INTEGER NOT NULL,
+	"target"	TEXT NOT NULL,
+	"channel_id"	INTEGER NOT NULL,
+	UNIQUE("target_type", "target"),
+	PRIMARY KEY("id" AUTOINCREMENT)
+);
+
+CREATE TABLE IF NOT EXISTS "device" (
+	"id"	INTEGER,
+	"serial"	TEXT NOT NULL UNIQUE,
+	"mac"	TEXT UNIQUE,
+	"device_type"	INTEGER NOT NULL,
+	"group_name"	TEXT,
+	"firmware"	TEXT NOT NULL DEFAULT '[]',
+	"check_in_time"	datetime,
+	"desc"	TEXT NOT NULL DEFAULT '',
+	"update_time"	datetime NOT NULL,
+	PRIMARY KEY("id" AUTOINCREMENT)
+);
+
+CREATE TABLE IF NOT EXISTS "install_report" (
+	"id"	INTEGER,
+	"firm_id"	INTEGER NOT NULL,
+	"device_id"	TEXT NOT NULL,
+	"event"	INTEGER NOT NULL,
+	"error_code"	INTEGER,
+	"running_version"	TEXT,
+	"report_time"	datetime NOT NULL,
+	PRIMARY KEY("id" AUTOINCREMENT)
+);
+
+CREATE INDEX IF NOT EXISTS "install_report_firm" ON "install_report" ("firm_id", "device_id");
+
+CREATE TABLE IF NOT EXISTS "refresh_token" (
+	"jti"	TEXT NOT NULL,
+	"family"	TEXT NOT NULL,
+	"user_id"	INTEGER NOT NULL,
+	"used"	INTEGER NOT NULL DEFAULT 0,
+	"revoked"	INTEGER NOT NULL DEFAULT 0,
+	"expire_time"	datetime NOT NULL,
+	PRIMARY KEY("jti")
+);
+
+CREATE INDEX IF NOT EXISTS "refresh_token_family" ON "refresh_token" ("family");
+
+CREATE TABLE IF NOT EXISTS "audit" (
+	"id"	INTEGER,
+	"user_id"	INTEGER,
+	"action"	TEXT NOT NULL,
+	"entity"	INTEGER NOT NULL,
+	"entity_id"	TEXT,
+	"before"	TEXT,
+	"after"	TEXT,
+	"ip"	TEXT,
+	"create_time"	datetime NOT NULL,
+	PRIMARY KEY("id" AUTOINCREMENT)
+);
+
+CREATE INDEX IF NOT EXISTS "audit_entity" ON "audit" ("entity", "entity_id");
+CREATE INDEX IF NOT EXISTS "audit_user" ON "audit" ("user_id");
+
+CREATE TABLE IF NOT EXISTS "recovery_code" (
+	"id"	INTEGER,
+	"user_id"	INTEGER NOT NULL,
+	"code_hash"	TEXT NOT NULL,
+	"used"	INTEGER NOT NULL DEFAULT 0,
+	PRIMARY KEY("id" AUTOINCREMENT)
+);
+
+CREATE INDEX IF NOT EXISTS "recovery_code_user" ON "recovery_code" ("user_id");
+
+CREATE TABLE IF NOT EXISTS "api_key" (
+	"id"	INTEGER,
+	"user_id"	INTEGER NOT NULL,
+	"name"	TEXT NOT NULL,
+	"prefix"	TEXT NOT NULL,
+	"key_hash"	TEXT NOT NULL UNIQUE,
+	"scopes"	TEXT NOT NULL DEFAULT '[]',
+	"create_time"	datetime DEFAULT current_timestamp,
+	"last_used_time"	datetime,
+	"revoked"	INTEGER NOT NULL DEFAULT 0,
+	PRIMARY KEY("id" AUTOINCREMENT)
+);
+
+CREATE INDEX IF NOT EXISTS "api_key_user" ON "api_key" ("user_id");
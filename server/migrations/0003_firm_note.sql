@@ -0,0 +1,33 @@
+-- 多语言更新说明: 将 des_en、des_ko、des_sp 迁移到 firm_note, locale 为 BCP-47 语言标签,
+-- 没有匹配的语言时使用 firm.desc
+CREATE TABLE IF NOT EXISTS "firm_note" (
+	"firm_id"	INTEGER NOT NULL,
+	"locale"	TEXT NOT NULL,
+	"text"	TEXT NOT NULL,
+	PRIMARY KEY("firm_id", "locale")
+);
+
+INSERT OR IGNORE INTO "firm_note" ("firm_id", "locale", "text")
+	SELECT "id", 'en', "des_en" FROM "firm" WHERE IFNULL("des_en", '') != '';
+INSERT OR IGNORE INTO "firm_note" ("firm_id", "locale", "text")
+	SELECT "id", 'ko', "des_ko" FROM "firm" WHERE IFNULL("des_ko", '') != '';
+INSERT OR IGNORE INTO "firm_note" ("firm_id", "locale", "text")
+	SELECT "id", 'es', "des_sp" FROM "firm" WHERE IFNULL("des_sp", '') != '';
+
+ALTER TABLE "firm" DROP COLUMN "des_en";
+ALTER TABLE "firm" DROP COLUMN "des_ko";
+ALTER TABLE "firm" DROP COLUMN "des_sp";
+
+-- 固件说明的全文索引, rowid 对应 firm.id, notes 为各语言说明按行拼接, 由服务端在增删改固件时同步
+-- trigram 分词可匹配中文、韩文的子串
+CREATE VIRTUAL TABLE IF NOT EXISTS "firm_fts" USING fts5(
+	"version_name",
+	"desc",
+	"notes",
+	tokenize = 'trigram'
+);
+
+INSERT INTO "firm_fts" ("rowid", "version_name", "desc", "notes")
+	SELECT "id", "version_name", "desc",
+		IFNULL((SELECT group_concat("text", char(10)) FROM "firm_note" WHERE "firm_id" = "firm"."id"), '')
+	FROM "firm";
@@ -0,0 +1,5 @@
+-- 固件列表按版本号排序、翻页用的排序键 (`Version::sort_key`), 无法解析的版本为空字符串
+-- 由服务端在增改固件时写入, 已有数据在迁移后由服务端补齐
+ALTER TABLE "firm" ADD COLUMN "version_key" TEXT;
+
+CREATE INDEX IF NOT EXISTS "firm_version_key" ON "firm" ("version_key", "id");
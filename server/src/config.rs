@@ -0,0 +1,436 @@
+use std::{env, fs, io::ErrorKind, path::PathBuf};
+
+use once_cell::sync::OnceCell;
+use serde::Deserialize;
+use thiserror::Error;
+
+/// 开发用的默认密钥, 非开发模式下禁止使用
+const DEFAULT_SECRET: &str = "token_key";
+const DEFAULT_DEVICE_KEY: &str = "device_key";
+
+/// 服务配置, 读取 `FIRM_CONFIG` 指定的文件 (默认 `./config.toml`), 再以 `FIRM_*` 环境变量覆盖
+///
+/// ```toml
+/// dev = false
+///
+/// [server]
+/// bind = "0.0.0.0:3000"
+/// api_url = "https://firm.example.com"
+/// static_dir = "./dist"
+/// cors_origins = ["https://admin.example.com"]
+/// trust_proxy = true
+///
+/// [database]
+/// url = "sqlite://firm.db"
+/// auto_migrate = true
+///
+/// [auth]
+/// secret_file = "/run/secrets/firm_token"
+/// access_ttl = 7200
+/// refresh_ttl = 604800
+/// device_key = "..."
+///
+/// [lockout]
+/// free_attempts = 3
+/// max_failures = 10
+/// lock_duration = 900
+///
+/// [password]
+/// min_length = 10
+/// denylist_file = "./common-passwords.txt"
+///
+/// [storage]
+/// blob_dir = "./blobs"
+/// keystore = "./keystore.json"
+/// max_file_size = 268435456
+/// ```
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// 开发模式, debug 构建默认开启
+    pub dev: bool,
+    pub server: ServerConfig,
+    pub database: DatabaseConfig,
+    pub auth: AuthConfig,
+    pub lockout: LockoutConfig,
+    pub password: PasswordConfig,
+    pub storage: StorageConfig,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub bind: String,
+    /// OpenAPI 文档中的服务地址
+    pub api_url: String,
+    pub static_dir: PathBuf,
+    /// 为空时开发模式允许任意来源, 否则不允许跨域
+    pub cors_origins: Vec<String>,
+    /// 位于反向代理后时, 以代理追加到 `X-Forwarded-For` 末尾的地址作为客户端地址
+    pub trust_proxy: bool,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DatabaseConfig {
+    pub url: String,
+    /// 启动时执行数据库迁移, 关闭后需先运行 `firm_management migrate`
+    pub auto_migrate: bool,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuthConfig {
+    /// JWT 密钥, `secret_file` 优先
+    pub secret: String,
+    pub secret_file: Option<PathBuf>,
+    /// 访问令牌有效期, 秒
+    pub access_ttl: i64,
+    /// 刷新令牌有效期, 秒
+    pub refresh_ttl: i64,
+    /// 设备端接口密钥
+    pub device_key: String,
+    /// 两步验证在认证器中显示的名称
+    pub totp_issuer: String,
+}
+
+/// 登录失败的退避和锁定策略, 时间单位为秒
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LockoutConfig {
+    /// 超过该失败次数后开始指数退避
+    pub free_attempts: u32,
+    /// 首次退避时长, 之后每次失败翻倍
+    pub base_delay: u64,
+    pub max_delay: u64,
+    /// 账号连续失败达到该次数后锁定
+    pub max_failures: u32,
+    /// 同一 IP 失败达到该次数后拒绝登录
+    pub ip_max_failures: u32,
+    pub lock_duration: u64,
+    /// 无失败超过该时长后清零计数
+    pub reset_after: u64,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PasswordConfig {
+    pub min_length: usize,
+    /// 常见或已泄露密码列表, 每行一个
+    pub denylist_file: Option<PathBuf>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StorageConfig {
+    pub blob_dir: PathBuf,
+    pub keystore: PathBuf,
+    /// 上传或下载的固件文件大小上限, 字节
+    pub max_file_size: u64,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            dev: cfg!(debug_assertions),
+            server: ServerConfig::default(),
+            database: DatabaseConfig::default(),
+            auth: AuthConfig::default(),
+            lockout: LockoutConfig::default(),
+            password: PasswordConfig::default(),
+            storage: StorageConfig::default(),
+        }
+    }
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            bind: "0.0.0.0:3000".to_string(),
+            api_url: "http://0.0.0.0:3000".to_string(),
+            static_dir: "./dist".into(),
+            cors_origins: Vec::new(),
+            trust_proxy: false,
+        }
+    }
+}
+
+impl Default for DatabaseConfig {
+    fn default() -> Self {
+        DatabaseConfig {
+            url: "sqlite://firm.db".to_string(),
+            auto_migrate: true,
+        }
+    }
+}
+
+impl Default for AuthConfig {
+    fn default() -> Self {
+        AuthConfig {
+            secret: DEFAULT_SECRET.to_string(),
+            secret_file: None,
+            access_ttl: 7200,
+            refresh_ttl: 60 * 60 * 24 * 7,
+            device_key: DEFAULT_DEVICE_KEY.to_string(),
+            totp_issuer: "firm_management".to_string(),
+        }
+    }
+}
+
+impl Default for LockoutConfig {
+    fn default() -> Self {
+        LockoutConfig {
+            free_attempts: 3,
+            base_delay: 1,
+            max_delay: 300,
+            max_failures: 10,
+            ip_max_failures: 50,
+            lock_duration: 900,
+            reset_after: 3600,
+        }
+    }
+}
+
+impl Default for PasswordConfig {
+    fn default() -> Self {
+        PasswordConfig {
+            min_length: 8,
+            denylist_file: None,
+        }
+    }
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        StorageConfig {
+            blob_dir: "./blobs".into(),
+            keystore: "./keystore.json".into(),
+            max_file_size: 256 * 1024 * 1024,
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum ConfigError {
+    #[error("read config `{0}`: {1}")]
+    Read(PathBuf, std::io::Error),
+    #[error("parse config: {0}")]
+    Parse(#[from] toml::de::Error),
+    #[error("invalid env `{0}`")]
+    Env(&'static str),
+    #[error("invalid config: {0}")]
+    Invalid(String),
+}
+
+static CONFIG: OnceCell<Config> = OnceCell::new();
+
+/// 启动时设置全局配置
+pub fn init_config(config: Config) {
+    let _ = CONFIG.set(config);
+}
+
+pub fn config() -> &'static Config {
+    CONFIG.get_or_init(Config::default)
+}
+
+impl Config {
+    /// 读取配置文件和环境变量, 并校验
+    pub fn load() -> Result<Config, ConfigError> {
+        let path: PathBuf = env::var_os("FIRM_CONFIG")
+            .map(PathBuf::from)
+            .unwrap_or_else(|| "./config.toml".into());
+        let mut config = match fs::read_to_string(&path) {
+            Ok(content) => Config::parse(&content)?,
+            Err(e) if e.kind() == ErrorKind::NotFound && env::var_os("FIRM_CONFIG").is_none() => {
+                Config::default()
+            }
+            Err(e) => return Err(ConfigError::Read(path, e)),
+        };
+        config.apply_env(|k| env::var(k).ok())?;
+        if let Some(file) = &config.auth.secret_file {
+            config.auth.secret = fs::read_to_string(file)
+                .map_err(|e| ConfigError::Read(file.clone(), e))?
+                .trim()
+                .to_string();
+        }
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn parse(content: &str) -> Result<Config, ConfigError> {
+        Ok(toml::from_str(content)?)
+    }
+
+    fn apply_env(&mut self, var: impl Fn(&str) -> Option<String>) -> Result<(), ConfigError> {
+        fn parse<T: std::str::FromStr>(name: &'static str, v: String) -> Result<T, ConfigError> {
+            v.parse().map_err(|_| ConfigError::Env(name))
+        }
+        if let Some(v) = var("FIRM_DEV") {
+            self.dev = parse("FIRM_DEV", v)?;
+        }
+        if let Some(v) = var("FIRM_SERVER_BIND") {
+            self.server.bind = v;
+        }
+        if let Some(v) = var("FIRM_SERVER_API_URL") {
+            self.server.api_url = v;
+        }
+        if let Some(v) = var("FIRM_SERVER_STATIC_DIR") {
+            self.server.static_dir = v.into();
+        }
+        if let Some(v) = var("FIRM_SERVER_CORS_ORIGINS") {
+            self.server.cors_origins = v
+                .split(',')
+                .map(str::trim)
+                .filter(|o| !o.is_empty())
+                .map(String::from)
+                .collect();
+        }
+        if let Some(v) = var("FIRM_SERVER_TRUST_PROXY") {
+            self.server.trust_proxy = parse("FIRM_SERVER_TRUST_PROXY", v)?;
+        }
+        if let Some(v) = var("FIRM_DATABASE_URL") {
+            self.database.url = v;
+        }
+        if let Some(v) = var("FIRM_DATABASE_AUTO_MIGRATE") {
+            self.database.auto_migrate = parse("FIRM_DATABASE_AUTO_MIGRATE", v)?;
+        }
+        if let Some(v) = var("FIRM_AUTH_SECRET") {
+            self.auth.secret = v;
+        }
+        if let Some(v) = var("FIRM_AUTH_SECRET_FILE") {
+            self.auth.secret_file = Some(v.into());
+        }
+        if let Some(v) = var("FIRM_AUTH_ACCESS_TTL") {
+            self.auth.access_ttl = parse("FIRM_AUTH_ACCESS_TTL", v)?;
+        }
+        if let Some(v) = var("FIRM_AUTH_REFRESH_TTL") {
+            self.auth.refresh_ttl = parse("FIRM_AUTH_REFRESH_TTL", v)?;
+        }
+        if let Some(v) = var("FIRM_AUTH_DEVICE_KEY") {
+            self.auth.device_key = v;
+        }
+        if let Some(v) = var("FIRM_AUTH_TOTP_ISSUER") {
+            self.auth.totp_issuer = v;
+        }
+        if let Some(v) = var("FIRM_LOCKOUT_FREE_ATTEMPTS") {
+            self.lockout.free_attempts = parse("FIRM_LOCKOUT_FREE_ATTEMPTS", v)?;
+        }
+        if let Some(v) = var("FIRM_LOCKOUT_BASE_DELAY") {
+            self.lockout.base_delay = parse("FIRM_LOCKOUT_BASE_DELAY", v)?;
+        }
+        if let Some(v) = var("FIRM_LOCKOUT_MAX_DELAY") {
+            self.lockout.max_delay = parse("FIRM_LOCKOUT_MAX_DELAY", v)?;
+        }
+        if let Some(v) = var("FIRM_LOCKOUT_MAX_FAILURES") {
+            self.lockout.max_failures = parse("FIRM_LOCKOUT_MAX_FAILURES", v)?;
+        }
+        if let Some(v) = var("FIRM_LOCKOUT_IP_MAX_FAILURES") {
+            self.lockout.ip_max_failures = parse("FIRM_LOCKOUT_IP_MAX_FAILURES", v)?;
+        }
+        if let Some(v) = var("FIRM_LOCKOUT_LOCK_DURATION") {
+            self.lockout.lock_duration = parse("FIRM_LOCKOUT_LOCK_DURATION", v)?;
+        }
+        if let Some(v) = var("FIRM_LOCKOUT_RESET_AFTER") {
+            self.lockout.reset_after = parse("FIRM_LOCKOUT_RESET_AFTER", v)?;
+        }
+        if let Some(v) = var("FIRM_PASSWORD_MIN_LENGTH") {
+            self.password.min_length = parse("FIRM_PASSWORD_MIN_LENGTH", v)?;
+        }
+        if let Some(v) = var("FIRM_PASSWORD_DENYLIST_FILE") {
+            self.password.denylist_file = Some(v.into());
+        }
+        if let Some(v) = var("FIRM_STORAGE_BLOB_DIR") {
+            self.storage.blob_dir = v.into();
+        }
+        if let Some(v) = var("FIRM_STORAGE_KEYSTORE") {
+            self.storage.keystore = v.into();
+        }
+        if let Some(v) = var("FIRM_STORAGE_MAX_FILE_SIZE") {
+            self.storage.max_file_size = parse("FIRM_STORAGE_MAX_FILE_SIZE", v)?;
+        }
+        Ok(())
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        let invalid = |msg: &str| Err(ConfigError::Invalid(msg.to_string()));
+        if self.auth.secret.is_empty() {
+            return invalid("auth.secret is empty");
+        }
+        if self.auth.access_ttl <= 0 || self.auth.refresh_ttl <= self.auth.access_ttl {
+            return invalid("auth.refresh_ttl must be longer than auth.access_ttl");
+        }
+        if self.auth.device_key.is_empty() {
+            return invalid("auth.device_key is empty");
+        }
+        let lockout = &self.lockout;
+        if lockout.max_failures <= lockout.free_attempts
+            || lockout.ip_max_failures <= lockout.free_attempts
+        {
+            return invalid("lockout.max_failures must be greater than lockout.free_attempts");
+        }
+        if lockout.base_delay == 0 || lockout.max_delay < lockout.base_delay {
+            return invalid("lockout.max_delay must be at least lockout.base_delay");
+        }
+        if self.password.min_length == 0 || self.password.min_length > 72 {
+            return invalid("password.min_length must be between 1 and 72");
+        }
+        if self.storage.max_file_size == 0 {
+            return invalid("storage.max_file_size must be greater than 0");
+        }
+        if !self.dev {
+            if self.auth.secret == DEFAULT_SECRET || self.auth.secret.len() < 32 {
+                return invalid(
+                    "auth.secret must be set to at least 32 characters outside dev mode",
+                );
+            }
+            if self.auth.device_key == DEFAULT_DEVICE_KEY {
+                return invalid("auth.device_key must be set outside dev mode");
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use super::Config;
+
+    #[test]
+    pub fn test_config() {
+        let mut config = Config::parse(
+            "dev = false\n[server]\nbind = \"127.0.0.1:8080\"\n[auth]\ndevice_key = \"k\"\n",
+        )
+        .unwrap();
+        assert_eq!(config.server.bind, "127.0.0.1:8080");
+        assert_eq!(config.database.url, "sqlite://firm.db");
+        assert!(config.database.auto_migrate);
+        assert!(config.validate().is_err());
+
+        let env: HashMap<&str, &str> = [
+            ("FIRM_AUTH_SECRET", "0123456789abcdef0123456789abcdef"),
+            ("FIRM_SERVER_CORS_ORIGINS", "https://a.com, https://b.com"),
+            ("FIRM_AUTH_ACCESS_TTL", "600"),
+            ("FIRM_LOCKOUT_BASE_DELAY", "2"),
+            ("FIRM_LOCKOUT_RESET_AFTER", "120"),
+        ]
+        .into_iter()
+        .collect();
+        config
+            .apply_env(|k| env.get(k).map(|v| v.to_string()))
+            .unwrap();
+        assert_eq!(config.server.cors_origins.len(), 2);
+        assert_eq!(config.auth.access_ttl, 600);
+        assert_eq!(config.lockout.base_delay, 2);
+        assert_eq!(config.lockout.reset_after, 120);
+        assert!(config.validate().is_ok());
+
+        assert!(Config::parse("[lockout]\nmax_failures = 2\n")
+            .unwrap()
+            .validate()
+            .is_err());
+        assert!(Config::parse("[server]\nport = 1\n").is_err());
+        assert!(config.apply_env(|_| Some("x".to_string())).is_err());
+    }
+}
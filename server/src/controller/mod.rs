@@ -1,16 +1,39 @@
-use poem::{web::Data, Error, Request, Result};
-use poem_openapi::{auth::ApiKey, param::Path, payload::Json, OpenApi, SecurityScheme};
+use poem::{web::Data, Request, Result};
+use poem_openapi::{
+    auth::ApiKey,
+    param::{Header, Path, Query},
+    payload::{Binary, Json, PlainText},
+    ApiResponse, OpenApi, SecurityScheme,
+};
 
 use crate::{
+    config::config,
     domain::{
-        dto::DeviceSoft,
+        dto::{Channel, DeviceSoft},
         vo::{
-            BaseInfo, ReturnData, Token, VoAddFirm, VoAddHard, VoAddSoft, VoDeviceHard, VoFirm,
-            VoLogin, VoUpdateFirm, VoUpdateHard, VoUpdateSoft, VoUpdateUser, VoUser,
+            BaseInfo, CustomError, ReturnData, Token, VoAcceptInvite, VoAddApiKey, VoAddChannel,
+            VoAddDevice, VoAddFirm, VoAddHard, VoAddInstallReport, VoAddSoft, VoAddUser, VoApiKey,
+            VoApiScope, VoAuditEntity, VoAuditPage, VoBlob, VoChannelAssign, VoCheckIn,
+            VoCheckUpdate, VoDevice, VoDeviceHard, VoFirm, VoFirmChannel, VoFirmPage, VoFirmQuery,
+            VoFirmSort, VoFirmStatus, VoImportDevices, VoInstallReport, VoInvite, VoLogin,
+            VoLoginChallenge, VoLoginTotp, VoNewApiKey, VoPublicKey, VoRecoveryCodes,
+            VoRefreshToken, VoRenameUser, VoResetPass, VoRole, VoRolloutStep, VoSearchHit,
+            VoSetChannelAssign, VoSetRole, VoTotpCode, VoTotpEnroll, VoUpdateChannel,
+            VoUpdateCheck, VoUpdateDevice, VoUpdateFirm, VoUpdateHard, VoUpdateRollout,
+            VoUpdateSoft, VoUpdateUser, VoUploadFirm, VoUser, VoUserInfo,
         },
     },
-    service::{SYS_FIRM_SERVICE, SYS_HARD_SERVICE, SYS_SOFT_SERVICE, SYS_USER_SERVICE},
-    utils::jwt::{gen_user_token, validate_token},
+    service::{
+        audit_json, ApiKeyOwner, AuditContext, SYS_API_KEY_SERVICE, SYS_AUDIT_SERVICE,
+        SYS_CHANNEL_SERVICE, SYS_DEVICE_SERVICE, SYS_FIRM_SERVICE, SYS_HARD_SERVICE,
+        SYS_SOFT_SERVICE, SYS_USER_SERVICE,
+    },
+    utils::{
+        blob_store::{parse_range, SharedBlobStore},
+        jwt::validate_token,
+        locale,
+        signing::keystore,
+    },
     DbPool,
 };
 
@@ -23,8 +46,134 @@ use crate::{
 )]
 struct TokenAuthorization(pub VoUser);
 
-async fn api_checker(_: &Request, api_key: ApiKey) -> Option<VoUser> {
-    validate_token(api_key.key.as_str())
+impl TokenAuthorization {
+    /// 检查用户角色
+    fn require(&self, role: VoRole) -> Result<(), CustomError> {
+        if self.0.role >= role {
+            Ok(())
+        } else {
+            Err(CustomError::PermissionDenied)
+        }
+    }
+}
+
+async fn api_checker(req: &Request, api_key: ApiKey) -> Option<VoUser> {
+    let user = validate_token(api_key.key.as_str())?;
+    let pool = req.data::<DbPool>()?;
+    SYS_USER_SERVICE.check_token(&Data(pool), user).await.ok()
+}
+
+/// CI 等机器调用使用的 API key
+#[derive(SecurityScheme)]
+#[oai(
+    type = "api_key",
+    key_name = "x-api-key",
+    in = "header",
+    checker = "api_key_checker"
+)]
+struct ApiKeyAuthorization(pub ApiKeyOwner);
+
+impl ApiKeyAuthorization {
+    /// 检查 key 的权限范围, 所属用户的角色也须满足
+    fn require(&self, scope: VoApiScope) -> Result<(), CustomError> {
+        if self.0.scopes.contains(&scope) && self.0.user.role >= scope.role() {
+            Ok(())
+        } else {
+            Err(CustomError::PermissionDenied)
+        }
+    }
+}
+
+async fn api_key_checker(req: &Request, api_key: ApiKey) -> Option<ApiKeyOwner> {
+    let pool = req.data::<DbPool>()?;
+    SYS_API_KEY_SERVICE
+        .check(&Data(pool), api_key.key.as_str())
+        .await
+        .ok()
+}
+
+/// 客户端地址, 开启 `trust_proxy` 时使用 `X-Forwarded-For` 的最后一项
+///
+/// 最后一项由可信代理追加, 之前的各项可能由客户端伪造
+fn client_ip(req: &Request) -> Option<String> {
+    let forwarded = if config().server.trust_proxy {
+        req.headers()
+            .get_all("x-forwarded-for")
+            .iter()
+            .next_back()
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.rsplit(',').next())
+            .map(|v| v.trim().to_string())
+            .filter(|v| !v.is_empty())
+    } else {
+        None
+    };
+    forwarded.or_else(|| {
+        req.remote_addr()
+            .as_socket_addr()
+            .map(|addr| addr.ip().to_string())
+    })
+}
+
+/// 审计用的操作人及客户端地址
+fn audit_context(req: &Request, user: &VoUser) -> AuditContext {
+    AuditContext {
+        user_id: Some(user.id),
+        ip: client_ip(req),
+    }
+}
+
+/// 按 `Accept-Language` 协商固件的更新说明
+fn localize<'a>(firms: impl IntoIterator<Item = &'a mut VoFirm>, accept_language: Option<&str>) {
+    let ranges = locale::accept_language(accept_language.unwrap_or_default());
+    for firm in firms {
+        firm.localize(&ranges);
+    }
+}
+
+/// 设备端接口使用的密钥
+#[derive(SecurityScheme)]
+#[oai(
+    type = "api_key",
+    key_name = "device-key",
+    in = "header",
+    checker = "device_checker"
+)]
+struct DeviceAuthorization(());
+
+async fn device_checker(_: &Request, api_key: ApiKey) -> Option<()> {
+    (api_key.key == config().auth.device_key).then_some(())
+}
+
+#[derive(ApiResponse)]
+enum DownloadResponse {
+    /// 完整文件
+    #[oai(status = 200)]
+    Full(
+        Binary<Vec<u8>>,
+        #[oai(header = "Content-Type")] String,
+        #[oai(header = "Accept-Ranges")] String,
+    ),
+    /// 部分内容
+    #[oai(status = 206)]
+    Partial(
+        Binary<Vec<u8>>,
+        #[oai(header = "Content-Type")] String,
+        #[oai(header = "Content-Range")] String,
+    ),
+    /// 请求范围无效
+    #[oai(status = 416)]
+    RangeNotSatisfiable(#[oai(header = "Content-Range")] String),
+}
+
+/// 登录结果, 开启两步验证时返回挑战令牌
+#[derive(ApiResponse)]
+enum LoginResponse {
+    #[oai(status = 200)]
+    Token(Json<Token>),
+    /// 需要提交验证码, 见 `/login/totp`
+    #[oai(status = 202)]
+    Challenge(Json<VoLoginChallenge>),
 }
 
 pub struct Api;
@@ -33,23 +182,535 @@ pub struct Api;
 impl Api {
     /// 登陆
     #[oai(path = "/login", method = "post")]
-    async fn login(&self, pool: Data<&DbPool>, data: Json<VoLogin>) -> Result<Json<Token>> {
-        let user = SYS_USER_SERVICE.login(&pool, data.0).await?;
-        Ok(Json(gen_user_token(user)))
+    async fn login(
+        &self,
+        pool: Data<&DbPool>,
+        req: &Request,
+        data: Json<VoLogin>,
+    ) -> Result<LoginResponse> {
+        let ip = client_ip(req);
+        let user = SYS_USER_SERVICE.login(&pool, data.0, ip.as_deref()).await?;
+        if let Some(challenge) = SYS_USER_SERVICE.login_challenge(&pool, &user).await? {
+            return Ok(LoginResponse::Challenge(Json(challenge)));
+        }
+        let token = SYS_USER_SERVICE.issue_token(&pool, user).await?;
+        Ok(LoginResponse::Token(Json(token)))
+    }
+
+    /// 两步登录, 用挑战令牌和验证码或恢复码换取令牌
+    #[oai(path = "/login/totp", method = "post")]
+    async fn login_totp(
+        &self,
+        pool: Data<&DbPool>,
+        req: &Request,
+        data: Json<VoLoginTotp>,
+    ) -> Result<Json<Token>> {
+        let ip = client_ip(req);
+        let user = SYS_USER_SERVICE
+            .login_totp(&pool, data.0, ip.as_deref())
+            .await?;
+        let token = SYS_USER_SERVICE.issue_token(&pool, user).await?;
+        Ok(Json(token))
     }
 
-    /// 更新用户密码
+    /// 使用刷新令牌换取新的令牌
+    #[oai(path = "/token/refresh", method = "post")]
+    async fn refresh_token(
+        &self,
+        pool: Data<&DbPool>,
+        data: Json<VoRefreshToken>,
+    ) -> Result<Json<Token>> {
+        let token = SYS_USER_SERVICE
+            .refresh_token(&pool, &data.0.refresh_token)
+            .await?;
+        Ok(Json(token))
+    }
+
+    /// 更新用户密码, 其他登录失效, 返回当前会话的新令牌
     #[oai(path = "/user/updatePass", method = "post")]
     async fn update_password(
         &self,
         pool: Data<&DbPool>,
+        req: &Request,
         user: TokenAuthorization,
         data: Json<VoUpdateUser>,
-    ) -> Result<()> {
-        SYS_USER_SERVICE
-            .change_pass(&pool, &user.0, data.0)
-            .await
-            .map_err(Error::from)
+    ) -> Result<Json<Token>> {
+        let token = SYS_AUDIT_SERVICE
+            .track(
+                &pool,
+                &audit_context(req, &user.0),
+                "update_password",
+                VoAuditEntity::User,
+                user.0.id,
+                SYS_USER_SERVICE.change_pass(&pool, &user.0, data.0),
+            )
+            .await?;
+        Ok(Json(token))
+    }
+
+    /// 退出所有登录
+    #[oai(path = "/user/logoutAll", method = "post")]
+    async fn logout_all(
+        &self,
+        pool: Data<&DbPool>,
+        req: &Request,
+        user: TokenAuthorization,
+    ) -> Result<Json<ReturnData>> {
+        SYS_AUDIT_SERVICE
+            .track(
+                &pool,
+                &audit_context(req, &user.0),
+                "logout_all",
+                VoAuditEntity::User,
+                user.0.id,
+                SYS_USER_SERVICE.revoke_tokens(&pool, user.0.id),
+            )
+            .await?;
+        Ok(Json(ReturnData::default()))
+    }
+
+    /// 登记两步验证, 返回密钥和 otpauth URI
+    #[oai(path = "/user/totp/enroll", method = "post")]
+    async fn totp_enroll(
+        &self,
+        pool: Data<&DbPool>,
+        req: &Request,
+        user: TokenAuthorization,
+    ) -> Result<Json<VoTotpEnroll>> {
+        let enroll = SYS_AUDIT_SERVICE
+            .track(
+                &pool,
+                &audit_context(req, &user.0),
+                "totp_enroll",
+                VoAuditEntity::User,
+                user.0.id,
+                SYS_USER_SERVICE.totp_enroll(&pool, &user.0),
+            )
+            .await?;
+        Ok(Json(enroll))
+    }
+
+    /// 验证首个验证码并开启两步验证, 返回恢复码
+    #[oai(path = "/user/totp/activate", method = "post")]
+    async fn totp_activate(
+        &self,
+        pool: Data<&DbPool>,
+        req: &Request,
+        data: Json<VoTotpCode>,
+        user: TokenAuthorization,
+    ) -> Result<Json<VoRecoveryCodes>> {
+        let codes = SYS_AUDIT_SERVICE
+            .track(
+                &pool,
+                &audit_context(req, &user.0),
+                "totp_activate",
+                VoAuditEntity::User,
+                user.0.id,
+                SYS_USER_SERVICE.totp_activate(&pool, &user.0, &data.0.code),
+            )
+            .await?;
+        Ok(Json(codes))
+    }
+
+    /// 关闭两步验证
+    #[oai(path = "/user/totp/disable", method = "post")]
+    async fn totp_disable(
+        &self,
+        pool: Data<&DbPool>,
+        req: &Request,
+        data: Json<VoTotpCode>,
+        user: TokenAuthorization,
+    ) -> Result<Json<ReturnData>> {
+        SYS_AUDIT_SERVICE
+            .track(
+                &pool,
+                &audit_context(req, &user.0),
+                "totp_disable",
+                VoAuditEntity::User,
+                user.0.id,
+                SYS_USER_SERVICE.totp_disable(&pool, &user.0, &data.0.code),
+            )
+            .await?;
+        Ok(Json(ReturnData::default()))
+    }
+
+    /// 重新生成恢复码
+    #[oai(path = "/user/totp/recoveryCodes", method = "post")]
+    async fn recovery_codes(
+        &self,
+        pool: Data<&DbPool>,
+        req: &Request,
+        data: Json<VoTotpCode>,
+        user: TokenAuthorization,
+    ) -> Result<Json<VoRecoveryCodes>> {
+        let codes = SYS_AUDIT_SERVICE
+            .track(
+                &pool,
+                &audit_context(req, &user.0),
+                "recovery_codes",
+                VoAuditEntity::User,
+                user.0.id,
+                SYS_USER_SERVICE.regen_recovery_codes(&pool, &user.0, &data.0.code),
+            )
+            .await?;
+        Ok(Json(codes))
+    }
+
+    /// 获取自己的 API key
+    #[oai(path = "/apiKeys", method = "get")]
+    async fn api_keys(
+        &self,
+        pool: Data<&DbPool>,
+        user: TokenAuthorization,
+    ) -> Result<Json<Vec<VoApiKey>>> {
+        let keys = SYS_API_KEY_SERVICE.keys(&pool, user.0.id).await?;
+        Ok(Json(keys))
+    }
+
+    /// 创建 API key, key 只在此时返回
+    #[oai(path = "/apiKeys", method = "post")]
+    async fn add_api_key(
+        &self,
+        pool: Data<&DbPool>,
+        req: &Request,
+        data: Json<VoAddApiKey>,
+        user: TokenAuthorization,
+    ) -> Result<Json<VoNewApiKey>> {
+        let key = SYS_API_KEY_SERVICE.add_key(&pool, &user.0, data.0).await?;
+        SYS_AUDIT_SERVICE
+            .created(
+                &pool,
+                &audit_context(req, &user.0),
+                "add_api_key",
+                VoAuditEntity::ApiKey,
+                key.id,
+            )
+            .await?;
+        Ok(Json(key))
+    }
+
+    /// 吊销 API key
+    #[oai(path = "/apiKeys/:id", method = "delete")]
+    async fn revoke_api_key(
+        &self,
+        pool: Data<&DbPool>,
+        req: &Request,
+        id: Path<i32>,
+        user: TokenAuthorization,
+    ) -> Result<Json<ReturnData>> {
+        SYS_AUDIT_SERVICE
+            .track(
+                &pool,
+                &audit_context(req, &user.0),
+                "revoke_api_key",
+                VoAuditEntity::ApiKey,
+                id.0,
+                SYS_API_KEY_SERVICE.revoke_key(&pool, &user.0, id.0),
+            )
+            .await?;
+        Ok(Json(ReturnData::default()))
+    }
+
+    /// 接受邀请并设置密码
+    #[oai(path = "/invite/accept", method = "post")]
+    async fn accept_invite(
+        &self,
+        pool: Data<&DbPool>,
+        req: &Request,
+        data: Json<VoAcceptInvite>,
+    ) -> Result<Json<ReturnData>> {
+        let id = SYS_USER_SERVICE
+            .invited_user(&pool, &data.0.invite_token)
+            .await?;
+        // 接受邀请的用户即操作人
+        let ctx = AuditContext {
+            user_id: Some(id),
+            ip: client_ip(req),
+        };
+        SYS_AUDIT_SERVICE
+            .track(
+                &pool,
+                &ctx,
+                "accept_invite",
+                VoAuditEntity::User,
+                id,
+                SYS_USER_SERVICE.accept_invite(&pool, data.0),
+            )
+            .await?;
+        Ok(Json(ReturnData::default()))
+    }
+
+    /// 获取所有用户
+    #[oai(path = "/users", method = "get")]
+    async fn users(
+        &self,
+        pool: Data<&DbPool>,
+        user: TokenAuthorization,
+    ) -> Result<Json<Vec<VoUserInfo>>> {
+        user.require(VoRole::Admin)?;
+        let users = SYS_USER_SERVICE.users(&pool).await?;
+        Ok(Json(users))
+    }
+
+    /// 创建用户, 不设置密码时返回邀请码
+    #[oai(path = "/users", method = "post")]
+    async fn add_user(
+        &self,
+        pool: Data<&DbPool>,
+        req: &Request,
+        data: Json<VoAddUser>,
+        user: TokenAuthorization,
+    ) -> Result<Json<VoInvite>> {
+        user.require(VoRole::Admin)?;
+        let invite = SYS_USER_SERVICE.add_user(&pool, data.0).await?;
+        SYS_AUDIT_SERVICE
+            .created(
+                &pool,
+                &audit_context(req, &user.0),
+                "add_user",
+                VoAuditEntity::User,
+                invite.id,
+            )
+            .await?;
+        Ok(Json(invite))
+    }
+
+    /// 修改用户名
+    #[oai(path = "/users", method = "put")]
+    async fn rename_user(
+        &self,
+        pool: Data<&DbPool>,
+        req: &Request,
+        data: Json<VoRenameUser>,
+        user: TokenAuthorization,
+    ) -> Result<Json<ReturnData>> {
+        user.require(VoRole::Admin)?;
+        SYS_AUDIT_SERVICE
+            .track(
+                &pool,
+                &audit_context(req, &user.0),
+                "rename_user",
+                VoAuditEntity::User,
+                data.0.id,
+                SYS_USER_SERVICE.rename_user(&pool, data.0),
+            )
+            .await?;
+        Ok(Json(ReturnData::default()))
+    }
+
+    /// 删除用户
+    #[oai(path = "/users/:id", method = "delete")]
+    async fn delete_user(
+        &self,
+        pool: Data<&DbPool>,
+        req: &Request,
+        id: Path<i32>,
+        user: TokenAuthorization,
+    ) -> Result<Json<ReturnData>> {
+        user.require(VoRole::Admin)?;
+        SYS_AUDIT_SERVICE
+            .track(
+                &pool,
+                &audit_context(req, &user.0),
+                "delete_user",
+                VoAuditEntity::User,
+                id.0,
+                SYS_USER_SERVICE.delete_user(&pool, &user.0, id.0),
+            )
+            .await?;
+        Ok(Json(ReturnData::default()))
+    }
+
+    /// 重置用户密码, 不设置密码时返回新的邀请码
+    #[oai(path = "/users/:id/resetPass", method = "post")]
+    async fn reset_pass(
+        &self,
+        pool: Data<&DbPool>,
+        req: &Request,
+        id: Path<i32>,
+        data: Json<VoResetPass>,
+        user: TokenAuthorization,
+    ) -> Result<Json<VoInvite>> {
+        user.require(VoRole::Admin)?;
+        let invite = SYS_AUDIT_SERVICE
+            .track(
+                &pool,
+                &audit_context(req, &user.0),
+                "reset_pass",
+                VoAuditEntity::User,
+                id.0,
+                SYS_USER_SERVICE.reset_pass(&pool, id.0, data.0),
+            )
+            .await?;
+        Ok(Json(invite))
+    }
+
+    /// 修改用户角色
+    #[oai(path = "/users/:id/role", method = "post")]
+    async fn set_role(
+        &self,
+        pool: Data<&DbPool>,
+        req: &Request,
+        id: Path<i32>,
+        data: Json<VoSetRole>,
+        user: TokenAuthorization,
+    ) -> Result<Json<ReturnData>> {
+        user.require(VoRole::Admin)?;
+        SYS_AUDIT_SERVICE
+            .track(
+                &pool,
+                &audit_context(req, &user.0),
+                "set_role",
+                VoAuditEntity::User,
+                id.0,
+                SYS_USER_SERVICE.set_role(&pool, &user.0, id.0, data.0.role),
+            )
+            .await?;
+        Ok(Json(ReturnData::default()))
+    }
+
+    /// 禁用用户
+    #[oai(path = "/users/:id/disable", method = "post")]
+    async fn disable_user(
+        &self,
+        pool: Data<&DbPool>,
+        req: &Request,
+        id: Path<i32>,
+        user: TokenAuthorization,
+    ) -> Result<Json<ReturnData>> {
+        user.require(VoRole::Admin)?;
+        SYS_AUDIT_SERVICE
+            .track(
+                &pool,
+                &audit_context(req, &user.0),
+                "disable_user",
+                VoAuditEntity::User,
+                id.0,
+                SYS_USER_SERVICE.set_disabled(&pool, &user.0, id.0, true),
+            )
+            .await?;
+        Ok(Json(ReturnData::default()))
+    }
+
+    /// 启用用户
+    #[oai(path = "/users/:id/enable", method = "post")]
+    async fn enable_user(
+        &self,
+        pool: Data<&DbPool>,
+        req: &Request,
+        id: Path<i32>,
+        user: TokenAuthorization,
+    ) -> Result<Json<ReturnData>> {
+        user.require(VoRole::Admin)?;
+        SYS_AUDIT_SERVICE
+            .track(
+                &pool,
+                &audit_context(req, &user.0),
+                "enable_user",
+                VoAuditEntity::User,
+                id.0,
+                SYS_USER_SERVICE.set_disabled(&pool, &user.0, id.0, false),
+            )
+            .await?;
+        Ok(Json(ReturnData::default()))
+    }
+
+    /// 强制用户下线
+    #[oai(path = "/users/:id/logout", method = "post")]
+    async fn force_logout(
+        &self,
+        pool: Data<&DbPool>,
+        req: &Request,
+        id: Path<i32>,
+        user: TokenAuthorization,
+    ) -> Result<Json<ReturnData>> {
+        user.require(VoRole::Admin)?;
+        SYS_AUDIT_SERVICE
+            .track(
+                &pool,
+                &audit_context(req, &user.0),
+                "force_logout",
+                VoAuditEntity::User,
+                id.0,
+                SYS_USER_SERVICE.revoke_tokens(&pool, id.0),
+            )
+            .await?;
+        Ok(Json(ReturnData::default()))
+    }
+
+    /// 解除用户的登录失败锁定
+    #[oai(path = "/users/:id/unlock", method = "post")]
+    async fn unlock_user(
+        &self,
+        pool: Data<&DbPool>,
+        req: &Request,
+        id: Path<i32>,
+        user: TokenAuthorization,
+    ) -> Result<Json<ReturnData>> {
+        user.require(VoRole::Admin)?;
+        SYS_USER_SERVICE.unlock(&pool, id.0).await?;
+        SYS_AUDIT_SERVICE
+            .record(
+                &pool,
+                &audit_context(req, &user.0),
+                "unlock_user",
+                VoAuditEntity::User,
+                Some(id.0.to_string()),
+                None,
+                None,
+            )
+            .await?;
+        Ok(Json(ReturnData::default()))
+    }
+
+    /// 重置用户的两步验证, 用于丢失认证器和恢复码的情况
+    #[oai(path = "/users/:id/totp/reset", method = "post")]
+    async fn reset_totp(
+        &self,
+        pool: Data<&DbPool>,
+        req: &Request,
+        id: Path<i32>,
+        user: TokenAuthorization,
+    ) -> Result<Json<ReturnData>> {
+        user.require(VoRole::Admin)?;
+        SYS_AUDIT_SERVICE
+            .track(
+                &pool,
+                &audit_context(req, &user.0),
+                "reset_totp",
+                VoAuditEntity::User,
+                id.0,
+                SYS_USER_SERVICE.reset_totp(&pool, id.0),
+            )
+            .await?;
+        Ok(Json(ReturnData::default()))
+    }
+
+    /// 查询审计日志, 可按实体和操作人过滤
+    #[oai(path = "/audit", method = "get")]
+    #[allow(clippy::too_many_arguments)]
+    async fn audits(
+        &self,
+        pool: Data<&DbPool>,
+        entity: Query<Option<VoAuditEntity>>,
+        entity_id: Query<Option<String>>,
+        user_id: Query<Option<i32>>,
+        page: Query<Option<i32>>,
+        page_size: Query<Option<i32>>,
+        user: TokenAuthorization,
+    ) -> Result<Json<VoAuditPage>> {
+        user.require(VoRole::Admin)?;
+        let audits = SYS_AUDIT_SERVICE
+            .audits(
+                &pool,
+                entity.0,
+                entity_id.0.as_deref(),
+                user_id.0,
+                page.0,
+                page_size.0,
+            )
+            .await?;
+        Ok(Json(audits))
     }
 
     /// 获取所有硬件类型
@@ -68,10 +729,21 @@ impl Api {
     async fn add_devices(
         &self,
         pool: Data<&DbPool>,
+        req: &Request,
         data: Json<VoAddHard>,
-        _user: TokenAuthorization,
+        user: TokenAuthorization,
     ) -> Result<Json<ReturnData>> {
-        SYS_HARD_SERVICE.add_device(&pool, data.0).await?;
+        user.require(VoRole::ReleaseEngineer)?;
+        let id = SYS_HARD_SERVICE.add_device(&pool, data.0).await?;
+        SYS_AUDIT_SERVICE
+            .created(
+                &pool,
+                &audit_context(req, &user.0),
+                "add_devices",
+                VoAuditEntity::DeviceType,
+                id,
+            )
+            .await?;
         Ok(Json(ReturnData::default()))
     }
 
@@ -80,13 +752,133 @@ impl Api {
     async fn update_devices(
         &self,
         pool: Data<&DbPool>,
+        req: &Request,
         data: Json<VoUpdateHard>,
+        user: TokenAuthorization,
+    ) -> Result<Json<ReturnData>> {
+        user.require(VoRole::ReleaseEngineer)?;
+        SYS_AUDIT_SERVICE
+            .track(
+                &pool,
+                &audit_context(req, &user.0),
+                "update_devices",
+                VoAuditEntity::DeviceType,
+                data.0.id,
+                SYS_HARD_SERVICE.update_device(&pool, data.0),
+            )
+            .await?;
+        Ok(Json(ReturnData::default()))
+    }
+
+    /// 获取已登记的设备, 可按硬件类型和设备组过滤
+    #[oai(path = "/units", method = "get")]
+    async fn units(
+        &self,
+        pool: Data<&DbPool>,
+        device_type: Query<Option<i32>>,
+        group: Query<Option<String>>,
         _user: TokenAuthorization,
+    ) -> Result<Json<Vec<VoDevice>>> {
+        let devices = SYS_DEVICE_SERVICE
+            .devices(&pool, device_type.0, group.0.as_deref())
+            .await?;
+        Ok(Json(devices))
+    }
+
+    /// 登记设备
+    #[oai(path = "/units", method = "post")]
+    async fn add_unit(
+        &self,
+        pool: Data<&DbPool>,
+        req: &Request,
+        data: Json<VoAddDevice>,
+        user: TokenAuthorization,
     ) -> Result<Json<ReturnData>> {
-        SYS_HARD_SERVICE.update_device(&pool, data.0).await?;
+        user.require(VoRole::ReleaseEngineer)?;
+        let id = SYS_DEVICE_SERVICE.add_device(&pool, data.0).await?;
+        SYS_AUDIT_SERVICE
+            .created(
+                &pool,
+                &audit_context(req, &user.0),
+                "add_unit",
+                VoAuditEntity::Device,
+                id,
+            )
+            .await?;
         Ok(Json(ReturnData::default()))
     }
 
+    /// 更新设备
+    #[oai(path = "/units", method = "put")]
+    async fn update_unit(
+        &self,
+        pool: Data<&DbPool>,
+        req: &Request,
+        data: Json<VoUpdateDevice>,
+        user: TokenAuthorization,
+    ) -> Result<Json<ReturnData>> {
+        user.require(VoRole::ReleaseEngineer)?;
+        SYS_AUDIT_SERVICE
+            .track(
+                &pool,
+                &audit_context(req, &user.0),
+                "update_unit",
+                VoAuditEntity::Device,
+                data.0.id,
+                SYS_DEVICE_SERVICE.update_device(&pool, data.0),
+            )
+            .await?;
+        Ok(Json(ReturnData::default()))
+    }
+
+    /// 删除设备
+    #[oai(path = "/units/:id", method = "delete")]
+    async fn delete_unit(
+        &self,
+        pool: Data<&DbPool>,
+        req: &Request,
+        id: Path<i32>,
+        user: TokenAuthorization,
+    ) -> Result<Json<ReturnData>> {
+        user.require(VoRole::ReleaseEngineer)?;
+        SYS_AUDIT_SERVICE
+            .track(
+                &pool,
+                &audit_context(req, &user.0),
+                "delete_unit",
+                VoAuditEntity::Device,
+                id.0,
+                SYS_DEVICE_SERVICE.delete_device(&pool, id.0),
+            )
+            .await?;
+        Ok(Json(ReturnData::default()))
+    }
+
+    /// 批量导入设备, CSV 每行 `serial,mac,hard_version,group_name`
+    #[oai(path = "/units/import", method = "post")]
+    async fn import_units(
+        &self,
+        pool: Data<&DbPool>,
+        req: &Request,
+        data: PlainText<String>,
+        user: TokenAuthorization,
+    ) -> Result<Json<VoImportDevices>> {
+        user.require(VoRole::ReleaseEngineer)?;
+        let result = SYS_DEVICE_SERVICE.import(&pool, &data.0).await?;
+        SYS_AUDIT_SERVICE
+            .record(
+                &pool,
+                &audit_context(req, &user.0),
+                "import_units",
+                VoAuditEntity::Device,
+                None,
+                None,
+                audit_json(&data.0),
+            )
+            .await?;
+        Ok(Json(result))
+    }
+
     /// 获取所有软件类型
     #[oai(path = "/softTypes", method = "get")]
     async fn soft_types(
@@ -103,10 +895,21 @@ impl Api {
     async fn add_soft_types(
         &self,
         pool: Data<&DbPool>,
+        req: &Request,
         data: Json<VoAddSoft>,
-        _user: TokenAuthorization,
+        user: TokenAuthorization,
     ) -> Result<Json<ReturnData>> {
-        SYS_SOFT_SERVICE.add_soft_version(&pool, data.0).await?;
+        user.require(VoRole::ReleaseEngineer)?;
+        let id = SYS_SOFT_SERVICE.add_soft_version(&pool, data.0).await?;
+        SYS_AUDIT_SERVICE
+            .created(
+                &pool,
+                &audit_context(req, &user.0),
+                "add_soft_types",
+                VoAuditEntity::VersionType,
+                id,
+            )
+            .await?;
         Ok(Json(ReturnData::default()))
     }
 
@@ -115,45 +918,390 @@ impl Api {
     async fn update_soft_types(
         &self,
         pool: Data<&DbPool>,
+        req: &Request,
         data: Json<VoUpdateSoft>,
+        user: TokenAuthorization,
+    ) -> Result<Json<ReturnData>> {
+        user.require(VoRole::ReleaseEngineer)?;
+        SYS_AUDIT_SERVICE
+            .track(
+                &pool,
+                &audit_context(req, &user.0),
+                "update_soft_types",
+                VoAuditEntity::VersionType,
+                data.0.id,
+                SYS_SOFT_SERVICE.update_soft_version(&pool, data.0),
+            )
+            .await?;
+        Ok(Json(ReturnData::default()))
+    }
+
+    /// 获取所有发布渠道
+    #[oai(path = "/channels", method = "get")]
+    async fn channels(
+        &self,
+        pool: Data<&DbPool>,
+        _user: TokenAuthorization,
+    ) -> Result<Json<Vec<Channel>>> {
+        let channels = SYS_CHANNEL_SERVICE.channels(&pool).await?;
+        Ok(Json(channels))
+    }
+
+    /// 添加发布渠道
+    #[oai(path = "/channels", method = "post")]
+    async fn add_channel(
+        &self,
+        pool: Data<&DbPool>,
+        req: &Request,
+        data: Json<VoAddChannel>,
+        user: TokenAuthorization,
+    ) -> Result<Json<ReturnData>> {
+        user.require(VoRole::ReleaseEngineer)?;
+        let id = SYS_CHANNEL_SERVICE.add_channel(&pool, data.0).await?;
+        SYS_AUDIT_SERVICE
+            .created(
+                &pool,
+                &audit_context(req, &user.0),
+                "add_channel",
+                VoAuditEntity::Channel,
+                id,
+            )
+            .await?;
+        Ok(Json(ReturnData::default()))
+    }
+
+    /// 更新发布渠道
+    #[oai(path = "/channels", method = "put")]
+    async fn update_channel(
+        &self,
+        pool: Data<&DbPool>,
+        req: &Request,
+        data: Json<VoUpdateChannel>,
+        user: TokenAuthorization,
+    ) -> Result<Json<ReturnData>> {
+        user.require(VoRole::ReleaseEngineer)?;
+        SYS_AUDIT_SERVICE
+            .track(
+                &pool,
+                &audit_context(req, &user.0),
+                "update_channel",
+                VoAuditEntity::Channel,
+                data.0.id,
+                SYS_CHANNEL_SERVICE.update_channel(&pool, data.0),
+            )
+            .await?;
+        Ok(Json(ReturnData::default()))
+    }
+
+    /// 获取设备及设备组的渠道分配
+    #[oai(path = "/channelAssigns", method = "get")]
+    async fn channel_assigns(
+        &self,
+        pool: Data<&DbPool>,
         _user: TokenAuthorization,
+    ) -> Result<Json<Vec<VoChannelAssign>>> {
+        let assigns = SYS_CHANNEL_SERVICE.assigns(&pool).await?;
+        Ok(Json(assigns))
+    }
+
+    /// 为设备或设备组分配渠道
+    #[oai(path = "/channelAssigns", method = "put")]
+    async fn set_channel_assign(
+        &self,
+        pool: Data<&DbPool>,
+        req: &Request,
+        data: Json<VoSetChannelAssign>,
+        user: TokenAuthorization,
+    ) -> Result<Json<ReturnData>> {
+        user.require(VoRole::ReleaseEngineer)?;
+        let ctx = audit_context(req, &user.0);
+        match SYS_CHANNEL_SERVICE.find_assign(&pool, &data.0).await? {
+            Some(id) => {
+                SYS_AUDIT_SERVICE
+                    .track(
+                        &pool,
+                        &ctx,
+                        "set_channel_assign",
+                        VoAuditEntity::ChannelAssign,
+                        id,
+                        SYS_CHANNEL_SERVICE.set_assign(&pool, data.0),
+                    )
+                    .await?;
+            }
+            None => {
+                let id = SYS_CHANNEL_SERVICE.set_assign(&pool, data.0).await?;
+                SYS_AUDIT_SERVICE
+                    .created(
+                        &pool,
+                        &ctx,
+                        "set_channel_assign",
+                        VoAuditEntity::ChannelAssign,
+                        id,
+                    )
+                    .await?;
+            }
+        }
+        Ok(Json(ReturnData::default()))
+    }
+
+    /// 删除渠道分配, 设备回到默认渠道
+    #[oai(path = "/channelAssigns/:id", method = "delete")]
+    async fn delete_channel_assign(
+        &self,
+        pool: Data<&DbPool>,
+        req: &Request,
+        id: Path<i32>,
+        user: TokenAuthorization,
     ) -> Result<Json<ReturnData>> {
-        SYS_SOFT_SERVICE.update_soft_version(&pool, data.0).await?;
+        user.require(VoRole::ReleaseEngineer)?;
+        SYS_AUDIT_SERVICE
+            .track(
+                &pool,
+                &audit_context(req, &user.0),
+                "delete_channel_assign",
+                VoAuditEntity::ChannelAssign,
+                id.0,
+                SYS_CHANNEL_SERVICE.delete_assign(&pool, id.0),
+            )
+            .await?;
         Ok(Json(ReturnData::default()))
     }
 
-    /// 获取所有固件
+    /// 分页查询固件, 可按渠道名、硬件类型、软件类型、指纹版本、更新时间过滤,
+    /// `q` 搜索版本名和说明, `sort` 指定排序
     #[oai(path = "/firms", method = "get")]
+    #[allow(clippy::too_many_arguments)]
     async fn firms(
         &self,
         pool: Data<&DbPool>,
+        channel: Query<Option<String>>,
+        hard_version: Query<Option<i32>>,
+        version_type: Query<Option<i32>>,
+        finger_level: Query<Option<i32>>,
+        from: Query<Option<i64>>,
+        to: Query<Option<i64>>,
+        q: Query<Option<String>>,
+        sort: Query<Option<VoFirmSort>>,
+        page: Query<Option<i32>>,
+        page_size: Query<Option<i32>>,
+        cursor: Query<Option<String>>,
+        #[oai(name = "Accept-Language")] accept_language: Header<Option<String>>,
         _user: TokenAuthorization,
-    ) -> Result<Json<Vec<VoFirm>>> {
-        let firms = SYS_FIRM_SERVICE.firms(&pool).await?;
+    ) -> Result<Json<VoFirmPage>> {
+        let mut firms = SYS_FIRM_SERVICE
+            .firm_page(
+                &pool,
+                VoFirmQuery {
+                    channel: channel.0,
+                    hard_version: hard_version.0,
+                    version_type: version_type.0,
+                    finger_level: finger_level.0,
+                    from: from.0,
+                    to: to.0,
+                    q: q.0,
+                    sort: sort.0,
+                    page: page.0,
+                    page_size: page_size.0,
+                    cursor: cursor.0,
+                },
+            )
+            .await?;
+        localize(&mut firms.items, accept_language.0.as_deref());
         Ok(Json(firms))
     }
 
+    /// 全文搜索固件版本名和各语言说明, 返回高亮片段, 按相关度排序
+    #[oai(path = "/search", method = "get")]
+    async fn search(
+        &self,
+        pool: Data<&DbPool>,
+        q: Query<String>,
+        hard_version: Query<Option<i32>>,
+        limit: Query<Option<i32>>,
+        _user: TokenAuthorization,
+    ) -> Result<Json<Vec<VoSearchHit>>> {
+        let hits = SYS_FIRM_SERVICE
+            .search(&pool, &q.0, hard_version.0, limit.0)
+            .await?;
+        Ok(Json(hits))
+    }
+
     /// 添加固件
     #[oai(path = "/firms", method = "post")]
     async fn add_firms(
         &self,
         pool: Data<&DbPool>,
+        req: &Request,
         data: Json<VoAddFirm>,
-        _user: TokenAuthorization,
+        user: TokenAuthorization,
+    ) -> Result<Json<ReturnData>> {
+        user.require(VoRole::ReleaseEngineer)?;
+        let id = SYS_FIRM_SERVICE.add_firms(&pool, data.0).await?;
+        SYS_AUDIT_SERVICE
+            .created(
+                &pool,
+                &audit_context(req, &user.0),
+                "add_firms",
+                VoAuditEntity::Firm,
+                id,
+            )
+            .await?;
+        Ok(Json(ReturnData::default()))
+    }
+
+    /// 上传固件文件
+    #[oai(path = "/firms/upload", method = "post")]
+    async fn upload_firm(
+        &self,
+        pool: Data<&DbPool>,
+        req: &Request,
+        store: Data<&SharedBlobStore>,
+        data: VoUploadFirm,
+        user: TokenAuthorization,
+    ) -> Result<Json<VoBlob>> {
+        user.require(VoRole::ReleaseEngineer)?;
+        let blob = SYS_FIRM_SERVICE.upload(&pool, &store, data).await?;
+        SYS_AUDIT_SERVICE
+            .created(
+                &pool,
+                &audit_context(req, &user.0),
+                "upload_firm",
+                VoAuditEntity::Blob,
+                &blob.key,
+            )
+            .await?;
+        Ok(Json(blob))
+    }
+
+    /// 使用 API key 获取固件, 需要 `firm:read`
+    #[oai(path = "/ci/firms", method = "get")]
+    async fn ci_firms(
+        &self,
+        pool: Data<&DbPool>,
+        channel: Query<Option<String>>,
+        key: ApiKeyAuthorization,
+    ) -> Result<Json<Vec<VoFirm>>> {
+        key.require(VoApiScope::FirmRead)?;
+        let firms = SYS_FIRM_SERVICE.firms(&pool, channel.0.as_deref()).await?;
+        Ok(Json(firms))
+    }
+
+    /// 使用 API key 添加固件, 需要 `firm:write`
+    #[oai(path = "/ci/firms", method = "post")]
+    async fn ci_add_firms(
+        &self,
+        pool: Data<&DbPool>,
+        req: &Request,
+        data: Json<VoAddFirm>,
+        key: ApiKeyAuthorization,
     ) -> Result<Json<ReturnData>> {
-        SYS_FIRM_SERVICE.add_firms(&pool, data.0).await?;
+        key.require(VoApiScope::FirmWrite)?;
+        let id = SYS_FIRM_SERVICE.add_firms(&pool, data.0).await?;
+        SYS_AUDIT_SERVICE
+            .created(
+                &pool,
+                &audit_context(req, &key.0.user),
+                "ci_add_firms",
+                VoAuditEntity::Firm,
+                id,
+            )
+            .await?;
         Ok(Json(ReturnData::default()))
     }
 
+    /// 使用 API key 上传固件文件, 需要 `firm:write`
+    #[oai(path = "/ci/firms/upload", method = "post")]
+    async fn ci_upload_firm(
+        &self,
+        pool: Data<&DbPool>,
+        req: &Request,
+        store: Data<&SharedBlobStore>,
+        data: VoUploadFirm,
+        key: ApiKeyAuthorization,
+    ) -> Result<Json<VoBlob>> {
+        key.require(VoApiScope::FirmWrite)?;
+        let blob = SYS_FIRM_SERVICE.upload(&pool, &store, data).await?;
+        SYS_AUDIT_SERVICE
+            .created(
+                &pool,
+                &audit_context(req, &key.0.user),
+                "ci_upload_firm",
+                VoAuditEntity::Blob,
+                &blob.key,
+            )
+            .await?;
+        Ok(Json(blob))
+    }
+
+    /// 下载固件文件, 支持 Range 断点续传
+    #[oai(path = "/download/:id", method = "get")]
+    async fn download_firm(
+        &self,
+        pool: Data<&DbPool>,
+        store: Data<&SharedBlobStore>,
+        id: Path<i32>,
+        device_id: Query<Option<String>>,
+        #[oai(name = "Range")] range: Header<Option<String>>,
+        _device: DeviceAuthorization,
+    ) -> Result<DownloadResponse> {
+        let blob = SYS_FIRM_SERVICE
+            .firm_blob(&pool, id.0, device_id.0.as_deref())
+            .await?;
+        let size = store.size(&blob.key).await.map_err(CustomError::from)?;
+        let range = match range.0.as_deref() {
+            Some(range) => match parse_range(range, size) {
+                Some(range) => Some(range),
+                None => {
+                    return Ok(DownloadResponse::RangeNotSatisfiable(format!(
+                        "bytes */{}",
+                        size
+                    )))
+                }
+            },
+            None => None,
+        };
+        Ok(match range {
+            Some((offset, len)) => {
+                let data = store
+                    .read(&blob.key, offset, len)
+                    .await
+                    .map_err(CustomError::from)?;
+                DownloadResponse::Partial(
+                    Binary(data),
+                    blob.content_type,
+                    format!("bytes {}-{}/{}", offset, offset + len - 1, size),
+                )
+            }
+            None => {
+                let data = store
+                    .read(&blob.key, 0, size)
+                    .await
+                    .map_err(CustomError::from)?;
+                DownloadResponse::Full(Binary(data), blob.content_type, "bytes".to_string())
+            }
+        })
+    }
+
     /// 更新固件
     #[oai(path = "/firms", method = "put")]
     async fn update_firms(
         &self,
         pool: Data<&DbPool>,
+        req: &Request,
         data: Json<VoUpdateFirm>,
-        _user: TokenAuthorization,
+        user: TokenAuthorization,
     ) -> Result<Json<ReturnData>> {
-        SYS_FIRM_SERVICE.update_firms(&pool, data.0).await?;
+        user.require(VoRole::ReleaseEngineer)?;
+        SYS_AUDIT_SERVICE
+            .track(
+                &pool,
+                &audit_context(req, &user.0),
+                "update_firms",
+                VoAuditEntity::Firm,
+                data.0.id,
+                SYS_FIRM_SERVICE.update_firms(&pool, data.0),
+            )
+            .await?;
         Ok(Json(ReturnData::default()))
     }
 
@@ -162,10 +1310,290 @@ impl Api {
     async fn delete_device(
         &self,
         pool: Data<&DbPool>,
+        req: &Request,
         device: Path<i32>,
+        user: TokenAuthorization,
+    ) -> Result<Json<ReturnData>> {
+        user.require(VoRole::ReleaseEngineer)?;
+        SYS_AUDIT_SERVICE
+            .track(
+                &pool,
+                &audit_context(req, &user.0),
+                "delete_device",
+                VoAuditEntity::Firm,
+                device.0,
+                SYS_FIRM_SERVICE.delete_firm(&pool, device.0),
+            )
+            .await?;
+        Ok(Json(ReturnData::default()))
+    }
+
+    /// 固件转为草稿
+    #[oai(path = "/release/:id/draft", method = "post")]
+    async fn draft_firm(
+        &self,
+        pool: Data<&DbPool>,
+        req: &Request,
+        id: Path<i32>,
+        user: TokenAuthorization,
+    ) -> Result<Json<ReturnData>> {
+        user.require(VoRole::ReleaseEngineer)?;
+        SYS_AUDIT_SERVICE
+            .track(
+                &pool,
+                &audit_context(req, &user.0),
+                "draft_firm",
+                VoAuditEntity::Firm,
+                id.0,
+                SYS_FIRM_SERVICE.transition(&pool, id.0, VoFirmStatus::Draft),
+            )
+            .await?;
+        Ok(Json(ReturnData::default()))
+    }
+
+    /// 固件进入测试
+    #[oai(path = "/release/:id/test", method = "post")]
+    async fn test_firm(
+        &self,
+        pool: Data<&DbPool>,
+        req: &Request,
+        id: Path<i32>,
+        user: TokenAuthorization,
+    ) -> Result<Json<ReturnData>> {
+        user.require(VoRole::ReleaseEngineer)?;
+        SYS_AUDIT_SERVICE
+            .track(
+                &pool,
+                &audit_context(req, &user.0),
+                "test_firm",
+                VoAuditEntity::Firm,
+                id.0,
+                SYS_FIRM_SERVICE.transition(&pool, id.0, VoFirmStatus::Testing),
+            )
+            .await?;
+        Ok(Json(ReturnData::default()))
+    }
+
+    /// 发布固件
+    #[oai(path = "/release/:id/publish", method = "post")]
+    async fn publish_firm(
+        &self,
+        pool: Data<&DbPool>,
+        req: &Request,
+        id: Path<i32>,
+        user: TokenAuthorization,
+    ) -> Result<Json<ReturnData>> {
+        user.require(VoRole::ReleaseEngineer)?;
+        SYS_AUDIT_SERVICE
+            .track(
+                &pool,
+                &audit_context(req, &user.0),
+                "publish_firm",
+                VoAuditEntity::Firm,
+                id.0,
+                SYS_FIRM_SERVICE.transition(&pool, id.0, VoFirmStatus::Published),
+            )
+            .await?;
+        Ok(Json(ReturnData::default()))
+    }
+
+    /// 弃用固件, 设备不再升级到该版本
+    #[oai(path = "/release/:id/deprecate", method = "post")]
+    async fn deprecate_firm(
+        &self,
+        pool: Data<&DbPool>,
+        req: &Request,
+        id: Path<i32>,
+        user: TokenAuthorization,
+    ) -> Result<Json<ReturnData>> {
+        user.require(VoRole::ReleaseEngineer)?;
+        SYS_AUDIT_SERVICE
+            .track(
+                &pool,
+                &audit_context(req, &user.0),
+                "deprecate_firm",
+                VoAuditEntity::Firm,
+                id.0,
+                SYS_FIRM_SERVICE.transition(&pool, id.0, VoFirmStatus::Deprecated),
+            )
+            .await?;
+        Ok(Json(ReturnData::default()))
+    }
+
+    /// 撤回固件, 运行该版本的设备将被要求升级或回退
+    #[oai(path = "/release/:id/revoke", method = "post")]
+    async fn revoke_firm(
+        &self,
+        pool: Data<&DbPool>,
+        req: &Request,
+        id: Path<i32>,
+        user: TokenAuthorization,
+    ) -> Result<Json<ReturnData>> {
+        user.require(VoRole::ReleaseEngineer)?;
+        SYS_AUDIT_SERVICE
+            .track(
+                &pool,
+                &audit_context(req, &user.0),
+                "revoke_firm",
+                VoAuditEntity::Firm,
+                id.0,
+                SYS_FIRM_SERVICE.transition(&pool, id.0, VoFirmStatus::Revoked),
+            )
+            .await?;
+        Ok(Json(ReturnData::default()))
+    }
+
+    /// 固件的安装上报
+    #[oai(path = "/release/:id/reports", method = "get")]
+    async fn install_reports(
+        &self,
+        pool: Data<&DbPool>,
+        id: Path<i32>,
         _user: TokenAuthorization,
+    ) -> Result<Json<Vec<VoInstallReport>>> {
+        let reports = SYS_FIRM_SERVICE.reports(&pool, id.0).await?;
+        Ok(Json(reports))
+    }
+
+    /// 修改固件所属渠道
+    #[oai(path = "/release/:id/channel", method = "post")]
+    async fn set_firm_channel(
+        &self,
+        pool: Data<&DbPool>,
+        req: &Request,
+        id: Path<i32>,
+        data: Json<VoFirmChannel>,
+        user: TokenAuthorization,
+    ) -> Result<Json<ReturnData>> {
+        user.require(VoRole::ReleaseEngineer)?;
+        SYS_AUDIT_SERVICE
+            .track(
+                &pool,
+                &audit_context(req, &user.0),
+                "set_firm_channel",
+                VoAuditEntity::Firm,
+                id.0,
+                SYS_FIRM_SERVICE.set_channel(&pool, id.0, data.0.channel_id),
+            )
+            .await?;
+        Ok(Json(ReturnData::default()))
+    }
+
+    /// 设置分阶段发布策略
+    #[oai(path = "/rollout/:id", method = "put")]
+    async fn set_rollout(
+        &self,
+        pool: Data<&DbPool>,
+        req: &Request,
+        id: Path<i32>,
+        data: Json<VoUpdateRollout>,
+        user: TokenAuthorization,
+    ) -> Result<Json<ReturnData>> {
+        user.require(VoRole::ReleaseEngineer)?;
+        SYS_AUDIT_SERVICE
+            .track(
+                &pool,
+                &audit_context(req, &user.0),
+                "set_rollout",
+                VoAuditEntity::Firm,
+                id.0,
+                SYS_FIRM_SERVICE.set_rollout(&pool, id.0, data.0),
+            )
+            .await?;
+        Ok(Json(ReturnData::default()))
+    }
+
+    /// 暂停发布
+    #[oai(path = "/rollout/:id/pause", method = "post")]
+    async fn pause_rollout(
+        &self,
+        pool: Data<&DbPool>,
+        req: &Request,
+        id: Path<i32>,
+        user: TokenAuthorization,
+    ) -> Result<Json<ReturnData>> {
+        user.require(VoRole::ReleaseEngineer)?;
+        SYS_AUDIT_SERVICE
+            .track(
+                &pool,
+                &audit_context(req, &user.0),
+                "pause_rollout",
+                VoAuditEntity::Firm,
+                id.0,
+                SYS_FIRM_SERVICE.pause_rollout(&pool, id.0, true),
+            )
+            .await?;
+        Ok(Json(ReturnData::default()))
+    }
+
+    /// 恢复发布
+    #[oai(path = "/rollout/:id/resume", method = "post")]
+    async fn resume_rollout(
+        &self,
+        pool: Data<&DbPool>,
+        req: &Request,
+        id: Path<i32>,
+        user: TokenAuthorization,
+    ) -> Result<Json<ReturnData>> {
+        user.require(VoRole::ReleaseEngineer)?;
+        SYS_AUDIT_SERVICE
+            .track(
+                &pool,
+                &audit_context(req, &user.0),
+                "resume_rollout",
+                VoAuditEntity::Firm,
+                id.0,
+                SYS_FIRM_SERVICE.pause_rollout(&pool, id.0, false),
+            )
+            .await?;
+        Ok(Json(ReturnData::default()))
+    }
+
+    /// 扩大发布比例
+    #[oai(path = "/rollout/:id/advance", method = "post")]
+    async fn advance_rollout(
+        &self,
+        pool: Data<&DbPool>,
+        req: &Request,
+        id: Path<i32>,
+        data: Json<VoRolloutStep>,
+        user: TokenAuthorization,
+    ) -> Result<Json<ReturnData>> {
+        user.require(VoRole::ReleaseEngineer)?;
+        SYS_AUDIT_SERVICE
+            .track(
+                &pool,
+                &audit_context(req, &user.0),
+                "advance_rollout",
+                VoAuditEntity::Firm,
+                id.0,
+                SYS_FIRM_SERVICE.step_rollout(&pool, id.0, data.0.percent, true),
+            )
+            .await?;
+        Ok(Json(ReturnData::default()))
+    }
+
+    /// 回退发布比例, 已升级的设备不受影响
+    #[oai(path = "/rollout/:id/rollback", method = "post")]
+    async fn rollback_rollout(
+        &self,
+        pool: Data<&DbPool>,
+        req: &Request,
+        id: Path<i32>,
+        data: Json<VoRolloutStep>,
+        user: TokenAuthorization,
     ) -> Result<Json<ReturnData>> {
-        SYS_FIRM_SERVICE.delete_firm(&pool, device.0).await?;
+        user.require(VoRole::ReleaseEngineer)?;
+        SYS_AUDIT_SERVICE
+            .track(
+                &pool,
+                &audit_context(req, &user.0),
+                "rollback_rollout",
+                VoAuditEntity::Firm,
+                id.0,
+                SYS_FIRM_SERVICE.step_rollout(&pool, id.0, data.0.percent, false),
+            )
+            .await?;
         Ok(Json(ReturnData::default()))
     }
 
@@ -175,12 +1603,100 @@ impl Api {
         &self,
         pool: Data<&DbPool>,
         device: Path<i32>,
+        channel: Query<Option<String>>,
+        #[oai(name = "Accept-Language")] accept_language: Header<Option<String>>,
         _user: TokenAuthorization,
     ) -> Result<Json<Vec<VoFirm>>> {
-        let firms = SYS_FIRM_SERVICE.firms_by_device(&pool, device.0).await?;
+        let mut firms = SYS_FIRM_SERVICE
+            .firms_by_device(&pool, device.0, channel.0.as_deref())
+            .await?;
+        localize(&mut firms, accept_language.0.as_deref());
         Ok(Json(firms))
     }
 
+    /// 设备检查更新
+    #[oai(path = "/update", method = "get")]
+    #[allow(clippy::too_many_arguments)]
+    async fn check_update(
+        &self,
+        pool: Data<&DbPool>,
+        device_id: Query<Option<String>>,
+        group: Query<Option<String>>,
+        hard_version: Query<String>,
+        version_type: Query<i32>,
+        current: Query<String>,
+        finger_level: Query<Option<i32>>,
+        installed: Query<Option<String>>,
+        #[oai(name = "Accept-Language")] accept_language: Header<Option<String>>,
+        _device: DeviceAuthorization,
+    ) -> Result<Json<VoUpdateCheck>> {
+        let mut result = SYS_FIRM_SERVICE
+            .check_update(
+                &pool,
+                VoCheckUpdate {
+                    device_id: device_id.0,
+                    group: group.0,
+                    hard_version: hard_version.0,
+                    version_type: version_type.0,
+                    current: current.0,
+                    finger_level: finger_level.0.unwrap_or_default(),
+                    installed: installed.0,
+                },
+            )
+            .await?;
+        localize(result.firm.as_mut(), accept_language.0.as_deref());
+        Ok(Json(result))
+    }
+
+    /// 设备登记当前运行的版本
+    #[oai(path = "/checkin", method = "post")]
+    async fn check_in(
+        &self,
+        pool: Data<&DbPool>,
+        data: Json<VoCheckIn>,
+        _device: DeviceAuthorization,
+    ) -> Result<Json<ReturnData>> {
+        SYS_DEVICE_SERVICE
+            .check_in(
+                &pool,
+                &data.0.device_id,
+                data.0.version_type,
+                &data.0.current,
+            )
+            .await?
+            .ok_or(CustomError::DataNotFound)?;
+        Ok(Json(ReturnData::default()))
+    }
+
+    /// 设备上报下载及安装结果
+    #[oai(path = "/report", method = "post")]
+    async fn report(
+        &self,
+        pool: Data<&DbPool>,
+        data: Json<VoAddInstallReport>,
+        _device: DeviceAuthorization,
+    ) -> Result<Json<ReturnData>> {
+        SYS_FIRM_SERVICE.report(&pool, data.0).await?;
+        Ok(Json(ReturnData::default()))
+    }
+
+    /// 固件签名公钥
+    #[oai(path = "/keys", method = "get")]
+    async fn public_keys(&self, _device: DeviceAuthorization) -> Json<Vec<VoPublicKey>> {
+        let store = keystore();
+        Json(
+            store
+                .public_keys()
+                .into_iter()
+                .map(|(key_id, public_key)| VoPublicKey {
+                    active: store.active_key_id() == Some(key_id.as_str()),
+                    key_id,
+                    public_key,
+                })
+                .collect(),
+        )
+    }
+
     /// 获取基础数据
     #[oai(path = "/baseInfo", method = "get")]
     async fn base_info(
@@ -190,6 +1706,11 @@ impl Api {
     ) -> Result<Json<BaseInfo>> {
         let hard = SYS_HARD_SERVICE.devices(&pool).await?;
         let soft = SYS_SOFT_SERVICE.soft_versions(&pool).await?;
-        Ok(Json(BaseInfo { hard, soft }))
+        let channels = SYS_CHANNEL_SERVICE.channels(&pool).await?;
+        Ok(Json(BaseInfo {
+            hard,
+            soft,
+            channels,
+        }))
     }
 }
@@ -9,6 +9,16 @@ pub struct User {
     pub mail: String,
     pub password: String,
     pub update_time: DateTime<Utc>,
+    pub role: i32, // 0只读, 1发布工程师, 2管理员
+    pub disabled: bool,
+    /// 邀请码的 sha256, 接受邀请后清空
+    pub invite_hash: Option<String>,
+    pub invite_expire: Option<DateTime<Utc>>,
+    /// base32 编码的 TOTP 密钥, 验证首个验证码后启用
+    pub totp_secret: Option<String>,
+    pub totp_enabled: bool,
+    /// 最近一次使用的时间步, 防止验证码重放
+    pub totp_step: i64,
 }
 
 #[derive(sqlx::FromRow, Serialize, Deserialize, Object)]
@@ -44,7 +54,114 @@ pub struct Firm {
     pub rely_version_type: Option<i32>,
     pub min: Option<String>,
     pub max: Option<String>,
-    pub des_en: String,
-    pub des_ko: String,
-    pub des_sp: String,
+    pub size: Option<i64>,
+    pub content_type: Option<String>,
+    pub blob_key: Option<String>,
+    pub sha256: Option<String>,
+    pub crc32: Option<String>,
+    pub md5: Option<String>,
+    pub checksum_state: i32, // 0未校验, 1一致, 2不一致, 3文件缺失
+    pub verify_time: Option<DateTime<Utc>>,
+    pub signature: Option<String>,
+    pub signature_key_id: Option<String>,
+    pub status: i32, // 0草稿, 1测试, 2发布, 3弃用, 4撤回
+    pub status_time: Option<DateTime<Utc>>,
+    pub rollout_percent: i32,
+    pub rollout_allowlist: String, // json 数组, 设备id
+    pub rollout_start: Option<DateTime<Utc>>,
+    pub rollout_paused: bool,
+    pub channel_id: i32,
+}
+
+/// 固件的一条多语言说明
+#[derive(sqlx::FromRow, Serialize, Deserialize, Object)]
+pub struct FirmNote {
+    pub firm_id: i32,
+    pub locale: String,
+    pub text: String,
+}
+
+/// 发布渠道, 设备可收到 `level` 不高于其渠道的固件
+#[derive(sqlx::FromRow, Serialize, Deserialize, Object)]
+pub struct Channel {
+    pub id: i32,
+    pub name: String,
+    pub level: i32, // 0 stable, 1 beta, 2 internal
+    pub desc: String,
+}
+
+#[derive(sqlx::FromRow, Serialize, Deserialize, Object)]
+pub struct ChannelAssign {
+    pub id: i32,
+    pub target_type: i32, // 1设备, 2设备组
+    pub target: String,
+    pub channel_id: i32,
+}
+
+/// 已登记的设备
+#[derive(sqlx::FromRow, Serialize, Deserialize, Object)]
+pub struct Device {
+    pub id: i32,
+    pub serial: String,
+    pub mac: Option<String>,
+    pub device_type: i32,
+    pub group_name: Option<String>,
+    pub firmware: String, // json 数组, 各软件类型的当前版本
+    pub check_in_time: Option<DateTime<Utc>>,
+    pub desc: String,
+    pub update_time: DateTime<Utc>,
+}
+
+/// 设备上报的安装进度
+#[derive(sqlx::FromRow, Serialize, Deserialize, Object)]
+pub struct InstallReport {
+    pub id: i32,
+    pub firm_id: i32,
+    pub device_id: String,
+    pub event: i32, // 0开始下载, 1下载完成, 2安装成功, 3安装失败
+    pub error_code: Option<i32>,
+    pub running_version: Option<String>,
+    pub report_time: DateTime<Utc>,
+}
+
+/// 操作审计
+#[derive(sqlx::FromRow, Serialize, Deserialize, Object)]
+pub struct Audit {
+    pub id: i32,
+    pub user_id: Option<i32>,
+    pub action: String,
+    pub entity: i32,
+    pub entity_id: Option<String>,
+    pub before: Option<String>, // json
+    pub after: Option<String>,  // json
+    pub ip: Option<String>,
+    pub create_time: DateTime<Utc>,
+}
+
+/// 机器访问用的 API key, 只保存 sha256
+#[derive(sqlx::FromRow, Serialize, Deserialize, Object)]
+pub struct ApiKey {
+    pub id: i32,
+    pub user_id: i32,
+    pub name: String,
+    /// key 的前几位, 用于辨认
+    pub prefix: String,
+    pub key_hash: String,
+    pub scopes: String, // json
+    pub create_time: DateTime<Utc>,
+    pub last_used_time: Option<DateTime<Utc>>,
+    pub revoked: bool,
+}
+
+/// 上传的固件文件
+#[derive(sqlx::FromRow, Serialize, Deserialize, Object)]
+pub struct Blob {
+    pub key: String,
+    pub file_name: String,
+    pub size: i64,
+    pub content_type: String,
+    pub sha256: String,
+    pub crc32: String,
+    pub md5: String,
+    pub update_time: DateTime<Utc>,
 }
@@ -1,6 +1,12 @@
-use super::dto::{DeviceHard, DeviceSoft, Firm, User};
-use poem::{http::StatusCode, Error as PError};
-use poem_openapi::{Object, Enum};
+use std::collections::HashSet;
+
+use super::dto::{
+    ApiKey, Audit, Blob, Channel, ChannelAssign, Device, DeviceHard, DeviceSoft, Firm, FirmNote,
+    InstallReport, User,
+};
+use crate::utils::{locale, version::Version};
+use poem::{error::ResponseError, http::StatusCode, Error as PError, Response};
+use poem_openapi::{types::multipart::Upload, Enum, Multipart, Object};
 use serde::{Deserialize, Serialize};
 use sqlx::Error as SqlxError;
 use thiserror::Error;
@@ -9,26 +15,33 @@ use thiserror::Error;
 #[derive(Debug, Serialize, Deserialize, Clone, Object)]
 pub struct Token {
     pub access_token: String,
-    // pub refresh_token: String,
+    pub refresh_token: String,
     pub user: VoUser,
 }
 
 impl Token {
-    pub fn new(access_token: String, user: VoUser) -> Token {
+    pub fn new(access_token: String, refresh_token: String, user: VoUser) -> Token {
         Token {
             access_token,
-            // refresh_token,
+            refresh_token,
             user,
         }
     }
 }
 
+/// 刷新令牌
+#[derive(Debug, Serialize, Deserialize, Object)]
+pub struct VoRefreshToken {
+    pub refresh_token: String,
+}
+
 /// 用户
 #[derive(Debug, Serialize, Clone, Deserialize, Object)]
 pub struct VoUser {
     pub id: i32,
     pub name: String,
     pub ticker: i64,
+    pub role: VoRole,
 }
 
 impl From<User> for VoUser {
@@ -37,10 +50,48 @@ impl From<User> for VoUser {
             id: u.id,
             name: u.name,
             ticker: u.update_time.timestamp(),
+            role: u.role.into(),
+        }
+    }
+}
+
+/// 用户角色, 高级角色拥有低级角色的全部权限
+#[derive(Debug, Serialize, Deserialize, Enum, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum VoRole {
+    /// 只读
+    Viewer,
+    /// 管理固件、硬件类型、渠道和设备
+    ReleaseEngineer,
+    /// 管理用户
+    Admin,
+}
+
+impl From<i32> for VoRole {
+    fn from(d: i32) -> Self {
+        match d {
+            1 => VoRole::ReleaseEngineer,
+            2 => VoRole::Admin,
+            _ => VoRole::Viewer,
         }
     }
 }
 
+impl From<VoRole> for i32 {
+    fn from(d: VoRole) -> Self {
+        match d {
+            VoRole::Viewer => 0,
+            VoRole::ReleaseEngineer => 1,
+            VoRole::Admin => 2,
+        }
+    }
+}
+
+/// 修改用户角色
+#[derive(Debug, Serialize, Deserialize, Object)]
+pub struct VoSetRole {
+    pub role: VoRole,
+}
+
 /// 更新用户密码
 #[derive(Debug, Serialize, Clone, Deserialize, Object)]
 pub struct VoUpdateUser {
@@ -48,11 +99,162 @@ pub struct VoUpdateUser {
     pub new_pass: String,
 }
 
+/// 密码策略规则
+#[derive(Debug, Serialize, Deserialize, Enum, Clone, Copy, PartialEq, Eq)]
+pub enum VoPasswordRule {
+    /// 短于最小长度
+    TooShort,
+    /// 超过 72 字节
+    TooLong,
+    /// 在常见或泄露密码列表中
+    Common,
+    /// 与旧密码相同
+    SameAsOld,
+}
+
+/// 违反的密码规则
+#[derive(Debug, Serialize, Deserialize, Object)]
+pub struct VoPasswordViolation {
+    pub rule: VoPasswordRule,
+    pub message: String,
+}
+
+/// 密码不符合策略时的返回
+#[derive(Debug, Serialize, Deserialize, Object)]
+pub struct VoPasswordViolations {
+    pub violations: Vec<VoPasswordViolation>,
+}
+
+impl From<&[VoPasswordRule]> for VoPasswordViolations {
+    fn from(rules: &[VoPasswordRule]) -> Self {
+        let violations = rules
+            .iter()
+            .map(|rule| VoPasswordViolation {
+                rule: *rule,
+                message: match rule {
+                    VoPasswordRule::TooShort => "password is too short",
+                    VoPasswordRule::TooLong => "password is longer than 72 bytes",
+                    VoPasswordRule::Common => "password is too common",
+                    VoPasswordRule::SameAsOld => "new password must differ from the old one",
+                }
+                .to_string(),
+            })
+            .collect();
+        VoPasswordViolations { violations }
+    }
+}
+
+/// 用户管理中的用户信息
+#[derive(Debug, Serialize, Deserialize, Object)]
+pub struct VoUserInfo {
+    pub id: i32,
+    pub name: String,
+    pub mail: String,
+    pub role: VoRole,
+    pub disabled: bool,
+    /// 登录失败次数过多被临时锁定
+    pub locked: bool,
+    /// 已开启两步验证
+    pub totp: bool,
+    /// 尚未接受邀请
+    pub invited: bool,
+    pub update_time: i64,
+}
+
+impl From<User> for VoUserInfo {
+    fn from(u: User) -> Self {
+        VoUserInfo {
+            id: u.id,
+            name: u.name,
+            mail: u.mail,
+            role: u.role.into(),
+            disabled: u.disabled,
+            locked: false,
+            totp: u.totp_enabled,
+            invited: u.invite_hash.is_some(),
+            update_time: u.update_time.timestamp(),
+        }
+    }
+}
+
+/// 创建用户, 不设置密码时生成邀请码
+#[derive(Debug, Serialize, Deserialize, Object)]
+pub struct VoAddUser {
+    pub name: String,
+    pub mail: String,
+    pub password: Option<String>,
+    /// 默认 Viewer
+    pub role: Option<VoRole>,
+}
+
+/// 创建用户或重置密码的结果
+#[derive(Debug, Serialize, Deserialize, Object)]
+pub struct VoInvite {
+    pub id: i32,
+    /// 仅在生成邀请时返回, 需转交用户
+    pub invite_token: Option<String>,
+}
+
+/// 修改用户名
+#[derive(Debug, Serialize, Deserialize, Object)]
+pub struct VoRenameUser {
+    pub id: i32,
+    pub name: String,
+}
+
+/// 重置密码, 不设置密码时重新生成邀请码
+#[derive(Debug, Serialize, Deserialize, Object)]
+pub struct VoResetPass {
+    pub password: Option<String>,
+}
+
+/// 接受邀请并设置密码
+#[derive(Debug, Serialize, Deserialize, Object)]
+pub struct VoAcceptInvite {
+    pub invite_token: String,
+    pub password: String,
+}
+
+/// 已开启两步验证时, 密码校验通过后返回挑战令牌
+#[derive(Debug, Serialize, Deserialize, Object)]
+pub struct VoLoginChallenge {
+    pub challenge_token: String,
+    /// 有效期, 秒
+    pub expires_in: i64,
+}
+
+/// 两步登录的第二步, `code` 为验证码或恢复码
+#[derive(Debug, Serialize, Deserialize, Object)]
+pub struct VoLoginTotp {
+    pub challenge_token: String,
+    pub code: String,
+}
+
+/// 两步验证的验证码或恢复码
+#[derive(Debug, Serialize, Deserialize, Object)]
+pub struct VoTotpCode {
+    pub code: String,
+}
+
+/// 两步验证登记信息, 验证首个验证码后生效
+#[derive(Debug, Serialize, Deserialize, Object)]
+pub struct VoTotpEnroll {
+    pub secret: String,
+    pub otpauth_uri: String,
+}
+
+/// 恢复码, 只在生成时返回一次
+#[derive(Debug, Serialize, Deserialize, Object)]
+pub struct VoRecoveryCodes {
+    pub codes: Vec<String>,
+}
+
 /// 基础数据
 #[derive(Serialize, Deserialize, Object)]
 pub struct BaseInfo {
     pub hard: Vec<VoDeviceHard>,
     pub soft: Vec<DeviceSoft>,
+    pub channels: Vec<Channel>,
 }
 
 /// 登陆
@@ -77,13 +279,42 @@ pub struct VoFirm {
     pub rely_version_type: Option<i32>,
     pub min: Option<String>,
     pub max: Option<String>,
-    pub des_en: String,
-    pub des_ko: String,
-    pub des_sp: String,
+    /// 各语言的更新说明
+    pub notes: Vec<VoFirmNote>,
+    /// 按 `Accept-Language` 协商的更新说明, 没有匹配的语言时为 `desc`
+    pub note: String,
+    /// `note` 的语言, 使用 `desc` 时为空
+    pub note_locale: Option<String>,
+    pub size: Option<i64>,
+    pub content_type: Option<String>,
+    pub blob_key: Option<String>,
+    pub sha256: Option<String>,
+    pub crc32: Option<String>,
+    pub md5: Option<String>,
+    pub checksum_state: VoChecksumState,
+    pub verify_time: Option<i64>,
+    /// 对 sha256 摘要的 Ed25519 签名
+    pub signature: Option<String>,
+    pub signature_key_id: Option<String>,
+    pub status: VoFirmStatus,
+    pub status_time: Option<i64>,
+    pub rollout: VoRollout,
+    pub channel_id: i32,
+    pub installs: VoInstallStats,
 }
 
 impl From<Firm> for VoFirm {
     fn from(f: Firm) -> Self {
+        let rollout = VoRollout {
+            percent: f.rollout_percent,
+            allowlist: serde_json::from_str(&f.rollout_allowlist).unwrap_or_default(),
+            start_time: f.rollout_start.map(|t| t.timestamp()),
+            paused: f.rollout_paused,
+        };
+        let url = match f.blob_key {
+            Some(_) => download_url(f.id),
+            None => f.url,
+        };
         VoFirm {
             id: f.id,
             hard_version: f.hard_version,
@@ -91,19 +322,357 @@ impl From<Firm> for VoFirm {
             version_format: f.version_format,
             version_type: f.version_type,
             finger_level: f.finger_level,
-            url: f.url,
-            desc: f.desc,
+            url,
+            desc: f.desc.clone(),
             update_time: f.update_time.timestamp(),
             rely_version_type: f.rely_version_type,
             min: f.min,
             max: f.max,
-            des_en: f.des_en,
-            des_ko: f.des_ko,
-            des_sp: f.des_sp,
+            notes: Vec::new(),
+            note: f.desc,
+            note_locale: None,
+            size: f.size,
+            content_type: f.content_type,
+            blob_key: f.blob_key,
+            sha256: f.sha256,
+            crc32: f.crc32,
+            md5: f.md5,
+            checksum_state: f.checksum_state.into(),
+            verify_time: f.verify_time.map(|t| t.timestamp()),
+            signature: f.signature,
+            signature_key_id: f.signature_key_id,
+            status: f.status.into(),
+            status_time: f.status_time.map(|t| t.timestamp()),
+            rollout,
+            channel_id: f.channel_id,
+            installs: VoInstallStats::default(),
+        }
+    }
+}
+
+impl VoFirm {
+    /// 按语言范围挑选更新说明, `ranges` 由 `Accept-Language` 解析
+    pub fn localize(&mut self, ranges: &[String]) {
+        let locales: Vec<&str> = self.notes.iter().map(|n| n.locale.as_str()).collect();
+        if let Some(i) = locale::negotiate(ranges, &locales) {
+            self.note = self.notes[i].text.clone();
+            self.note_locale = Some(self.notes[i].locale.clone());
+        }
+    }
+}
+
+/// 固件的一条多语言更新说明
+#[derive(Object, Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct VoFirmNote {
+    /// BCP-47 语言标签, 如 `en`、`zh-Hant`、`es-419`
+    pub locale: String,
+    pub text: String,
+}
+
+impl From<FirmNote> for VoFirmNote {
+    fn from(n: FirmNote) -> Self {
+        VoFirmNote {
+            locale: n.locale,
+            text: n.text,
+        }
+    }
+}
+
+/// 固件列表排序, `-` 开头为倒序
+#[derive(Serialize, Deserialize, Enum, Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum VoFirmSort {
+    /// 按版本号从新到旧
+    #[default]
+    #[oai(rename = "version")]
+    #[serde(rename = "version")]
+    Version,
+    #[oai(rename = "update_time")]
+    #[serde(rename = "update_time")]
+    UpdateTime,
+    #[oai(rename = "-update_time")]
+    #[serde(rename = "-update_time")]
+    UpdateTimeDesc,
+    #[oai(rename = "version_name")]
+    #[serde(rename = "version_name")]
+    VersionName,
+    #[oai(rename = "-version_name")]
+    #[serde(rename = "-version_name")]
+    VersionNameDesc,
+    #[oai(rename = "id")]
+    #[serde(rename = "id")]
+    Id,
+    #[oai(rename = "-id")]
+    #[serde(rename = "-id")]
+    IdDesc,
+}
+
+/// 固件列表查询条件
+#[derive(Default)]
+pub struct VoFirmQuery {
+    pub channel: Option<String>,
+    pub hard_version: Option<i32>,
+    pub version_type: Option<i32>,
+    pub finger_level: Option<i32>,
+    /// 更新时间范围, 秒级时间戳, 包含两端
+    pub from: Option<i64>,
+    pub to: Option<i64>,
+    /// 在版本名和各语言说明中搜索
+    pub q: Option<String>,
+    pub sort: Option<VoFirmSort>,
+    pub page: Option<i32>,
+    pub page_size: Option<i32>,
+    /// 上一页返回的 `next_cursor`, 优先于 `page`
+    pub cursor: Option<String>,
+}
+
+/// 固件分页
+#[derive(Object, Serialize, Deserialize)]
+pub struct VoFirmPage {
+    pub total: i64,
+    pub page: i32,
+    pub page_size: i32,
+    /// 下一页的游标, 已是最后一页时为空
+    pub next_cursor: Option<String>,
+    pub items: Vec<VoFirm>,
+}
+
+/// 全文搜索命中的字段片段, 内容已做 HTML 转义, 关键词以 `<mark></mark>` 标出
+#[derive(Object, Serialize, Deserialize)]
+pub struct VoSnippet {
+    /// 字段名: `version_name`、`desc` 或 `note`
+    pub field: String,
+    /// `note` 的语言标签
+    pub locale: Option<String>,
+    pub text: String,
+}
+
+/// 全文搜索结果
+#[derive(Object, Serialize, Deserialize)]
+pub struct VoSearchHit {
+    pub firm: VoFirm,
+    pub snippets: Vec<VoSnippet>,
+}
+
+/// 分阶段发布策略
+#[derive(Object, Serialize, Deserialize, Clone)]
+pub struct VoRollout {
+    /// 0-100, 按设备id哈希分桶
+    pub percent: i32,
+    /// 不受比例限制的设备id
+    pub allowlist: Vec<String>,
+    pub start_time: Option<i64>,
+    pub paused: bool,
+}
+
+/// 设置分阶段发布策略
+#[derive(Object, Serialize, Deserialize)]
+pub struct VoUpdateRollout {
+    #[oai(validator(minimum(value = "0"), maximum(value = "100")))]
+    pub percent: i32,
+    pub allowlist: Vec<String>,
+    pub start_time: Option<i64>,
+}
+
+/// 调整发布比例
+#[derive(Object, Serialize, Deserialize)]
+pub struct VoRolloutStep {
+    #[oai(validator(minimum(value = "0"), maximum(value = "100")))]
+    pub percent: i32,
+}
+
+/// 固件发布状态
+#[derive(Serialize, Deserialize, Enum, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum VoFirmStatus {
+    Draft,
+    Testing,
+    Published,
+    Deprecated,
+    Revoked,
+}
+
+impl VoFirmStatus {
+    /// 只有草稿和测试中的固件可以修改内容
+    pub fn editable(self) -> bool {
+        matches!(self, VoFirmStatus::Draft | VoFirmStatus::Testing)
+    }
+
+    /// 允许的状态变更, 撤回后不可再变更
+    pub fn can_transition_to(self, to: VoFirmStatus) -> bool {
+        use VoFirmStatus::*;
+        matches!(
+            (self, to),
+            (Draft, Testing)
+                | (Draft, Published)
+                | (Testing, Draft)
+                | (Testing, Published)
+                | (Published, Deprecated)
+                | (Published, Revoked)
+                | (Deprecated, Published)
+                | (Deprecated, Revoked)
+        )
+    }
+}
+
+impl From<i32> for VoFirmStatus {
+    fn from(d: i32) -> Self {
+        match d {
+            1 => VoFirmStatus::Testing,
+            2 => VoFirmStatus::Published,
+            3 => VoFirmStatus::Deprecated,
+            4 => VoFirmStatus::Revoked,
+            // 未知状态不对设备可见
+            _ => VoFirmStatus::Draft,
         }
     }
 }
 
+impl From<VoFirmStatus> for i32 {
+    fn from(d: VoFirmStatus) -> Self {
+        match d {
+            VoFirmStatus::Draft => 0,
+            VoFirmStatus::Testing => 1,
+            VoFirmStatus::Published => 2,
+            VoFirmStatus::Deprecated => 3,
+            VoFirmStatus::Revoked => 4,
+        }
+    }
+}
+
+/// 固件文件校验状态
+#[derive(Serialize, Deserialize, Enum, Clone, Copy, PartialEq, Eq)]
+pub enum VoChecksumState {
+    Unverified,
+    Valid,
+    Mismatch,
+    Missing,
+}
+
+impl From<i32> for VoChecksumState {
+    fn from(d: i32) -> Self {
+        match d {
+            1 => VoChecksumState::Valid,
+            2 => VoChecksumState::Mismatch,
+            3 => VoChecksumState::Missing,
+            _ => VoChecksumState::Unverified,
+        }
+    }
+}
+
+impl From<VoChecksumState> for i32 {
+    fn from(d: VoChecksumState) -> Self {
+        match d {
+            VoChecksumState::Unverified => 0,
+            VoChecksumState::Valid => 1,
+            VoChecksumState::Mismatch => 2,
+            VoChecksumState::Missing => 3,
+        }
+    }
+}
+
+/// 固件文件下载地址
+pub fn download_url(id: i32) -> String {
+    format!("/api/download/{}", id)
+}
+
+/// 上传固件文件
+#[derive(Multipart)]
+pub struct VoUploadFirm {
+    pub file: Upload,
+}
+
+/// 已上传的固件文件
+#[derive(Object, Serialize, Deserialize)]
+pub struct VoBlob {
+    pub key: String,
+    pub file_name: String,
+    pub size: i64,
+    pub content_type: String,
+    pub sha256: String,
+    pub crc32: String,
+    pub md5: String,
+}
+
+impl From<Blob> for VoBlob {
+    fn from(b: Blob) -> Self {
+        VoBlob {
+            key: b.key,
+            file_name: b.file_name,
+            size: b.size,
+            content_type: b.content_type,
+            sha256: b.sha256,
+            crc32: b.crc32,
+            md5: b.md5,
+        }
+    }
+}
+
+/// 设备检查更新
+pub struct VoCheckUpdate {
+    pub device_id: Option<String>,
+    pub group: Option<String>,
+    pub hard_version: String,
+    pub version_type: i32,
+    pub current: String,
+    pub finger_level: i32,
+    /// 设备上其它软件类型的当前版本, 格式 `type:version,type:version`
+    pub installed: Option<String>,
+}
+
+/// 设备登记当前运行的版本
+#[derive(Object, Serialize, Deserialize)]
+pub struct VoCheckIn {
+    pub device_id: String,
+    pub version_type: i32,
+    pub current: String,
+}
+
+/// 检查更新结果
+#[derive(Object, Serialize, Deserialize)]
+pub struct VoUpdateCheck {
+    pub has_update: bool,
+    /// 设备当前版本已被撤回, 需要尽快升级或回退到 `firm`
+    pub revoked: bool,
+    pub firm: Option<VoFirm>,
+    pub manifest: Option<VoSignedManifest>,
+}
+
+/// 升级清单内容, 序列化后签名
+#[derive(Serialize)]
+pub struct Manifest {
+    pub firm_id: i32,
+    pub hard_version: String,
+    pub version_name: String,
+    pub version_format: String,
+    pub version_type: i32,
+    pub url: String,
+    pub size: Option<i64>,
+    pub sha256: Option<String>,
+    pub image_signature: Option<String>,
+    pub image_key_id: Option<String>,
+    pub rely_version_type: Option<i32>,
+    pub min: Option<String>,
+    pub max: Option<String>,
+    pub revoked_current: bool,
+    pub key_id: String,
+    pub issued_at: i64,
+}
+
+/// 签名的升级清单, 设备使用 `key_id` 对应的公钥校验 `payload` 原文
+#[derive(Object, Serialize, Deserialize)]
+pub struct VoSignedManifest {
+    pub payload: String,
+    pub key_id: String,
+    pub signature: String,
+}
+
+/// 签名公钥
+#[derive(Object, Serialize, Deserialize)]
+pub struct VoPublicKey {
+    pub key_id: String,
+    pub public_key: String,
+    pub active: bool,
+}
+
 #[derive(Debug, Error)]
 pub enum CustomError {
     #[error("token expire")]
@@ -116,12 +685,57 @@ pub enum CustomError {
     DataNotFound,
     #[error("password not incorrept")]
     PasswordError,
+    #[error("invalid param: `{0}`")]
+    InvalidParam(String),
+    #[error("invalid state: `{0}`")]
+    InvalidState(String),
+    #[error("account disabled")]
+    AccountDisabled,
+    #[error("permission denied")]
+    PermissionDenied,
+    #[error("too many login attempts, retry after {0}s")]
+    TooManyAttempts(u64),
+    #[error("account locked, retry after {0}s")]
+    AccountLocked(u64),
+    #[error("password policy: `{0:?}`")]
+    PasswordPolicy(Vec<VoPasswordRule>),
+}
+
+/// 以 json 返回违反的密码规则
+#[derive(Debug, Error)]
+#[error("password policy")]
+struct PasswordPolicyError(Vec<VoPasswordRule>);
+
+impl ResponseError for PasswordPolicyError {
+    fn status(&self) -> StatusCode {
+        StatusCode::UNPROCESSABLE_ENTITY
+    }
+
+    fn as_response(&self) -> Response {
+        let body = VoPasswordViolations::from(self.0.as_slice());
+        Response::builder()
+            .status(self.status())
+            .content_type("application/json")
+            .body(serde_json::to_string(&body).unwrap_or_default())
+    }
 }
 
 impl From<SqlxError> for CustomError {
     fn from(e: SqlxError) -> Self {
         match e {
             SqlxError::RowNotFound => CustomError::DataNotFound,
+            SqlxError::Database(ref d) if d.message().starts_with("UNIQUE constraint failed") => {
+                CustomError::InvalidState(d.message().to_string())
+            }
+            _ => CustomError::Internal(e.to_string()),
+        }
+    }
+}
+
+impl From<std::io::Error> for CustomError {
+    fn from(e: std::io::Error) -> Self {
+        match e.kind() {
+            std::io::ErrorKind::NotFound => CustomError::DataNotFound,
             _ => CustomError::Internal(e.to_string()),
         }
     }
@@ -129,7 +743,6 @@ impl From<SqlxError> for CustomError {
 
 impl From<CustomError> for PError {
     fn from(e: CustomError) -> Self {
-        println!("{:?}", e);
         match e {
             CustomError::TokenError
             | CustomError::MailOrPasswordFail
@@ -139,7 +752,27 @@ impl From<CustomError> for PError {
             CustomError::DataNotFound => {
                 PError::from_string(format!("{:?}", e), StatusCode::NOT_FOUND)
             }
-            _ => PError::from_string(format!("{:?}", e), StatusCode::INTERNAL_SERVER_ERROR),
+            CustomError::InvalidParam(_) => {
+                PError::from_string(format!("{:?}", e), StatusCode::BAD_REQUEST)
+            }
+            CustomError::InvalidState(_) => {
+                PError::from_string(format!("{:?}", e), StatusCode::CONFLICT)
+            }
+            CustomError::AccountDisabled | CustomError::PermissionDenied => {
+                PError::from_string(format!("{:?}", e), StatusCode::FORBIDDEN)
+            }
+            CustomError::TooManyAttempts(_) => {
+                PError::from_string(format!("{:?}", e), StatusCode::TOO_MANY_REQUESTS)
+            }
+            CustomError::AccountLocked(_) => {
+                PError::from_string(format!("{:?}", e), StatusCode::LOCKED)
+            }
+            CustomError::PasswordPolicy(rules) => PasswordPolicyError(rules).into(),
+            // 客户端错误已在响应中返回, 只记录服务端错误
+            _ => {
+                tracing::error!(error = ?e, "request failed");
+                PError::from_string(format!("{:?}", e), StatusCode::INTERNAL_SERVER_ERROR)
+            }
         }
     }
 }
@@ -223,6 +856,392 @@ pub struct VoUpdateHard {
     pub desc: String,
 }
 
+/// 设备当前运行的版本
+#[derive(Object, Serialize, Deserialize, Clone)]
+pub struct VoRunning {
+    pub version_type: i32,
+    /// 设备上报的版本号
+    pub version: String,
+}
+
+/// 已登记的设备
+#[derive(Object, Serialize, Deserialize)]
+pub struct VoDevice {
+    pub id: i32,
+    pub serial: String,
+    pub mac: Option<String>,
+    /// 硬件类型id
+    pub device_type: i32,
+    pub group_name: Option<String>,
+    pub firmware: Vec<VoRunning>,
+    pub check_in_time: Option<i64>,
+    pub desc: String,
+    pub update_time: i64,
+}
+
+impl From<Device> for VoDevice {
+    fn from(d: Device) -> Self {
+        VoDevice {
+            id: d.id,
+            serial: d.serial,
+            mac: d.mac,
+            device_type: d.device_type,
+            group_name: d.group_name,
+            firmware: serde_json::from_str(&d.firmware).unwrap_or_default(),
+            check_in_time: d.check_in_time.map(|t| t.timestamp()),
+            desc: d.desc,
+            update_time: d.update_time.timestamp(),
+        }
+    }
+}
+
+/// 登记设备
+#[derive(Object, Serialize, Deserialize)]
+pub struct VoAddDevice {
+    pub serial: String,
+    pub mac: Option<String>,
+    pub device_type: i32,
+    pub group_name: Option<String>,
+    pub desc: String,
+}
+
+/// 更新设备
+#[derive(Object, Serialize, Deserialize)]
+pub struct VoUpdateDevice {
+    pub id: i32,
+    pub serial: String,
+    pub mac: Option<String>,
+    pub device_type: i32,
+    pub group_name: Option<String>,
+    pub desc: String,
+}
+
+/// 批量导入结果, 有错误时不导入任何设备
+#[derive(Object, Serialize, Deserialize)]
+pub struct VoImportDevices {
+    pub imported: i32,
+    pub errors: Vec<String>,
+}
+
+/// 安装进度
+#[derive(Serialize, Deserialize, Enum, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum VoInstallEvent {
+    DownloadStarted,
+    DownloadCompleted,
+    InstallSucceeded,
+    InstallFailed,
+}
+
+impl From<i32> for VoInstallEvent {
+    fn from(d: i32) -> Self {
+        match d {
+            1 => VoInstallEvent::DownloadCompleted,
+            2 => VoInstallEvent::InstallSucceeded,
+            3 => VoInstallEvent::InstallFailed,
+            _ => VoInstallEvent::DownloadStarted,
+        }
+    }
+}
+
+impl From<VoInstallEvent> for i32 {
+    fn from(d: VoInstallEvent) -> Self {
+        match d {
+            VoInstallEvent::DownloadStarted => 0,
+            VoInstallEvent::DownloadCompleted => 1,
+            VoInstallEvent::InstallSucceeded => 2,
+            VoInstallEvent::InstallFailed => 3,
+        }
+    }
+}
+
+/// 设备 (或代理蓝牙升级的 App) 上报安装进度
+#[derive(Object, Serialize, Deserialize)]
+pub struct VoAddInstallReport {
+    pub firm_id: i32,
+    pub device_id: String,
+    pub event: VoInstallEvent,
+    /// 安装失败时必填
+    pub error_code: Option<i32>,
+    /// 安装后运行的版本
+    pub running_version: Option<String>,
+}
+
+#[derive(Object, Serialize, Deserialize)]
+pub struct VoInstallReport {
+    pub id: i32,
+    pub firm_id: i32,
+    pub device_id: String,
+    pub event: VoInstallEvent,
+    pub error_code: Option<i32>,
+    pub running_version: Option<String>,
+    pub report_time: i64,
+}
+
+impl From<InstallReport> for VoInstallReport {
+    fn from(r: InstallReport) -> Self {
+        VoInstallReport {
+            id: r.id,
+            firm_id: r.firm_id,
+            device_id: r.device_id,
+            event: r.event.into(),
+            error_code: r.error_code,
+            running_version: r.running_version,
+            report_time: r.report_time.timestamp(),
+        }
+    }
+}
+
+/// 安装统计, 按设备计数, 成功/失败取设备最后一次安装结果
+#[derive(Object, Serialize, Deserialize, Default, Clone)]
+pub struct VoInstallStats {
+    pub downloads: i32,
+    pub successes: i32,
+    pub failures: i32,
+    /// 0-1, 尚无安装结果时为 0
+    pub failure_rate: f64,
+}
+
+impl VoInstallStats {
+    pub fn new(downloads: i32, successes: i32, failures: i32) -> Self {
+        let finished = successes + failures;
+        VoInstallStats {
+            downloads,
+            successes,
+            failures,
+            failure_rate: if finished > 0 {
+                failures as f64 / finished as f64
+            } else {
+                0.0
+            },
+        }
+    }
+}
+
+/// 审计对象
+#[derive(Serialize, Deserialize, Enum, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum VoAuditEntity {
+    Firm,
+    Blob,
+    DeviceType,
+    VersionType,
+    Channel,
+    ChannelAssign,
+    Device,
+    User,
+    ApiKey,
+}
+
+impl From<i32> for VoAuditEntity {
+    fn from(d: i32) -> Self {
+        match d {
+            1 => VoAuditEntity::Blob,
+            2 => VoAuditEntity::DeviceType,
+            3 => VoAuditEntity::VersionType,
+            4 => VoAuditEntity::Channel,
+            5 => VoAuditEntity::ChannelAssign,
+            6 => VoAuditEntity::Device,
+            7 => VoAuditEntity::User,
+            8 => VoAuditEntity::ApiKey,
+            _ => VoAuditEntity::Firm,
+        }
+    }
+}
+
+impl From<VoAuditEntity> for i32 {
+    fn from(d: VoAuditEntity) -> Self {
+        match d {
+            VoAuditEntity::Firm => 0,
+            VoAuditEntity::Blob => 1,
+            VoAuditEntity::DeviceType => 2,
+            VoAuditEntity::VersionType => 3,
+            VoAuditEntity::Channel => 4,
+            VoAuditEntity::ChannelAssign => 5,
+            VoAuditEntity::Device => 6,
+            VoAuditEntity::User => 7,
+            VoAuditEntity::ApiKey => 8,
+        }
+    }
+}
+
+/// API key 权限范围
+#[derive(Serialize, Deserialize, Enum, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum VoApiScope {
+    #[oai(rename = "firm:read")]
+    #[serde(rename = "firm:read")]
+    FirmRead,
+    #[oai(rename = "firm:write")]
+    #[serde(rename = "firm:write")]
+    FirmWrite,
+}
+
+impl VoApiScope {
+    /// 创建和使用该权限所需的最低角色
+    pub fn role(&self) -> VoRole {
+        match self {
+            VoApiScope::FirmRead => VoRole::Viewer,
+            VoApiScope::FirmWrite => VoRole::ReleaseEngineer,
+        }
+    }
+}
+
+/// API key 信息, 不含 key 本身
+#[derive(Object, Serialize, Deserialize)]
+pub struct VoApiKey {
+    pub id: i32,
+    pub user_id: i32,
+    pub name: String,
+    pub prefix: String,
+    pub scopes: Vec<VoApiScope>,
+    pub create_time: i64,
+    pub last_used_time: Option<i64>,
+    pub revoked: bool,
+}
+
+impl From<ApiKey> for VoApiKey {
+    fn from(k: ApiKey) -> Self {
+        VoApiKey {
+            id: k.id,
+            user_id: k.user_id,
+            name: k.name,
+            prefix: k.prefix,
+            scopes: serde_json::from_str(&k.scopes).unwrap_or_default(),
+            create_time: k.create_time.timestamp(),
+            last_used_time: k.last_used_time.map(|t| t.timestamp()),
+            revoked: k.revoked,
+        }
+    }
+}
+
+/// 创建 API key
+#[derive(Object, Serialize, Deserialize)]
+pub struct VoAddApiKey {
+    pub name: String,
+    pub scopes: Vec<VoApiScope>,
+}
+
+/// 新建的 API key, `key` 只在创建时返回一次
+#[derive(Object, Serialize, Deserialize)]
+pub struct VoNewApiKey {
+    pub id: i32,
+    pub key: String,
+}
+
+/// 审计记录, `before`/`after` 为修改前后的 json
+#[derive(Object, Serialize, Deserialize)]
+pub struct VoAudit {
+    pub id: i32,
+    pub user_id: Option<i32>,
+    pub action: String,
+    pub entity: VoAuditEntity,
+    pub entity_id: Option<String>,
+    pub before: Option<String>,
+    pub after: Option<String>,
+    pub ip: Option<String>,
+    pub create_time: i64,
+}
+
+impl From<Audit> for VoAudit {
+    fn from(a: Audit) -> Self {
+        VoAudit {
+            id: a.id,
+            user_id: a.user_id,
+            action: a.action,
+            entity: a.entity.into(),
+            entity_id: a.entity_id,
+            before: a.before,
+            after: a.after,
+            ip: a.ip,
+            create_time: a.create_time.timestamp(),
+        }
+    }
+}
+
+/// 审计记录分页
+#[derive(Object, Serialize, Deserialize)]
+pub struct VoAuditPage {
+    pub total: i64,
+    pub page: i32,
+    pub page_size: i32,
+    pub items: Vec<VoAudit>,
+}
+
+/// 添加发布渠道
+#[derive(Object, Serialize, Deserialize)]
+pub struct VoAddChannel {
+    pub name: String,
+    pub level: i32,
+    pub desc: String,
+}
+
+/// 更新发布渠道
+#[derive(Object, Serialize, Deserialize)]
+pub struct VoUpdateChannel {
+    pub id: i32,
+    pub name: String,
+    pub level: i32,
+    pub desc: String,
+}
+
+/// 设置固件的发布渠道
+#[derive(Object, Serialize, Deserialize)]
+pub struct VoFirmChannel {
+    pub channel_id: i32,
+}
+
+/// 渠道分配对象
+#[derive(Serialize, Deserialize, Enum, Clone, Copy, PartialEq, Eq)]
+pub enum VoAssignTarget {
+    Device,
+    Group,
+}
+
+impl From<i32> for VoAssignTarget {
+    fn from(d: i32) -> Self {
+        match d {
+            2 => VoAssignTarget::Group,
+            _ => VoAssignTarget::Device,
+        }
+    }
+}
+
+impl From<VoAssignTarget> for i32 {
+    fn from(d: VoAssignTarget) -> Self {
+        match d {
+            VoAssignTarget::Device => 1,
+            VoAssignTarget::Group => 2,
+        }
+    }
+}
+
+/// 设备或设备组的渠道
+#[derive(Object, Serialize, Deserialize)]
+pub struct VoChannelAssign {
+    pub id: i32,
+    pub target_type: VoAssignTarget,
+    /// 设备id或设备组名
+    pub target: String,
+    pub channel_id: i32,
+}
+
+impl From<ChannelAssign> for VoChannelAssign {
+    fn from(a: ChannelAssign) -> Self {
+        VoChannelAssign {
+            id: a.id,
+            target_type: a.target_type.into(),
+            target: a.target,
+            channel_id: a.channel_id,
+        }
+    }
+}
+
+/// 分配渠道
+#[derive(Object, Serialize, Deserialize)]
+pub struct VoSetChannelAssign {
+    pub target_type: VoAssignTarget,
+    pub target: String,
+    pub channel_id: i32,
+}
+
 /// 添加软件类型
 #[derive(Object, Serialize, Deserialize)]
 pub struct VoAddSoft {
@@ -244,15 +1263,19 @@ pub struct VoAddFirm {
     pub version_format: String,
     pub version_type: i32,
     pub finger_level: i32,
-    pub url: String,
+    /// 外部文件地址, 与 `blob_key` 二选一
+    pub url: Option<String>,
+    /// 通过 `/firms/upload` 上传的文件
+    pub blob_key: Option<String>,
     pub desc: String,
     pub update_time: i64,
     pub rely_version_type: Option<i32>,
     pub min: Option<String>,
     pub max: Option<String>,
-    pub des_en: String,
-    pub des_ko: String,
-    pub des_sp: String,
+    /// 各语言的更新说明, 覆盖原有的全部说明
+    #[oai(default)]
+    #[serde(default)]
+    pub notes: Vec<VoFirmNote>,
 }
 impl VoAddFirm {
     pub fn check_data(self) -> VoAddFirm {
@@ -266,19 +1289,80 @@ impl VoAddFirm {
                 version_type: self.version_type,
                 finger_level: self.finger_level,
                 url: self.url,
+                blob_key: self.blob_key,
                 desc: self.desc,
                 update_time: self.update_time,
                 rely_version_type: None,
                 min: None,
                 max: None,
-                des_en: self.des_en,
-                des_ko: self.des_ko,
-                des_sp: self.des_sp, 
+                notes: self.notes,
             }
         }
     }
 }
 
+/// 固件文件需指定外部地址或上传的文件
+fn validate_source(url: &Option<String>, blob_key: &Option<String>) -> Result<(), CustomError> {
+    let url = url.as_deref().is_none_or(|u| u.trim().is_empty());
+    let blob = blob_key.as_deref().is_none_or(|b| b.trim().is_empty());
+    if url == blob {
+        return Err(CustomError::InvalidParam("url or blob_key".to_string()));
+    }
+    Ok(())
+}
+
+/// 更新说明的语言标签须合法且不重复
+fn validate_notes(notes: &[VoFirmNote]) -> Result<(), CustomError> {
+    let mut locales = HashSet::new();
+    for note in notes {
+        match locale::normalize(&note.locale) {
+            Some(l) if locales.insert(l.to_ascii_lowercase()) => {}
+            _ => return Err(CustomError::InvalidParam(format!("locale {}", note.locale))),
+        }
+    }
+    Ok(())
+}
+
+/// 校验固件版本号及升级限制
+fn validate_versions(
+    version_name: &str,
+    version_format: &str,
+    min: &Option<String>,
+    max: &Option<String>,
+) -> Result<(), CustomError> {
+    if Version::parse_device(version_name).is_none() {
+        return Err(CustomError::InvalidParam("version_name".to_string()));
+    }
+    if Version::parse(version_format).is_none() {
+        return Err(CustomError::InvalidParam("version_format".to_string()));
+    }
+    let bound = |b: &Option<String>, name: &str| match b.as_deref().map(str::trim) {
+        Some(b) if !b.is_empty() => Version::parse_device(b)
+            .map(Some)
+            .ok_or_else(|| CustomError::InvalidParam(name.to_string())),
+        _ => Ok(None),
+    };
+    if let (Some(min), Some(max)) = (bound(min, "min")?, bound(max, "max")?) {
+        if min > max {
+            return Err(CustomError::InvalidParam("min > max".to_string()));
+        }
+    }
+    Ok(())
+}
+
+impl VoAddFirm {
+    pub fn validate(&self) -> Result<(), CustomError> {
+        validate_source(&self.url, &self.blob_key)?;
+        validate_versions(
+            &self.version_name,
+            &self.version_format,
+            &self.min,
+            &self.max,
+        )?;
+        validate_notes(&self.notes)
+    }
+}
+
 /// 更新固件
 #[derive(Object, Serialize, Deserialize)]
 pub struct VoUpdateFirm {
@@ -288,18 +1372,33 @@ pub struct VoUpdateFirm {
     pub version_format: String,
     pub version_type: i32,
     pub finger_level: i32,
-    pub url: String,
+    /// 外部文件地址, 与 `blob_key` 二选一
+    pub url: Option<String>,
+    /// 通过 `/firms/upload` 上传的文件
+    pub blob_key: Option<String>,
     pub desc: String,
     pub update_time: i64,
     pub rely_version_type: Option<i32>,
     pub min: Option<String>,
     pub max: Option<String>,
-    pub des_en: String,
-    pub des_ko: String,
-    pub des_sp: String,
+    /// 各语言的更新说明, 覆盖原有的全部说明
+    #[oai(default)]
+    #[serde(default)]
+    pub notes: Vec<VoFirmNote>,
 }
 
 impl VoUpdateFirm {
+    pub fn validate(&self) -> Result<(), CustomError> {
+        validate_source(&self.url, &self.blob_key)?;
+        validate_versions(
+            &self.version_name,
+            &self.version_format,
+            &self.min,
+            &self.max,
+        )?;
+        validate_notes(&self.notes)
+    }
+
     pub fn check_data(self) -> VoUpdateFirm {
         if self.rely_version_type.is_some() || self.min.is_some() {
             self
@@ -312,14 +1411,13 @@ impl VoUpdateFirm {
                 version_type: self.version_type,
                 finger_level: self.finger_level,
                 url: self.url,
+                blob_key: self.blob_key,
                 desc: self.desc,
                 update_time: self.update_time,
                 rely_version_type: None,
                 min: None,
                 max: None,
-                des_en: self.des_en,
-                des_ko: self.des_ko,
-                des_sp: self.des_sp, 
+                notes: self.notes,
             }
         }
     }
@@ -335,4 +1433,30 @@ impl Default for ReturnData {
     fn default() -> Self {
         Self { message: String::from("ok") }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod test {
+    use super::{VoFirmStatus, VoInstallStats};
+
+    #[test]
+    fn test_install_stats() {
+        let stats = VoInstallStats::new(10, 6, 2);
+        assert_eq!(stats.failure_rate, 0.25);
+        assert_eq!(VoInstallStats::new(3, 0, 0).failure_rate, 0.0);
+    }
+
+    #[test]
+    fn test_firm_status() {
+        use VoFirmStatus::*;
+        assert!(Draft.can_transition_to(Published));
+        assert!(Published.can_transition_to(Revoked));
+        assert!(Deprecated.can_transition_to(Published));
+        assert!(!Published.can_transition_to(Draft));
+        assert!(!Revoked.can_transition_to(Published));
+        assert!(!Draft.can_transition_to(Draft));
+        assert!(Draft.editable() && Testing.editable());
+        assert!(!Published.editable() && !Revoked.editable());
+        assert_eq!(VoFirmStatus::from(9), Draft);
+    }
+}
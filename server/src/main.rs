@@ -2,39 +2,133 @@
 extern crate lazy_static;
 extern crate thiserror;
 
+use std::{str::FromStr, sync::Arc, time::Duration};
+
+use config::{init_config, Config};
 use controller::Api;
 use poem::{
-    endpoint::StaticFilesEndpoint, listener::TcpListener, middleware::Cors, EndpointExt, Result,
-    Route, Server,
+    endpoint::StaticFilesEndpoint, listener::TcpListener, middleware::Cors, web::Data, EndpointExt,
+    Result, Route, Server,
 };
 use poem_openapi::OpenApiService;
+use service::SYS_FIRM_SERVICE;
+use sqlx::sqlite::SqliteConnectOptions;
+use utils::{
+    blob_store::{LocalBlobStore, SharedBlobStore},
+    password_policy::{init_password_policy, PasswordPolicy},
+    signing::{init_keystore, KeyStore},
+};
 
+pub mod config;
 pub mod controller;
 // pub mod dao;
 pub mod domain;
+pub mod migrate;
 pub mod service;
 pub mod utils;
 type DbPool = sqlx::SqlitePool;
+
+/// 定时重新校验固件文件摘要
+fn spawn_checksum_job(pool: DbPool, store: SharedBlobStore) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(60 * 60));
+        loop {
+            interval.tick().await;
+            if let Err(e) = SYS_FIRM_SERVICE
+                .verify_checksums(&Data(&pool), &Data(&store))
+                .await
+            {
+                tracing::warn!(error = %e, "checksum job failed");
+            }
+        }
+    });
+}
+/// 连接数据库, 文件不存在时创建
+async fn connect(url: &str) -> Result<DbPool, sqlx::Error> {
+    let options = SqliteConnectOptions::from_str(url)?.create_if_missing(true);
+    DbPool::connect_with(options).await
+}
+
+/// 执行数据库迁移并记录日志, 之后补齐需要由服务端计算的列
+async fn run_migrations(pool: &DbPool) -> Result<(), Box<dyn std::error::Error>> {
+    let applied = migrate::migrate(pool).await?;
+    if applied.is_empty() {
+        tracing::info!("database is up to date");
+    }
+    let filled = SYS_FIRM_SERVICE.fill_version_keys(&Data(pool)).await?;
+    if filled > 0 {
+        tracing::info!(firms = filled, "firm version keys filled");
+    }
+    Ok(())
+}
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     if std::env::var_os("RUST_LOG").is_none() {
-        std::env::set_var("RUST_LOG", "poem=debug");
+        std::env::set_var("RUST_LOG", "poem=debug,firm_management=info");
     }
     // env_logger::init();
     tracing_subscriber::fmt::init();
-    let pool = DbPool::connect("sqlite://firm.db").await?;
-    let api_service = OpenApiService::new(Api, "Firm Api", "1.0.0").server("http://0.0.0.0:3000");
+    init_config(Config::load()?);
+    let config = config::config();
+    // `firm_management migrate` 只执行数据库迁移
+    match std::env::args().nth(1).as_deref() {
+        Some("migrate") => {
+            let pool = connect(&config.database.url).await?;
+            run_migrations(&pool).await?;
+            return Ok(());
+        }
+        Some(command) => return Err(format!("unknown command `{}`", command).into()),
+        None => {}
+    }
+    if config.dev {
+        tracing::warn!("running in dev mode");
+    }
+    let keystore = KeyStore::load(&config.storage.keystore)?;
+    if keystore.is_empty() {
+        tracing::warn!(
+            "{} not found, firmware will not be signed",
+            config.storage.keystore.display()
+        );
+    }
+    init_keystore(keystore);
+    let policy = PasswordPolicy::load(
+        config.password.min_length,
+        config.password.denylist_file.as_deref(),
+    )?;
+    tracing::info!(denylist = policy.denylist_len(), "password policy loaded");
+    init_password_policy(policy);
+    let pool = connect(&config.database.url).await?;
+    if config.database.auto_migrate {
+        run_migrations(&pool).await?;
+    }
+    let store: SharedBlobStore = Arc::new(LocalBlobStore::new(&config.storage.blob_dir));
+    spawn_checksum_job(pool.clone(), store.clone());
+    let api_service =
+        OpenApiService::new(Api, "Firm Api", "1.0.0").server(config.server.api_url.as_str());
     // let ui = api_service.swagger_ui();
     // let spec = api_service.spec();
     let route = Route::new()
-        .nest("/", StaticFilesEndpoint::new("./dist").index_file("index.html"))
-        .nest("/api", api_service)
+        .nest(
+            "/",
+            StaticFilesEndpoint::new(&config.server.static_dir).index_file("index.html"),
+        )
         // .nest("/ui", ui)
         // .at("/spec", poem::endpoint::make_sync(move |_| spec.clone()))
-        .with(Cors::new())
-        .data(pool);
+        .nest("/api", api_service);
+    let origins = &config.server.cors_origins;
+    let route = if !origins.is_empty() {
+        route
+            .with(Cors::new().allow_origins(origins.iter().map(String::as_str)))
+            .boxed()
+    } else if config.dev {
+        route.with(Cors::new()).boxed()
+    } else {
+        route.boxed()
+    };
+    let route = route.data(pool).data(store);
 
-    Server::new(TcpListener::bind("0.0.0.0:3000"))
+    Server::new(TcpListener::bind(config.server.bind.as_str()))
         .run(route)
         .await?;
     Ok(())
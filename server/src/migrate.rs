@@ -0,0 +1,208 @@
+use chrono::Utc;
+use crypto::{digest::Digest, sha2::Sha256};
+use sqlx::{Executor, SqlitePool};
+use thiserror::Error;
+
+use crate::utils::query_builder::QueryBuilder;
+
+/// 数据库迁移, 按版本号顺序执行, 每个迁移只执行一次
+pub struct Migration {
+    pub version: i64,
+    pub name: &'static str,
+    pub sql: &'static str,
+}
+
+impl Migration {
+    /// 迁移脚本的 sha256, 用于发现已执行的脚本被修改
+    fn checksum(&self) -> String {
+        let mut sha256 = Sha256::new();
+        sha256.input_str(self.sql);
+        sha256.result_str()
+    }
+}
+
+/// 新增迁移时在末尾追加, 已发布的脚本不能再修改
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        name: "init",
+        sql: include_str!("../migrations/0001_init.sql"),
+    },
+    Migration {
+        version: 2,
+        name: "release_management",
+        sql: include_str!("../migrations/0002_release_management.sql"),
+    },
+    Migration {
+        version: 3,
+        name: "firm_note",
+        sql: include_str!("../migrations/0003_firm_note.sql"),
+    },
+    Migration {
+        version: 4,
+        name: "firm_version_key",
+        sql: include_str!("../migrations/0004_firm_version_key.sql"),
+    },
+];
+
+const TABLE_MIGRATIONS: &str = "_migrations";
+const CREATE_MIGRATIONS: &str = r#"CREATE TABLE IF NOT EXISTS "_migrations" (
+	"version"	INTEGER NOT NULL,
+	"name"	TEXT NOT NULL,
+	"checksum"	TEXT NOT NULL,
+	"applied_time"	datetime NOT NULL,
+	PRIMARY KEY("version")
+)"#;
+
+#[derive(Debug, Error)]
+pub enum MigrateError {
+    #[error("migration {0}: {1}")]
+    Database(i64, sqlx::Error),
+    #[error("migration {0} was modified after it was applied")]
+    Modified(i64),
+    #[error("migration {0} was applied by a newer version of the server")]
+    Unknown(i64),
+}
+
+/// 执行未执行过的迁移, 返回本次执行的迁移
+///
+/// 每个迁移在单独的事务中执行, 失败时回滚该迁移, 之前的迁移保留
+pub async fn migrate(pool: &SqlitePool) -> Result<Vec<&'static Migration>, MigrateError> {
+    run(pool, MIGRATIONS).await
+}
+
+async fn run(
+    pool: &SqlitePool,
+    migrations: &'static [Migration],
+) -> Result<Vec<&'static Migration>, MigrateError> {
+    let db = |e| MigrateError::Database(0, e);
+    pool.execute(CREATE_MIGRATIONS).await.map_err(db)?;
+    let applied: Vec<(i64, String)> = QueryBuilder::select(TABLE_MIGRATIONS, "version, checksum")
+        .order_asc("version")
+        .build()
+        .query_as()
+        .fetch_all(pool)
+        .await
+        .map_err(db)?;
+    for (version, checksum) in &applied {
+        match migrations.iter().find(|m| m.version == *version) {
+            Some(m) if m.checksum() == *checksum => {}
+            Some(_) => return Err(MigrateError::Modified(*version)),
+            None => return Err(MigrateError::Unknown(*version)),
+        }
+    }
+
+    let mut done = Vec::new();
+    for migration in migrations
+        .iter()
+        .filter(|m| !applied.iter().any(|(v, _)| *v == m.version))
+    {
+        let db = |e| MigrateError::Database(migration.version, e);
+        let mut tx = pool.begin().await.map_err(db)?;
+        tx.execute(migration.sql).await.map_err(db)?;
+        QueryBuilder::insert(TABLE_MIGRATIONS)
+            .value("version", migration.version)
+            .value("name", migration.name)
+            .value("checksum", migration.checksum())
+            .value("applied_time", Utc::now())
+            .build()
+            .query()
+            .execute(&mut tx)
+            .await
+            .map_err(db)?;
+        tx.commit().await.map_err(db)?;
+        tracing::info!(
+            version = migration.version,
+            name = migration.name,
+            "migration applied"
+        );
+        done.push(migration);
+    }
+    Ok(done)
+}
+
+#[cfg(test)]
+mod test {
+    use sqlx::{sqlite::SqlitePoolOptions, SqlitePool};
+
+    use super::{migrate, run, MigrateError, Migration, MIGRATIONS};
+
+    async fn memory_pool() -> SqlitePool {
+        // 内存数据库每个连接独立, 只使用一个连接
+        SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    pub async fn test_migrate() {
+        let pool = memory_pool().await;
+        assert_eq!(migrate(&pool).await.unwrap().len(), MIGRATIONS.len());
+        assert!(migrate(&pool).await.unwrap().is_empty());
+
+        let url_type: String =
+            sqlx::query_scalar("SELECT type FROM pragma_table_info('firm') WHERE name = 'url'")
+                .fetch_one(&pool)
+                .await
+                .unwrap();
+        assert_eq!(url_type, "TEXT");
+        let channels: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM channel")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(channels, 3);
+    }
+
+    #[tokio::test]
+    pub async fn test_migrate_existing() {
+        // 迁移前手工建立的数据库
+        let pool = memory_pool().await;
+        sqlx::query(MIGRATIONS[0].sql).execute(&pool).await.unwrap();
+        sqlx::query("INSERT INTO firm (hard_version, version_name, version_type, url, des_en) VALUES (1, '010000', 1, 'http://a/b.bin', 'Initial')")
+            .execute(&pool)
+            .await
+            .unwrap();
+        sqlx::query("INSERT INTO user (name, mail, password) VALUES ('a', 'a@b.c', 'x')")
+            .execute(&pool)
+            .await
+            .unwrap();
+        migrate(&pool).await.unwrap();
+
+        let role: i32 = sqlx::query_scalar("SELECT role FROM user")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(role, 2);
+        let (status, url): (i32, String) = sqlx::query_as("SELECT status, url FROM firm")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!((status, url.as_str()), (2, "http://a/b.bin"));
+        let note: (String, String) = sqlx::query_as("SELECT locale, text FROM firm_note")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(note, ("en".to_string(), "Initial".to_string()));
+    }
+
+    #[tokio::test]
+    pub async fn test_migrate_modified() {
+        static MODIFIED: &[Migration] = &[Migration {
+            version: 1,
+            name: "init",
+            sql: "CREATE TABLE t (id INTEGER)",
+        }];
+        let pool = memory_pool().await;
+        run(&pool, &MIGRATIONS[..1]).await.unwrap();
+        assert!(matches!(
+            run(&pool, MODIFIED).await,
+            Err(MigrateError::Modified(1))
+        ));
+        assert!(matches!(
+            run(&pool, &[]).await,
+            Err(MigrateError::Unknown(1))
+        ));
+    }
+}
@@ -1,15 +1,53 @@
-use chrono::{DateTime, NaiveDateTime, Utc};
+use std::{
+    cmp::Reverse,
+    collections::HashMap,
+    future::Future,
+    sync::atomic::{AtomicU32, Ordering},
+    time::Duration,
+};
+
+use chrono::{DateTime, NaiveDateTime, TimeZone, Utc};
+use crypto::{digest::Digest, sha2::Sha256};
 use poem::web::Data;
+use sqlx::{Sqlite, Transaction};
+use tokio::io::AsyncReadExt;
 
 use crate::{
+    config::config,
     domain::{
-        dto::{DeviceHard, DeviceSoft, Firm, User},
+        dto::{
+            ApiKey, Audit, Blob, Channel, ChannelAssign, Device, DeviceHard, DeviceSoft, Firm,
+            FirmNote, InstallReport, User,
+        },
         vo::{
-            CustomError, VoAddFirm, VoAddHard, VoAddSoft, VoDeviceHard, VoFirm, VoLogin,
-            VoUpdateFirm, VoUpdateHard, VoUpdateSoft, VoUpdateUser, VoUser,
+            CustomError, Manifest, Token, VoAcceptInvite, VoAddApiKey, VoAddChannel, VoAddDevice,
+            VoAddFirm, VoAddHard, VoAddInstallReport, VoAddSoft, VoAddUser, VoApiKey, VoApiScope,
+            VoAssignTarget, VoAudit, VoAuditEntity, VoAuditPage, VoBlob, VoChannelAssign,
+            VoCheckUpdate, VoChecksumState, VoDevice, VoDeviceHard, VoFirm, VoFirmNote, VoFirmPage,
+            VoFirmQuery, VoFirmSort, VoFirmStatus, VoImportDevices, VoInstallEvent,
+            VoInstallReport, VoInstallStats, VoInvite, VoLogin, VoLoginChallenge, VoLoginTotp,
+            VoNewApiKey, VoRecoveryCodes, VoRenameUser, VoResetPass, VoRole, VoRunning,
+            VoSearchHit, VoSetChannelAssign, VoSignedManifest, VoSnippet, VoTotpEnroll,
+            VoUpdateChannel, VoUpdateCheck, VoUpdateDevice, VoUpdateFirm, VoUpdateHard,
+            VoUpdateRollout, VoUpdateSoft, VoUpdateUser, VoUploadFirm, VoUser, VoUserInfo,
+        },
+    },
+    utils::{
+        blob_store::SharedBlobStore,
+        checksum::{DigestHasher, Digests},
+        jwt::{
+            gen_challenge_token, gen_token_id, gen_user_token, validate_challenge_token,
+            validate_refresh_token, CHALLENGE_TTL,
         },
+        locale,
+        login_guard::{Blocked, LoginGuard},
+        password_policy::password_policy,
+        query_builder::{Cond, QueryBuilder, SqlValue},
+        signing::{from_hex, keystore, Signature},
+        totp,
+        ttl_cache::TtlCache,
+        version::Version,
     },
-    utils::sql_helper::SqlHelper,
     DbPool,
 };
 
@@ -18,88 +56,828 @@ lazy_static! {
     pub static ref SYS_HARD_SERVICE: DeviceHardService = DeviceHardService {};
     pub static ref SYS_SOFT_SERVICE: DeviceSoftService = DeviceSoftService {};
     pub static ref SYS_FIRM_SERVICE: FirmService = FirmService {};
+    pub static ref SYS_CHANNEL_SERVICE: ChannelService = ChannelService {};
+    pub static ref SYS_DEVICE_SERVICE: DeviceService = DeviceService {};
+    pub static ref SYS_AUDIT_SERVICE: AuditService = AuditService {};
+    pub static ref SYS_API_KEY_SERVICE: ApiKeyService = ApiKeyService {};
+    /// 账号不存在时用于校验的密码哈希, 与真实密码的 cost 相同
+    static ref DUMMY_HASH: String = bcrypt::hash("dummy", 10).expect("bcrypt hash");
+    /// 下载外部固件文件, 超时后放弃, 避免校验任务被无响应的地址卡住
+    static ref FETCH_CLIENT: reqwest::Client = reqwest::Client::builder()
+        .connect_timeout(FETCH_CONNECT_TIMEOUT)
+        .timeout(FETCH_TIMEOUT)
+        .build()
+        .expect("http client");
 }
 
+/// 连接外部文件地址的超时
+const FETCH_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+/// 下载外部文件的总超时, 包括读取响应内容
+const FETCH_TIMEOUT: Duration = Duration::from_secs(300);
+/// 校验已上传文件时每次读取的字节数
+const BLOB_READ_CHUNK: u64 = 1024 * 1024;
+
 pub struct UserService;
 
 const TABLE_USER: &str = "user";
-const USER_COLUMNS: &str = "id, name, mail, password, update_time";
+const USER_COLUMNS: &str =
+    "id, name, mail, password, update_time, role, disabled, invite_hash, invite_expire, totp_secret, totp_enabled, totp_step";
+const TABLE_REFRESH_TOKEN: &str = "refresh_token";
+const TABLE_RECOVERY_CODE: &str = "recovery_code";
+const TICKER_TTL: Duration = Duration::from_secs(60);
+/// 每次生成的恢复码数量
+const RECOVERY_CODES: usize = 10;
+/// 邀请码有效期, 小时
+const INVITE_EXPIRE: i64 = 72;
+lazy_static! {
+    /// 用户id -> (ticker, 是否禁用, 角色)
+    static ref TICKER_CACHE: TtlCache<i32, (i64, bool, i32)> = TtlCache::new(TICKER_TTL);
+    /// 登录失败计数, 键为 `mail:<邮箱>` 或 `ip:<地址>`
+    static ref LOGIN_GUARD: LoginGuard = LoginGuard::default();
+}
+
+fn account_key(mail: &str) -> String {
+    format!("mail:{}", mail.trim().to_lowercase())
+}
+
+impl From<Blocked> for CustomError {
+    fn from(b: Blocked) -> Self {
+        let secs = b.retry_after.as_secs().max(1);
+        if b.locked {
+            CustomError::AccountLocked(secs)
+        } else {
+            CustomError::TooManyAttempts(secs)
+        }
+    }
+}
 
 impl UserService {
-    pub async fn login(&self, pool: &Data<&DbPool>, data: VoLogin) -> Result<VoUser, CustomError> {
-        let mut helper = SqlHelper::query(TABLE_USER, USER_COLUMNS);
-        let sql = helper.and_where_eq("mail").sql();
-        let user: User = sqlx::query_as(&sql)
-            .bind(&data.email)
+    pub async fn login(
+        &self,
+        pool: &Data<&DbPool>,
+        data: VoLogin,
+        ip: Option<&str>,
+    ) -> Result<VoUser, CustomError> {
+        let account = account_key(&data.email);
+        let ip_key = ip.map(|ip| format!("ip:{}", ip));
+        if let Some(ip_key) = &ip_key {
+            LOGIN_GUARD.check(ip_key)?;
+        }
+        LOGIN_GUARD.check(&account)?;
+        let user: Option<User> = QueryBuilder::select(TABLE_USER, USER_COLUMNS)
+            .and_where(Cond::eq("mail", &data.email))
+            .build()
+            .query_as()
+            .fetch_optional(pool.0)
+            .await?;
+        // 账号不存在时同样校验一次, 避免通过响应时间判断账号是否存在
+        let hash = user
+            .as_ref()
+            .map_or(DUMMY_HASH.as_str(), |u| u.password.as_str());
+        let verified = matches!(bcrypt::verify(&data.password, hash), Ok(true));
+        let user = match user {
+            Some(user) if verified => user,
+            _ => return Err(self.login_failed(&data.email, &account, ip, ip_key.as_deref())),
+        };
+        LOGIN_GUARD.reset(&account);
+        if user.disabled {
+            return Err(CustomError::AccountDisabled);
+        }
+        Ok(user.into())
+    }
+
+    /// 记录登录失败, 达到限制时返回退避或锁定错误
+    fn login_failed(
+        &self,
+        mail: &str,
+        account: &str,
+        ip: Option<&str>,
+        ip_key: Option<&str>,
+    ) -> CustomError {
+        let policy = &config().lockout;
+        let ip_blocked =
+            ip_key.and_then(|key| LOGIN_GUARD.fail(policy, key, policy.ip_max_failures));
+        if let Some(Blocked { locked: true, .. }) = ip_blocked {
+            tracing::warn!(ip, "login blocked for ip after repeated failures");
+        }
+        let account_blocked = LOGIN_GUARD.fail(policy, account, policy.max_failures);
+        if let Some(Blocked { locked: true, .. }) = account_blocked {
+            tracing::warn!(mail, ip, "account locked after repeated login failures");
+        }
+        // 账号和 IP 都受限时返回等待时间更长的
+        match (account_blocked, ip_blocked) {
+            (Some(a), Some(i)) => std::cmp::max_by_key(a, i, |b| b.retry_after).into(),
+            (Some(blocked), None) | (None, Some(blocked)) => blocked.into(),
+            (None, None) => CustomError::MailOrPasswordFail,
+        }
+    }
+
+    /// 解除登录失败锁定
+    pub async fn unlock(&self, pool: &Data<&DbPool>, id: i32) -> Result<(), CustomError> {
+        let mail: String = QueryBuilder::select(TABLE_USER, "mail")
+            .and_where(Cond::eq("id", id))
+            .build()
+            .query_scalar()
+            .fetch_one(pool.0)
+            .await?;
+        LOGIN_GUARD.reset(&account_key(&mail));
+        tracing::info!(user = id, "account unlocked");
+        Ok(())
+    }
+
+    async fn user(&self, pool: &Data<&DbPool>, id: i32) -> Result<User, CustomError> {
+        let user = QueryBuilder::select(TABLE_USER, USER_COLUMNS)
+            .and_where(Cond::eq("id", id))
+            .build()
+            .query_as()
+            .fetch_one(pool.0)
+            .await?;
+        Ok(user)
+    }
+
+    /// 已开启两步验证时返回挑战令牌, 否则可直接签发令牌
+    pub async fn login_challenge(
+        &self,
+        pool: &Data<&DbPool>,
+        user: &VoUser,
+    ) -> Result<Option<VoLoginChallenge>, CustomError> {
+        let enabled: bool = QueryBuilder::select(TABLE_USER, "totp_enabled")
+            .and_where(Cond::eq("id", user.id))
+            .build()
+            .query_scalar()
             .fetch_one(pool.0)
+            .await?;
+        Ok(enabled.then(|| VoLoginChallenge {
+            challenge_token: gen_challenge_token(user.id),
+            expires_in: CHALLENGE_TTL,
+        }))
+    }
+
+    /// 两步登录的第二步, 失败计入登录失败次数
+    pub async fn login_totp(
+        &self,
+        pool: &Data<&DbPool>,
+        data: VoLoginTotp,
+        ip: Option<&str>,
+    ) -> Result<VoUser, CustomError> {
+        let id = validate_challenge_token(&data.challenge_token).ok_or(CustomError::TokenError)?;
+        let user = self.user(pool, id).await?;
+        let account = account_key(&user.mail);
+        let ip_key = ip.map(|ip| format!("ip:{}", ip));
+        if let Some(ip_key) = &ip_key {
+            LOGIN_GUARD.check(ip_key)?;
+        }
+        LOGIN_GUARD.check(&account)?;
+        if !user.totp_enabled {
+            return Err(CustomError::TokenError);
+        }
+        if !self.second_factor(pool, &user, &data.code).await? {
+            return Err(self.login_failed(&user.mail, &account, ip, ip_key.as_deref()));
+        }
+        LOGIN_GUARD.reset(&account);
+        if user.disabled {
+            return Err(CustomError::AccountDisabled);
+        }
+        Ok(user.into())
+    }
+
+    /// 校验验证码或恢复码, 已使用的时间步和恢复码不能再次使用
+    async fn second_factor(
+        &self,
+        pool: &Data<&DbPool>,
+        user: &User,
+        code: &str,
+    ) -> Result<bool, CustomError> {
+        let secret = match &user.totp_secret {
+            Some(secret) => secret,
+            None => return Ok(false),
+        };
+        if let Some(step) = totp::verify(secret, code, Utc::now().timestamp()) {
+            let rows = QueryBuilder::update(TABLE_USER)
+                .set("totp_step", step)
+                .and_where(Cond::eq("id", user.id))
+                .and_where(Cond::lt("totp_step", step))
+                .build()
+                .query()
+                .execute(pool.0)
+                .await?
+                .rows_affected();
+            return Ok(rows > 0);
+        }
+        let hash = token_hash(&totp::normalize_recovery_code(code));
+        let rows = QueryBuilder::update(TABLE_RECOVERY_CODE)
+            .set("used", true)
+            .and_where(Cond::eq("user_id", user.id))
+            .and_where(Cond::eq("code_hash", hash))
+            .and_where(Cond::eq("used", false))
+            .build()
+            .query()
+            .execute(pool.0)
+            .await?
+            .rows_affected();
+        Ok(rows > 0)
+    }
+
+    /// 生成两步验证密钥, 验证首个验证码前不生效
+    pub async fn totp_enroll(
+        &self,
+        pool: &Data<&DbPool>,
+        user: &VoUser,
+    ) -> Result<VoTotpEnroll, CustomError> {
+        let user = self.user(pool, user.id).await?;
+        if user.totp_enabled {
+            return Err(CustomError::InvalidState(
+                "totp already enabled".to_string(),
+            ));
+        }
+        let secret = totp::gen_secret();
+        QueryBuilder::update(TABLE_USER)
+            .set("totp_secret", &secret)
+            .set("totp_step", 0)
+            .and_where(Cond::eq("id", user.id))
+            .build()
+            .query()
+            .execute(pool.0)
+            .await?;
+        Ok(VoTotpEnroll {
+            otpauth_uri: totp::otpauth_uri(&config().auth.totp_issuer, &user.mail, &secret),
+            secret,
+        })
+    }
+
+    /// 验证首个验证码后开启两步验证, 返回恢复码
+    pub async fn totp_activate(
+        &self,
+        pool: &Data<&DbPool>,
+        user: &VoUser,
+        code: &str,
+    ) -> Result<VoRecoveryCodes, CustomError> {
+        let user = self.user(pool, user.id).await?;
+        if user.totp_enabled {
+            return Err(CustomError::InvalidState(
+                "totp already enabled".to_string(),
+            ));
+        }
+        let step = user
+            .totp_secret
+            .as_deref()
+            .and_then(|secret| totp::verify(secret, code, Utc::now().timestamp()))
+            .ok_or_else(|| CustomError::InvalidParam("code".to_string()))?;
+        QueryBuilder::update(TABLE_USER)
+            .set("totp_enabled", true)
+            .set("totp_step", step)
+            .and_where(Cond::eq("id", user.id))
+            .build()
+            .query()
+            .execute(pool.0)
+            .await?;
+        self.gen_recovery_codes(pool, user.id).await
+    }
+
+    /// 关闭两步验证, 需要验证码或恢复码
+    pub async fn totp_disable(
+        &self,
+        pool: &Data<&DbPool>,
+        user: &VoUser,
+        code: &str,
+    ) -> Result<(), CustomError> {
+        let user = self.user(pool, user.id).await?;
+        if !user.totp_enabled || !self.second_factor(pool, &user, code).await? {
+            return Err(CustomError::InvalidParam("code".to_string()));
+        }
+        self.reset_totp(pool, user.id).await
+    }
+
+    /// 重新生成恢复码, 旧的恢复码失效
+    pub async fn regen_recovery_codes(
+        &self,
+        pool: &Data<&DbPool>,
+        user: &VoUser,
+        code: &str,
+    ) -> Result<VoRecoveryCodes, CustomError> {
+        let user = self.user(pool, user.id).await?;
+        if !user.totp_enabled || !self.second_factor(pool, &user, code).await? {
+            return Err(CustomError::InvalidParam("code".to_string()));
+        }
+        self.gen_recovery_codes(pool, user.id).await
+    }
+
+    /// 清除两步验证, 用于用户关闭或管理员重置
+    pub async fn reset_totp(&self, pool: &Data<&DbPool>, id: i32) -> Result<(), CustomError> {
+        let mut tx = pool.0.begin().await?;
+        QueryBuilder::update(TABLE_USER)
+            .set("totp_secret", None::<String>)
+            .set("totp_enabled", false)
+            .set("totp_step", 0)
+            .and_where(Cond::eq("id", id))
+            .build()
+            .query()
+            .execute(&mut tx)
+            .await?;
+        QueryBuilder::delete(TABLE_RECOVERY_CODE)
+            .and_where(Cond::eq("user_id", id))
+            .build()
+            .query()
+            .execute(&mut tx)
+            .await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn gen_recovery_codes(
+        &self,
+        pool: &Data<&DbPool>,
+        id: i32,
+    ) -> Result<VoRecoveryCodes, CustomError> {
+        let codes: Vec<String> = (0..RECOVERY_CODES)
+            .map(|_| totp::gen_recovery_code())
+            .collect();
+        let mut tx = pool.0.begin().await?;
+        QueryBuilder::delete(TABLE_RECOVERY_CODE)
+            .and_where(Cond::eq("user_id", id))
+            .build()
+            .query()
+            .execute(&mut tx)
+            .await?;
+        for code in &codes {
+            QueryBuilder::insert(TABLE_RECOVERY_CODE)
+                .value("user_id", id)
+                .value(
+                    "code_hash",
+                    token_hash(&totp::normalize_recovery_code(code)),
+                )
+                .build()
+                .query()
+                .execute(&mut tx)
+                .await?;
+        }
+        tx.commit().await?;
+        Ok(VoRecoveryCodes { codes })
+    }
+    /// 登录后签发令牌, 开始新的令牌族
+    pub async fn issue_token(
+        &self,
+        pool: &Data<&DbPool>,
+        user: VoUser,
+    ) -> Result<Token, CustomError> {
+        self.issue(pool, user, &gen_token_id()).await
+    }
+
+    async fn issue(
+        &self,
+        pool: &Data<&DbPool>,
+        user: VoUser,
+        family: &str,
+    ) -> Result<Token, CustomError> {
+        let jti = gen_token_id();
+        let now = Utc::now();
+        QueryBuilder::delete(TABLE_REFRESH_TOKEN)
+            .and_where(Cond::lt("expire_time", now))
+            .build()
+            .query()
+            .execute(pool.0)
+            .await?;
+        QueryBuilder::insert(TABLE_REFRESH_TOKEN)
+            .value("jti", &jti)
+            .value("family", family)
+            .value("user_id", user.id)
+            .value(
+                "expire_time",
+                now + chrono::Duration::seconds(config().auth.refresh_ttl),
+            )
+            .build()
+            .query()
+            .execute(pool.0)
+            .await?;
+        Ok(gen_user_token(user, &jti, family))
+    }
+
+    /// 轮换刷新令牌; 已使用过的刷新令牌再次出现时作废整个令牌族
+    pub async fn refresh_token(
+        &self,
+        pool: &Data<&DbPool>,
+        refresh_token: &str,
+    ) -> Result<Token, CustomError> {
+        let claims = validate_refresh_token(refresh_token).ok_or(CustomError::TokenError)?;
+        let row: Option<(bool, bool)> = QueryBuilder::select(TABLE_REFRESH_TOKEN, "used, revoked")
+            .and_where(Cond::eq("jti", &claims.jti))
+            .and_where(Cond::eq("family", &claims.family))
+            .build()
+            .query_as()
+            .fetch_optional(pool.0)
+            .await?;
+        match row {
+            Some((false, false)) => {}
+            Some((true, false)) => {
+                self.revoke_family(pool, &claims.family).await?;
+                return Err(CustomError::TokenError);
+            }
+            _ => return Err(CustomError::TokenError),
+        }
+        let rows_affected = QueryBuilder::update(TABLE_REFRESH_TOKEN)
+            .set("used", true)
+            .and_where(Cond::eq("jti", &claims.jti))
+            .and_where(Cond::eq("used", false))
+            .and_where(Cond::eq("revoked", false))
+            .build()
+            .query()
+            .execute(pool.0)
+            .await?
+            .rows_affected();
+        if rows_affected == 0 {
+            // 并发使用同一刷新令牌
+            self.revoke_family(pool, &claims.family).await?;
+            return Err(CustomError::TokenError);
+        }
+        let user = self
+            .user(pool, claims.user.id)
             .await
-            .map_err(|_e| CustomError::MailOrPasswordFail)?;
-        match bcrypt::verify(&data.password, &user.password) {
-            Ok(true) => Ok(user.into()),
-            _ => Err(CustomError::MailOrPasswordFail),
+            .map_err(|_| CustomError::TokenError)?;
+        if user.disabled {
+            return Err(CustomError::AccountDisabled);
         }
+        self.issue(pool, user.into(), &claims.family).await
     }
+
+    async fn revoke_family(&self, pool: &Data<&DbPool>, family: &str) -> Result<(), CustomError> {
+        tracing::warn!(family, "refresh token reused, revoking token family");
+        QueryBuilder::update(TABLE_REFRESH_TOKEN)
+            .set("revoked", true)
+            .and_where(Cond::eq("family", family))
+            .build()
+            .query()
+            .execute(pool.0)
+            .await?;
+        Ok(())
+    }
+
+    /// 修改密码, 其他登录全部失效, 返回当前会话的新令牌
     pub async fn change_pass(
         &self,
         pool: &Data<&DbPool>,
         user: &VoUser,
         data: VoUpdateUser,
-    ) -> Result<(), CustomError> {
-        let mut helper = SqlHelper::query(TABLE_USER, USER_COLUMNS);
-        let sql = helper.and_where_eq("id").sql();
-        let user: User = sqlx::query_as(&sql)
-            .bind(user.id)
-            .fetch_one(pool.0)
+    ) -> Result<Token, CustomError> {
+        let user = self
+            .user(pool, user.id)
             .await
             .map_err(|_| CustomError::MailOrPasswordFail)?;
         match bcrypt::verify(&data.old_pass, &user.password) {
             Ok(true) => {
-                if let Ok(gen_pass) = bcrypt::hash(&user.password, 10) {
-                    sqlx::query("UPDATE user SET password = ?, update_time = ? WHERE id = ?")
-                        .bind(gen_pass)
-                        .bind(Utc::now().naive_utc())
-                        .bind(user.id)
-                        .execute(pool.0)
-                        .await?;
-                    Ok(())
-                } else {
-                    Err(CustomError::Internal("internal_error".to_string()))
-                }
+                check_password(&data.new_pass, Some(&user.password))?;
+                let gen_pass = bcrypt::hash(&data.new_pass, 10)
+                    .map_err(|e| CustomError::Internal(e.to_string()))?;
+                let mut tx = pool.0.begin().await?;
+                QueryBuilder::update(TABLE_USER)
+                    .set("password", gen_pass)
+                    .and_where(Cond::eq("id", user.id))
+                    .build()
+                    .query()
+                    .execute(&mut tx)
+                    .await?;
+                revoke_tokens(&mut tx, user.id).await?;
+                tx.commit().await?;
+                TICKER_CACHE.remove(&user.id);
+                let user = self.user(pool, user.id).await?;
+                self.issue_token(pool, user.into()).await
             }
             _ => Err(CustomError::PasswordError),
         }
     }
 
+    /// 校验令牌中的 `ticker` 和角色与数据库一致, 结果缓存 `TICKER_TTL`
     pub async fn check_token(
         &self,
         pool: &Data<&DbPool>,
         data: VoUser,
-    ) -> Result<User, CustomError> {
-        let mut helper = SqlHelper::query(TABLE_USER, USER_COLUMNS);
-        let sql = helper.and_where_eq("id").sql();
-        let user: User = sqlx::query_as(&sql)
-            .bind(data.id)
-            .fetch_one(pool.0)
-            .await
-            .map_err(|_| CustomError::TokenError)?;
-        if data.ticker == user.update_time.timestamp() {
-            Ok(user)
+    ) -> Result<VoUser, CustomError> {
+        let (ticker, disabled, role) = match TICKER_CACHE.get(&data.id) {
+            Some(cached) => cached,
+            None => {
+                let (update_time, disabled, role): (DateTime<Utc>, bool, i32) =
+                    QueryBuilder::select(TABLE_USER, "update_time, disabled, role")
+                        .and_where(Cond::eq("id", data.id))
+                        .build()
+                        .query_as()
+                        .fetch_one(pool.0)
+                        .await
+                        .map_err(|_| CustomError::TokenError)?;
+                let cached = (update_time.timestamp(), disabled, role);
+                TICKER_CACHE.insert(data.id, cached);
+                cached
+            }
+        };
+        if disabled {
+            Err(CustomError::AccountDisabled)
+        } else if data.ticker == ticker && i32::from(data.role) == role {
+            Ok(data)
+        } else {
+            Err(CustomError::TokenError)
+        }
+    }
+
+    /// 使用户的所有令牌失效
+    pub async fn revoke_tokens(&self, pool: &Data<&DbPool>, id: i32) -> Result<(), CustomError> {
+        let mut tx = pool.0.begin().await?;
+        revoke_tokens(&mut tx, id).await?;
+        tx.commit().await?;
+        TICKER_CACHE.remove(&id);
+        Ok(())
+    }
+
+    pub async fn users(&self, pool: &Data<&DbPool>) -> Result<Vec<VoUserInfo>, CustomError> {
+        let users: Vec<User> = QueryBuilder::select(TABLE_USER, USER_COLUMNS)
+            .build()
+            .query_as()
+            .fetch_all(pool.0)
+            .await?;
+        Ok(users
+            .into_iter()
+            .map(|u| {
+                let locked = LOGIN_GUARD.is_locked(&account_key(&u.mail));
+                VoUserInfo { locked, ..u.into() }
+            })
+            .collect())
+    }
+
+    /// 创建用户, 未提供密码时生成邀请码
+    pub async fn add_user(
+        &self,
+        pool: &Data<&DbPool>,
+        data: VoAddUser,
+    ) -> Result<VoInvite, CustomError> {
+        let name = data.name.trim();
+        let mail = data.mail.trim();
+        if name.is_empty() {
+            return Err(CustomError::InvalidParam("name".to_string()));
+        }
+        if !mail.contains('@') {
+            return Err(CustomError::InvalidParam("mail".to_string()));
+        }
+        let (password, invite) = self.credentials(data.password.as_deref())?;
+        let id = QueryBuilder::insert(TABLE_USER)
+            .value("name", name)
+            .value("mail", mail)
+            .value("password", password)
+            .value("update_time", Utc::now())
+            .value("role", i32::from(data.role.unwrap_or(VoRole::Viewer)))
+            .value("invite_hash", invite.as_ref().map(|(_, hash)| hash.clone()))
+            .value(
+                "invite_expire",
+                invite
+                    .as_ref()
+                    .map(|_| Utc::now() + chrono::Duration::hours(INVITE_EXPIRE)),
+            )
+            .returning("id")
+            .build()
+            .fetch_returning(pool.0)
+            .await?;
+        Ok(VoInvite {
+            id,
+            invite_token: invite.map(|(token, _)| token),
+        })
+    }
+
+    pub async fn rename_user(
+        &self,
+        pool: &Data<&DbPool>,
+        data: VoRenameUser,
+    ) -> Result<(), CustomError> {
+        let name = data.name.trim();
+        if name.is_empty() {
+            return Err(CustomError::InvalidParam("name".to_string()));
+        }
+        let rows_affected = QueryBuilder::update(TABLE_USER)
+            .set("name", name)
+            .and_where(Cond::eq("id", data.id))
+            .build()
+            .query()
+            .execute(pool.0)
+            .await?
+            .rows_affected();
+        if rows_affected > 0 {
+            Ok(())
+        } else {
+            Err(CustomError::DataNotFound)
+        }
+    }
+
+    /// 管理员重置密码, 用户的所有令牌失效
+    pub async fn reset_pass(
+        &self,
+        pool: &Data<&DbPool>,
+        id: i32,
+        data: VoResetPass,
+    ) -> Result<VoInvite, CustomError> {
+        let (password, invite) = self.credentials(data.password.as_deref())?;
+        let mut tx = pool.0.begin().await?;
+        let rows_affected = QueryBuilder::update(TABLE_USER)
+            .set("password", password)
+            .set("invite_hash", invite.as_ref().map(|(_, hash)| hash.clone()))
+            .set(
+                "invite_expire",
+                invite
+                    .as_ref()
+                    .map(|_| Utc::now() + chrono::Duration::hours(INVITE_EXPIRE)),
+            )
+            .and_where(Cond::eq("id", id))
+            .build()
+            .query()
+            .execute(&mut tx)
+            .await?
+            .rows_affected();
+        if rows_affected == 0 {
+            return Err(CustomError::DataNotFound);
+        }
+        revoke_tokens(&mut tx, id).await?;
+        tx.commit().await?;
+        TICKER_CACHE.remove(&id);
+        Ok(VoInvite {
+            id,
+            invite_token: invite.map(|(token, _)| token),
+        })
+    }
+
+    /// 接受邀请并设置密码
+    /// 邀请码对应的用户, 邀请码无效或过期时返回令牌错误
+    pub async fn invited_user(
+        &self,
+        pool: &Data<&DbPool>,
+        token: &str,
+    ) -> Result<i32, CustomError> {
+        QueryBuilder::select(TABLE_USER, "id")
+            .and_where(Cond::eq("invite_hash", token_hash(token)))
+            .and_where(Cond::gt("invite_expire", Utc::now()))
+            .build()
+            .query_scalar()
+            .fetch_optional(pool.0)
+            .await?
+            .ok_or(CustomError::TokenError)
+    }
+
+    pub async fn accept_invite(
+        &self,
+        pool: &Data<&DbPool>,
+        data: VoAcceptInvite,
+    ) -> Result<(), CustomError> {
+        let (password, _) = self.credentials(Some(&data.password))?;
+        let rows_affected = QueryBuilder::update(TABLE_USER)
+            .set("password", password)
+            .set("invite_hash", None::<String>)
+            .set("invite_expire", None::<DateTime<Utc>>)
+            .and_where(Cond::eq("invite_hash", token_hash(&data.invite_token)))
+            .and_where(Cond::gt("invite_expire", Utc::now()))
+            .build()
+            .query()
+            .execute(pool.0)
+            .await?
+            .rows_affected();
+        if rows_affected > 0 {
+            Ok(())
         } else {
             Err(CustomError::TokenError)
         }
     }
+
+    /// 修改角色, 已签发的令牌需刷新后生效; 不能修改自己的角色
+    pub async fn set_role(
+        &self,
+        pool: &Data<&DbPool>,
+        operator: &VoUser,
+        id: i32,
+        role: VoRole,
+    ) -> Result<(), CustomError> {
+        if operator.id == id {
+            return Err(CustomError::InvalidState("self".to_string()));
+        }
+        let rows_affected = QueryBuilder::update(TABLE_USER)
+            .set("role", i32::from(role))
+            .and_where(Cond::eq("id", id))
+            .build()
+            .query()
+            .execute(pool.0)
+            .await?
+            .rows_affected();
+        if rows_affected == 0 {
+            return Err(CustomError::DataNotFound);
+        }
+        TICKER_CACHE.remove(&id);
+        Ok(())
+    }
+
+    /// 启用或禁用用户, 不能操作自己
+    pub async fn set_disabled(
+        &self,
+        pool: &Data<&DbPool>,
+        operator: &VoUser,
+        id: i32,
+        disabled: bool,
+    ) -> Result<(), CustomError> {
+        if operator.id == id {
+            return Err(CustomError::InvalidState("self".to_string()));
+        }
+        let rows_affected = QueryBuilder::update(TABLE_USER)
+            .set("disabled", disabled)
+            .and_where(Cond::eq("id", id))
+            .build()
+            .query()
+            .execute(pool.0)
+            .await?
+            .rows_affected();
+        if rows_affected == 0 {
+            return Err(CustomError::DataNotFound);
+        }
+        if disabled {
+            self.revoke_tokens(pool, id).await
+        } else {
+            TICKER_CACHE.remove(&id);
+            Ok(())
+        }
+    }
+
+    pub async fn delete_user(
+        &self,
+        pool: &Data<&DbPool>,
+        operator: &VoUser,
+        id: i32,
+    ) -> Result<(), CustomError> {
+        if operator.id == id {
+            return Err(CustomError::InvalidState("self".to_string()));
+        }
+        let mut tx = pool.0.begin().await?;
+        let rows_affected = QueryBuilder::delete(TABLE_USER)
+            .and_where(Cond::eq("id", id))
+            .build()
+            .query()
+            .execute(&mut tx)
+            .await?
+            .rows_affected();
+        if rows_affected == 0 {
+            return Err(CustomError::DataNotFound);
+        }
+        for table in [TABLE_REFRESH_TOKEN, TABLE_RECOVERY_CODE, TABLE_API_KEY] {
+            QueryBuilder::delete(table)
+                .and_where(Cond::eq("user_id", id))
+                .build()
+                .query()
+                .execute(&mut tx)
+                .await?;
+        }
+        tx.commit().await?;
+        TICKER_CACHE.remove(&id);
+        Ok(())
+    }
+
+    /// 密码哈希; 未提供密码时返回空密码和邀请码 `(邀请码, 哈希)`
+    #[allow(clippy::type_complexity)]
+    fn credentials(
+        &self,
+        password: Option<&str>,
+    ) -> Result<(String, Option<(String, String)>), CustomError> {
+        match password {
+            Some(password) => {
+                check_password(password, None)?;
+                bcrypt::hash(password, 10)
+                    .map(|hash| (hash, None))
+                    .map_err(|e| CustomError::Internal(e.to_string()))
+            }
+            None => {
+                let token = gen_token_id();
+                let hash = token_hash(&token);
+                Ok((String::new(), Some((token, hash))))
+            }
+        }
+    }
+}
+
+/// 按密码策略检查新密码
+fn check_password(password: &str, old_hash: Option<&str>) -> Result<(), CustomError> {
+    let rules = password_policy().check(password, old_hash);
+    if rules.is_empty() {
+        Ok(())
+    } else {
+        Err(CustomError::PasswordPolicy(rules))
+    }
+}
+
+/// 邀请码和恢复码只保存 sha256
+fn token_hash(token: &str) -> String {
+    let mut sha256 = Sha256::new();
+    sha256.input_str(token);
+    sha256.result_str()
 }
 
 pub struct DeviceHardService;
 
 const TABLE_HARD: &str = "device_type";
 const HARD_COLUMNS: &str = "id, hard_version, name, category, has_ble, has_finger, has_stm32, desc";
-const HARD_ADD_COLUMNS: &str =
-    " hard_version, name, category, has_ble, has_finger, has_stm32, desc";
 impl DeviceHardService {
     pub async fn devices(&self, pool: &Data<&DbPool>) -> Result<Vec<VoDeviceHard>, CustomError> {
-        let sql = SqlHelper::query(TABLE_HARD, HARD_COLUMNS).sql();
-        let devices: Vec<DeviceHard> = sqlx::query_as(&sql).fetch_all(pool.0).await?;
+        let devices: Vec<DeviceHard> = QueryBuilder::select(TABLE_HARD, HARD_COLUMNS)
+            .build()
+            .query_as()
+            .fetch_all(pool.0)
+            .await?;
         let mut data = Vec::<VoDeviceHard>::with_capacity(devices.len());
         for d in devices {
             data.push(d.into());
@@ -111,24 +889,20 @@ impl DeviceHardService {
         &self,
         pool: &Data<&DbPool>,
         data: VoAddHard,
-    ) -> Result<(), CustomError> {
-        let sql = SqlHelper::insert(TABLE_HARD, HARD_ADD_COLUMNS).sql();
-        let rows_affected = sqlx::query(&sql)
-            .bind(data.hard_version)
-            .bind(data.name)
-            .bind(i32::from(data.category))
-            .bind(data.has_ble)
-            .bind(data.has_finger)
-            .bind(data.has_stm32)
-            .bind(data.desc)
-            .execute(pool.0)
-            .await?
-            .rows_affected();
-        if rows_affected > 0 {
-            Ok(())
-        } else {
-            Err(CustomError::DataNotFound)
-        }
+    ) -> Result<i32, CustomError> {
+        let id = QueryBuilder::insert(TABLE_HARD)
+            .value("hard_version", data.hard_version)
+            .value("name", data.name)
+            .value("category", i32::from(data.category))
+            .value("has_ble", data.has_ble)
+            .value("has_finger", data.has_finger)
+            .value("has_stm32", data.has_stm32)
+            .value("desc", data.desc)
+            .returning("id")
+            .build()
+            .fetch_returning(pool.0)
+            .await?;
+        Ok(id)
     }
 
     pub async fn update_device(
@@ -136,18 +910,17 @@ impl DeviceHardService {
         pool: &Data<&DbPool>,
         data: VoUpdateHard,
     ) -> Result<(), CustomError> {
-        let sql = SqlHelper::update(TABLE_HARD, HARD_ADD_COLUMNS)
-            .and_where_eq(" id ")
-            .sql();
-        let rows_affected = sqlx::query(&sql)
-            .bind(data.hard_version)
-            .bind(data.name)
-            .bind(i32::from(data.category))
-            .bind(data.has_ble)
-            .bind(data.has_finger)
-            .bind(data.has_stm32)
-            .bind(data.desc)
-            .bind(data.id)
+        let rows_affected = QueryBuilder::update(TABLE_HARD)
+            .set("hard_version", data.hard_version)
+            .set("name", data.name)
+            .set("category", i32::from(data.category))
+            .set("has_ble", data.has_ble)
+            .set("has_finger", data.has_finger)
+            .set("has_stm32", data.has_stm32)
+            .set("desc", data.desc)
+            .and_where(Cond::eq("id", data.id))
+            .build()
+            .query()
             .execute(pool.0)
             .await?
             .rows_affected();
@@ -163,14 +936,14 @@ pub struct DeviceSoftService;
 
 const TABLE_SOFT: &str = "version_type";
 const SOFT_COLUMNS: &str = "id, name";
-const SOFT_ADD_COLUMNS: &str = "name";
 impl DeviceSoftService {
     pub async fn soft_versions(
         &self,
         pool: &Data<&DbPool>,
     ) -> Result<Vec<DeviceSoft>, CustomError> {
-        let sql = SqlHelper::query(TABLE_SOFT, SOFT_COLUMNS).sql();
-        sqlx::query_as(&sql)
+        QueryBuilder::select(TABLE_SOFT, SOFT_COLUMNS)
+            .build()
+            .query_as()
             .fetch_all(pool.0)
             .await
             .map_err(CustomError::from)
@@ -180,10 +953,14 @@ impl DeviceSoftService {
         &self,
         pool: &Data<&DbPool>,
         data: VoAddSoft,
-    ) -> Result<(), CustomError> {
-        let sql = SqlHelper::insert(TABLE_SOFT, SOFT_ADD_COLUMNS).sql();
-        sqlx::query(&sql).bind(data.name).execute(pool.0).await?;
-        Ok(())
+    ) -> Result<i32, CustomError> {
+        let id = QueryBuilder::insert(TABLE_SOFT)
+            .value("name", data.name)
+            .returning("id")
+            .build()
+            .fetch_returning(pool.0)
+            .await?;
+        Ok(id)
     }
 
     pub async fn update_soft_version(
@@ -191,12 +968,11 @@ impl DeviceSoftService {
         pool: &Data<&DbPool>,
         data: VoUpdateSoft,
     ) -> Result<(), CustomError> {
-        let sql = SqlHelper::update(TABLE_SOFT, SOFT_ADD_COLUMNS)
-            .and_where_eq(" id ")
-            .sql();
-        let rows_affected = sqlx::query(&sql)
-            .bind(data.name)
-            .bind(data.id)
+        let rows_affected = QueryBuilder::update(TABLE_SOFT)
+            .set("name", data.name)
+            .and_where(Cond::eq("id", data.id))
+            .build()
+            .query()
             .execute(pool.0)
             .await?
             .rows_affected();
@@ -208,85 +984,1404 @@ impl DeviceSoftService {
     }
 }
 
-pub struct FirmService;
+pub struct ChannelService;
 
-const TABLE_FIRM: &str = "firm";
-const FIRM_COLUMNS: &str = "id, hard_version, version_name, version_format, version_type, finger_level, url, desc, update_time, rely_version_type, min, max, des_en, des_ko, des_sp";
-const FIRM_ADD_COLUMNS: &str = " hard_version, version_name, version_format, version_type, finger_level, url, desc, update_time, rely_version_type, min, max, des_en, des_ko, des_sp";
-impl FirmService {
-    pub async fn firms(&self, pool: &Data<&DbPool>) -> Result<Vec<VoFirm>, CustomError> {
-        let sql = SqlHelper::query(TABLE_FIRM, FIRM_COLUMNS)
-            .order_desc("update_time")
-            .sql();
-        let data: Vec<Firm> = sqlx::query_as(&sql).fetch_all(pool.0).await?;
-        let mut firms = Vec::with_capacity(data.len());
-        for f in data {
-            firms.push(f.into())
-        }
-        Ok(firms)
+const TABLE_CHANNEL: &str = "channel";
+const CHANNEL_COLUMNS: &str = "id, name, level, desc";
+const TABLE_CHANNEL_ASSIGN: &str = "channel_assign";
+const CHANNEL_ASSIGN_COLUMNS: &str = "id, target_type, target, channel_id";
+/// 未分配渠道的设备使用 stable
+const DEFAULT_CHANNEL: i32 = 1;
+impl ChannelService {
+    pub async fn channels(&self, pool: &Data<&DbPool>) -> Result<Vec<Channel>, CustomError> {
+        QueryBuilder::select(TABLE_CHANNEL, CHANNEL_COLUMNS)
+            .build()
+            .query_as()
+            .fetch_all(pool.0)
+            .await
+            .map_err(CustomError::from)
     }
-    pub async fn firms_by_device(
+
+    pub async fn channel(&self, pool: &Data<&DbPool>, id: i32) -> Result<Channel, CustomError> {
+        QueryBuilder::select(TABLE_CHANNEL, CHANNEL_COLUMNS)
+            .and_where(Cond::eq("id", id))
+            .build()
+            .query_as()
+            .fetch_one(pool.0)
+            .await
+            .map_err(CustomError::from)
+    }
+
+    pub async fn add_channel(
+        &self,
+        pool: &Data<&DbPool>,
+        data: VoAddChannel,
+    ) -> Result<i32, CustomError> {
+        let id = QueryBuilder::insert(TABLE_CHANNEL)
+            .value("name", data.name)
+            .value("level", data.level)
+            .value("desc", data.desc)
+            .returning("id")
+            .build()
+            .fetch_returning(pool.0)
+            .await?;
+        Ok(id)
+    }
+
+    pub async fn update_channel(
+        &self,
+        pool: &Data<&DbPool>,
+        data: VoUpdateChannel,
+    ) -> Result<(), CustomError> {
+        let rows_affected = QueryBuilder::update(TABLE_CHANNEL)
+            .set("name", data.name)
+            .set("level", data.level)
+            .set("desc", data.desc)
+            .and_where(Cond::eq("id", data.id))
+            .build()
+            .query()
+            .execute(pool.0)
+            .await?
+            .rows_affected();
+        if rows_affected > 0 {
+            Ok(())
+        } else {
+            Err(CustomError::DataNotFound)
+        }
+    }
+
+    pub async fn assigns(&self, pool: &Data<&DbPool>) -> Result<Vec<VoChannelAssign>, CustomError> {
+        let data: Vec<ChannelAssign> =
+            QueryBuilder::select(TABLE_CHANNEL_ASSIGN, CHANNEL_ASSIGN_COLUMNS)
+                .build()
+                .query_as()
+                .fetch_all(pool.0)
+                .await?;
+        Ok(data.into_iter().map(VoChannelAssign::from).collect())
+    }
+
+    /// 设备或设备组已有的渠道分配
+    pub async fn find_assign(
+        &self,
+        pool: &Data<&DbPool>,
+        data: &VoSetChannelAssign,
+    ) -> Result<Option<i32>, CustomError> {
+        let id = QueryBuilder::select(TABLE_CHANNEL_ASSIGN, "id")
+            .and_where(Cond::eq("target_type", i32::from(data.target_type)))
+            .and_where(Cond::eq("target", data.target.trim()))
+            .build()
+            .query_scalar()
+            .fetch_optional(pool.0)
+            .await?;
+        Ok(id)
+    }
+
+    /// 分配渠道, 已分配时覆盖, 返回分配的 id
+    pub async fn set_assign(
+        &self,
+        pool: &Data<&DbPool>,
+        data: VoSetChannelAssign,
+    ) -> Result<i32, CustomError> {
+        if data.target.trim().is_empty() {
+            return Err(CustomError::InvalidParam("target".to_string()));
+        }
+        self.channel(pool, data.channel_id).await?;
+        let id = QueryBuilder::insert(TABLE_CHANNEL_ASSIGN)
+            .value("target_type", i32::from(data.target_type))
+            .value("target", data.target.trim())
+            .value("channel_id", data.channel_id)
+            .on_conflict("target_type, target", &["channel_id"])
+            .returning("id")
+            .build()
+            .fetch_returning(pool.0)
+            .await?;
+        Ok(id)
+    }
+
+    pub async fn delete_assign(&self, pool: &Data<&DbPool>, id: i32) -> Result<(), CustomError> {
+        let rows_affected = QueryBuilder::delete(TABLE_CHANNEL_ASSIGN)
+            .and_where(Cond::eq("id", id))
+            .build()
+            .query()
+            .execute(pool.0)
+            .await?
+            .rows_affected();
+        if rows_affected > 0 {
+            Ok(())
+        } else {
+            Err(CustomError::DataNotFound)
+        }
+    }
+
+    /// 设备所在渠道, 设备的分配优先于设备组
+    async fn resolve(
+        &self,
+        pool: &Data<&DbPool>,
+        device_id: Option<&str>,
+        group: Option<&str>,
+    ) -> Result<i32, CustomError> {
+        let targets = [
+            (VoAssignTarget::Device, device_id),
+            (VoAssignTarget::Group, group),
+        ];
+        for (target_type, target) in targets {
+            let target = match target {
+                Some(t) if !t.is_empty() => t,
+                _ => continue,
+            };
+            let channel: Option<i32> = QueryBuilder::select(TABLE_CHANNEL_ASSIGN, "channel_id")
+                .and_where(Cond::eq("target_type", i32::from(target_type)))
+                .and_where(Cond::eq("target", target))
+                .build()
+                .query_scalar()
+                .fetch_optional(pool.0)
+                .await?;
+            if let Some(channel) = channel {
+                return Ok(channel);
+            }
+        }
+        Ok(DEFAULT_CHANNEL)
+    }
+
+    /// 设备可收到的渠道: level 不高于设备所在渠道
+    pub async fn visible_channels(
+        &self,
+        pool: &Data<&DbPool>,
+        device_id: Option<&str>,
+        group: Option<&str>,
+    ) -> Result<Vec<i32>, CustomError> {
+        let channel = self.resolve(pool, device_id, group).await?;
+        QueryBuilder::select(TABLE_CHANNEL, "id")
+            .and_where(Cond::raw(
+                "level <= (SELECT level FROM channel WHERE id = ?)",
+                vec![channel.into()],
+            ))
+            .build()
+            .query_scalar()
+            .fetch_all(pool.0)
+            .await
+            .map_err(CustomError::from)
+    }
+}
+
+pub struct DeviceService;
+
+const TABLE_DEVICE: &str = "device";
+const DEVICE_COLUMNS: &str =
+    "id, serial, mac, device_type, group_name, firmware, check_in_time, desc, update_time";
+impl DeviceService {
+    pub async fn devices(
+        &self,
+        pool: &Data<&DbPool>,
+        device_type: Option<i32>,
+        group: Option<&str>,
+    ) -> Result<Vec<VoDevice>, CustomError> {
+        let mut query = QueryBuilder::select(TABLE_DEVICE, DEVICE_COLUMNS).order_asc("serial");
+        if let Some(device_type) = device_type {
+            query = query.and_where(Cond::eq("device_type", device_type));
+        }
+        if let Some(group) = group {
+            query = query.and_where(Cond::eq("group_name", group));
+        }
+        let data: Vec<Device> = query.build().query_as().fetch_all(pool.0).await?;
+        Ok(data.into_iter().map(VoDevice::from).collect())
+    }
+
+    pub async fn add_device(
+        &self,
+        pool: &Data<&DbPool>,
+        data: VoAddDevice,
+    ) -> Result<i32, CustomError> {
+        let serial = normalize_serial(&data.serial)?;
+        self.check_type(pool, data.device_type).await?;
+        let id = QueryBuilder::insert(TABLE_DEVICE)
+            .value("serial", serial)
+            .value("mac", data.mac.as_deref().and_then(normalize_mac))
+            .value("device_type", data.device_type)
+            .value("group_name", data.group_name.as_deref().and_then(non_empty))
+            .value("desc", data.desc)
+            .value("update_time", Utc::now())
+            .returning("id")
+            .build()
+            .fetch_returning(pool.0)
+            .await?;
+        Ok(id)
+    }
+
+    pub async fn update_device(
+        &self,
+        pool: &Data<&DbPool>,
+        data: VoUpdateDevice,
+    ) -> Result<(), CustomError> {
+        let serial = normalize_serial(&data.serial)?;
+        self.check_type(pool, data.device_type).await?;
+        let rows_affected = QueryBuilder::update(TABLE_DEVICE)
+            .set("serial", serial)
+            .set("mac", data.mac.as_deref().and_then(normalize_mac))
+            .set("device_type", data.device_type)
+            .set("group_name", data.group_name.as_deref().and_then(non_empty))
+            .set("desc", data.desc)
+            .set("update_time", Utc::now())
+            .and_where(Cond::eq("id", data.id))
+            .build()
+            .query()
+            .execute(pool.0)
+            .await?
+            .rows_affected();
+        if rows_affected > 0 {
+            Ok(())
+        } else {
+            Err(CustomError::DataNotFound)
+        }
+    }
+
+    pub async fn delete_device(&self, pool: &Data<&DbPool>, id: i32) -> Result<(), CustomError> {
+        let rows_affected = QueryBuilder::delete(TABLE_DEVICE)
+            .and_where(Cond::eq("id", id))
+            .build()
+            .query()
+            .execute(pool.0)
+            .await?
+            .rows_affected();
+        if rows_affected > 0 {
+            Ok(())
+        } else {
+            Err(CustomError::DataNotFound)
+        }
+    }
+
+    async fn check_type(&self, pool: &Data<&DbPool>, device_type: i32) -> Result<(), CustomError> {
+        let found: Option<i32> = QueryBuilder::select(TABLE_HARD, "id")
+            .and_where(Cond::eq("id", device_type))
+            .build()
+            .query_scalar()
+            .fetch_optional(pool.0)
+            .await?;
+        found
+            .map(|_| ())
+            .ok_or_else(|| CustomError::InvalidParam("device_type".to_string()))
+    }
+
+    /// 批量导入 CSV, 已登记的序列号会被更新; 任意一行有误时整体不导入
+    pub async fn import(
+        &self,
+        pool: &Data<&DbPool>,
+        csv: &str,
+    ) -> Result<VoImportDevices, CustomError> {
+        let types: Vec<(i32, String)> = QueryBuilder::select(TABLE_HARD, "id, hard_version")
+            .build()
+            .query_as()
+            .fetch_all(pool.0)
+            .await?;
+        let types: HashMap<String, i32> = types.into_iter().map(|(id, h)| (h, id)).collect();
+        let (rows, mut errors) = parse_device_csv(csv);
+        for row in &rows {
+            if !types.contains_key(&row.hard_version) {
+                errors.push(format!(
+                    "line {}: unknown hard_version {}",
+                    row.line, row.hard_version
+                ));
+            }
+        }
+        if !errors.is_empty() {
+            return Ok(VoImportDevices {
+                imported: 0,
+                errors,
+            });
+        }
+
+        let now = Utc::now();
+        let mut tx = pool.0.begin().await?;
+        for row in &rows {
+            QueryBuilder::insert(TABLE_DEVICE)
+                .value("serial", &row.serial)
+                .value("mac", row.mac.as_ref())
+                .value("device_type", types[&row.hard_version])
+                .value("group_name", row.group_name.as_ref())
+                .value("desc", "")
+                .value("update_time", now)
+                .on_conflict(
+                    "serial",
+                    &["mac", "device_type", "group_name", "update_time"],
+                )
+                .build()
+                .query()
+                .execute(&mut tx)
+                .await
+                .map_err(|e| match CustomError::from(e) {
+                    CustomError::InvalidState(msg) => {
+                        CustomError::InvalidState(format!("line {}: {}", row.line, msg))
+                    }
+                    e => e,
+                })?;
+        }
+        tx.commit().await?;
+        Ok(VoImportDevices {
+            imported: rows.len() as i32,
+            errors,
+        })
+    }
+
+    /// 按序列号或 MAC 查找登记的设备
+    pub async fn find(
+        &self,
+        pool: &Data<&DbPool>,
+        device_id: &str,
+    ) -> Result<Option<Device>, CustomError> {
+        let device = QueryBuilder::select(TABLE_DEVICE, DEVICE_COLUMNS)
+            .and_where(Cond::or([
+                Cond::eq("serial", device_id),
+                Cond::eq("mac", normalize_mac(device_id)),
+            ]))
+            .build()
+            .query_as()
+            .fetch_optional(pool.0)
+            .await?;
+        Ok(device)
+    }
+
+    /// 登记设备当前运行的版本, 未登记的设备返回 `None`
+    pub async fn check_in(
+        &self,
+        pool: &Data<&DbPool>,
+        device_id: &str,
+        version_type: i32,
+        current: &str,
+    ) -> Result<Option<Device>, CustomError> {
+        let mut device = match self.find(pool, device_id).await? {
+            Some(d) => d,
+            None => return Ok(None),
+        };
+        let mut firmware: Vec<VoRunning> =
+            serde_json::from_str(&device.firmware).unwrap_or_default();
+        firmware.retain(|r| r.version_type != version_type);
+        firmware.push(VoRunning {
+            version_type,
+            version: current.to_string(),
+        });
+        firmware.sort_by_key(|r| r.version_type);
+        device.firmware =
+            serde_json::to_string(&firmware).map_err(|e| CustomError::Internal(e.to_string()))?;
+        device.check_in_time = Some(Utc::now());
+        QueryBuilder::update(TABLE_DEVICE)
+            .set("firmware", &device.firmware)
+            .set("check_in_time", device.check_in_time)
+            .and_where(Cond::eq("id", device.id))
+            .build()
+            .query()
+            .execute(pool.0)
+            .await?;
+        Ok(Some(device))
+    }
+}
+
+/// CSV 中的一行: `serial,mac,hard_version,group_name`, 首行为表头时跳过
+#[derive(Debug, PartialEq)]
+struct CsvDevice {
+    line: usize,
+    serial: String,
+    mac: Option<String>,
+    hard_version: String,
+    group_name: Option<String>,
+}
+
+fn parse_device_csv(csv: &str) -> (Vec<CsvDevice>, Vec<String>) {
+    let mut rows: Vec<CsvDevice> = Vec::new();
+    let mut errors = Vec::new();
+    for (i, line) in csv.lines().enumerate() {
+        let line_no = i + 1;
+        let line = line.trim();
+        if line.is_empty() || (i == 0 && line.to_ascii_lowercase().starts_with("serial")) {
+            continue;
+        }
+        let cols: Vec<&str> = line.split(',').map(str::trim).collect();
+        if cols.len() < 3 || cols.len() > 4 {
+            errors.push(format!("line {}: expected 3 or 4 columns", line_no));
+            continue;
+        }
+        let serial = match normalize_serial(cols[0]) {
+            Ok(s) => s,
+            Err(_) => {
+                errors.push(format!("line {}: empty serial", line_no));
+                continue;
+            }
+        };
+        if rows.iter().any(|r| r.serial == serial) {
+            errors.push(format!("line {}: duplicate serial {}", line_no, serial));
+            continue;
+        }
+        rows.push(CsvDevice {
+            line: line_no,
+            serial,
+            mac: normalize_mac(cols[1]),
+            hard_version: cols[2].to_string(),
+            group_name: cols.get(3).copied().and_then(non_empty),
+        });
+    }
+    (rows, errors)
+}
+
+fn normalize_serial(serial: &str) -> Result<String, CustomError> {
+    non_empty(serial).ok_or_else(|| CustomError::InvalidParam("serial".to_string()))
+}
+
+/// MAC 统一为大写, 以 `:` 分隔
+fn normalize_mac(mac: &str) -> Option<String> {
+    let hex: String = mac
+        .chars()
+        .filter(|c| c.is_ascii_hexdigit())
+        .map(|c| c.to_ascii_uppercase())
+        .collect();
+    if hex.len() != 12 {
+        return non_empty(mac);
+    }
+    let parts: Vec<&str> = (0..12).step_by(2).map(|i| &hex[i..i + 2]).collect();
+    Some(parts.join(":"))
+}
+
+fn non_empty(s: &str) -> Option<String> {
+    let s = s.trim();
+    (!s.is_empty()).then(|| s.to_string())
+}
+
+pub struct FirmService;
+
+const TABLE_FIRM: &str = "firm";
+const FIRM_COLUMNS: &str = "id, hard_version, version_name, version_format, version_type, finger_level, url, desc, update_time, rely_version_type, min, max, size, content_type, blob_key, sha256, crc32, md5, checksum_state, verify_time, signature, signature_key_id, status, status_time, rollout_percent, rollout_allowlist, rollout_start, rollout_paused, channel_id";
+const TABLE_FIRM_FTS: &str = "firm_fts";
+/// 全文索引的字段, 与 firm_fts 的列顺序一致
+const FIRM_FTS_COLUMNS: [&str; 3] = ["version_name", "desc", "notes"];
+const TABLE_FIRM_NOTE: &str = "firm_note";
+const TABLE_BLOB: &str = "blob";
+const BLOB_COLUMNS: &str = "key, file_name, size, content_type, sha256, crc32, md5, update_time";
+const TABLE_INSTALL_REPORT: &str = "install_report";
+const INSTALL_REPORT_COLUMNS: &str =
+    "id, firm_id, device_id, event, error_code, running_version, report_time";
+static BLOB_SEQ: AtomicU32 = AtomicU32::new(0);
+const FIRM_PAGE_SIZE: i32 = 20;
+impl FirmService {
+    pub async fn firms(
+        &self,
+        pool: &Data<&DbPool>,
+        channel: Option<&str>,
+    ) -> Result<Vec<VoFirm>, CustomError> {
+        let mut query = QueryBuilder::select(TABLE_FIRM, FIRM_COLUMNS);
+        if let Some(channel) = channel {
+            query = query.and_where(in_channel(channel));
+        }
+        let mut data: Vec<Firm> = query.build().query_as().fetch_all(pool.0).await?;
+        sort_by_version(&mut data);
+        self.with_details(pool, data).await
+    }
+
+    /// 补齐迁移前已有固件的 `version_key`
+    pub async fn fill_version_keys(&self, pool: &Data<&DbPool>) -> Result<u64, CustomError> {
+        let rows: Vec<(i32, String, String)> =
+            QueryBuilder::select(TABLE_FIRM, "id, version_format, version_name")
+                .and_where(Cond::is_null("version_key"))
+                .build()
+                .query_as()
+                .fetch_all(pool.0)
+                .await?;
+        let mut tx = pool.0.begin().await?;
+        for (id, version_format, version_name) in &rows {
+            QueryBuilder::update(TABLE_FIRM)
+                .set("version_key", version_key(version_format, version_name))
+                .and_where(Cond::eq("id", *id))
+                .build()
+                .query()
+                .execute(&mut tx)
+                .await?;
+        }
+        tx.commit().await?;
+        Ok(rows.len() as u64)
+    }
+
+    /// 分页查询固件, 默认按版本号从新到旧
+    pub async fn firm_page(
+        &self,
+        pool: &Data<&DbPool>,
+        query: VoFirmQuery,
+    ) -> Result<VoFirmPage, CustomError> {
+        let page_size = query.page_size.unwrap_or(FIRM_PAGE_SIZE).clamp(1, 100);
+        let mut select = QueryBuilder::select(TABLE_FIRM, FIRM_COLUMNS);
+        if let Some(channel) = query.channel.as_deref() {
+            select = select.and_where(in_channel(channel));
+        }
+        if let Some(hard_version) = query.hard_version {
+            select = select.and_where(Cond::eq("hard_version", hard_version));
+        }
+        if let Some(version_type) = query.version_type {
+            select = select.and_where(Cond::eq("version_type", version_type));
+        }
+        if let Some(finger_level) = query.finger_level {
+            select = select.and_where(Cond::eq("finger_level", finger_level));
+        }
+        if let Some(from) = query.from {
+            select = select.and_where(Cond::ge("update_time", timestamp(from, "from")?));
+        }
+        if let Some(to) = query.to {
+            select = select.and_where(Cond::le("update_time", timestamp(to, "to")?));
+        }
+        if let Some(q) = query.q.as_deref().map(str::trim).filter(|q| !q.is_empty()) {
+            let notes = QueryBuilder::select(TABLE_FIRM_NOTE, "firm_id")
+                .and_where(Cond::contains("text", q));
+            select = select.and_where(Cond::or([
+                Cond::contains("version_name", q),
+                Cond::contains("desc", q),
+                Cond::in_query("id", notes),
+            ]));
+        }
+        let total: i64 = select
+            .to_count()
+            .build()
+            .query_scalar()
+            .fetch_one(pool.0)
+            .await?;
+        let (column, desc) = match query.sort.unwrap_or_default() {
+            VoFirmSort::Version => ("version_key", true),
+            VoFirmSort::UpdateTime => ("update_time", false),
+            VoFirmSort::UpdateTimeDesc => ("update_time", true),
+            VoFirmSort::VersionName => ("version_name", false),
+            VoFirmSort::VersionNameDesc => ("version_name", true),
+            VoFirmSort::Id => ("id", false),
+            VoFirmSort::IdDesc => ("id", true),
+        };
+        let (offset, select) = match query.cursor.as_deref() {
+            Some(cursor) => {
+                let (id, value) = parse_firm_cursor(column, cursor)
+                    .ok_or_else(|| CustomError::InvalidParam("cursor".to_string()))?;
+                let (after, upto) = if desc { ("<", ">=") } else { (">", "<=") };
+                let keyset = |op: &str| {
+                    Cond::raw(
+                        format!("({}, id) {} (?, ?)", column, op),
+                        vec![value.clone(), id.into()],
+                    )
+                };
+                // 游标及之前的行数即当前偏移, 只用于计算页码
+                let offset: i64 = select
+                    .clone()
+                    .and_where(keyset(upto))
+                    .to_count()
+                    .build()
+                    .query_scalar()
+                    .fetch_one(pool.0)
+                    .await?;
+                (offset, select.and_where(keyset(after)))
+            }
+            None => {
+                let offset = (query.page.unwrap_or(1).max(1) - 1) as i64 * page_size as i64;
+                (offset, select.offset(offset))
+            }
+        };
+        // id 保证顺序稳定
+        let select = if desc {
+            select.order_desc(column).order_desc("id")
+        } else {
+            select.order_asc(column).order_asc("id")
+        };
+        let data: Vec<Firm> = select
+            .limit(page_size as i64)
+            .build()
+            .query_as()
+            .fetch_all(pool.0)
+            .await?;
+        let next = offset + data.len() as i64;
+        let next_cursor = match data.last() {
+            Some(last) if next < total => Some(firm_cursor(column, last)),
+            _ => None,
+        };
+        Ok(VoFirmPage {
+            total,
+            page: (offset / page_size as i64) as i32 + 1,
+            page_size,
+            next_cursor,
+            items: self.with_details(pool, data).await?,
+        })
+    }
+
+    /// 附加安装统计和多语言说明
+    async fn with_details(
+        &self,
+        pool: &Data<&DbPool>,
+        data: Vec<Firm>,
+    ) -> Result<Vec<VoFirm>, CustomError> {
+        let ids: Vec<i32> = data.iter().map(|f| f.id).collect();
+        let mut stats = self.install_stats(pool, &ids).await?;
+        let mut notes = self.notes(pool, &ids).await?;
+        Ok(data
+            .into_iter()
+            .map(|f| {
+                let mut firm = VoFirm::from(f);
+                firm.installs = stats.remove(&firm.id).unwrap_or_default();
+                firm.notes = notes.remove(&firm.id).unwrap_or_default();
+                firm
+            })
+            .collect())
+    }
+
+    /// 按语言标签排序的多语言说明
+    async fn notes(
+        &self,
+        pool: &Data<&DbPool>,
+        ids: &[i32],
+    ) -> Result<HashMap<i32, Vec<VoFirmNote>>, CustomError> {
+        let rows: Vec<FirmNote> = QueryBuilder::select(TABLE_FIRM_NOTE, "firm_id, locale, text")
+            .and_where(Cond::is_in("firm_id", ids.iter().copied()))
+            .order_asc("firm_id")
+            .order_asc("locale")
+            .build()
+            .query_as()
+            .fetch_all(pool.0)
+            .await?;
+        let mut notes: HashMap<i32, Vec<VoFirmNote>> = HashMap::new();
+        for row in rows {
+            notes
+                .entry(row.firm_id)
+                .or_default()
+                .push(VoFirmNote::from(row));
+        }
+        Ok(notes)
+    }
+
+    /// 全文搜索固件版本名和各语言说明, 按相关度排序
+    ///
+    /// 索引使用 trigram 分词, 能匹配中文和韩文的任意子串;
+    /// 不足三个字的词无法走索引, 改用 LIKE 匹配
+    pub async fn search(
+        &self,
+        pool: &Data<&DbPool>,
+        q: &str,
+        hard_version: Option<i32>,
+        limit: Option<i32>,
+    ) -> Result<Vec<VoSearchHit>, CustomError> {
+        let terms: Vec<&str> = q.split_whitespace().collect();
+        if terms.is_empty() {
+            return Err(CustomError::InvalidParam("q".to_string()));
+        }
+        let (long, short): (Vec<&str>, Vec<&str>) =
+            terms.iter().partition(|t| t.chars().count() >= 3);
+        let mut select = QueryBuilder::select(TABLE_FIRM_FTS, "rowid");
+        if long.is_empty() {
+            select = select.order_desc("rowid");
+        } else {
+            let matcher = long
+                .iter()
+                .map(|t| format!("\"{}\"", t.replace('"', "\"\"")))
+                .collect::<Vec<_>>()
+                .join(" ");
+            select = select
+                .and_where(Cond::raw(
+                    format!("{} MATCH ?", TABLE_FIRM_FTS),
+                    vec![matcher.into()],
+                ))
+                .order_asc("rank");
+        }
+        for term in &short {
+            select = select.and_where(Cond::or(FIRM_FTS_COLUMNS.map(|c| Cond::contains(c, term))));
+        }
+        if let Some(hard_version) = hard_version {
+            let firms = QueryBuilder::select(TABLE_FIRM, "id")
+                .and_where(Cond::eq("hard_version", hard_version));
+            select = select.and_where(Cond::in_query("rowid", firms));
+        }
+        let ids: Vec<i32> = select
+            .limit(limit.unwrap_or(FIRM_PAGE_SIZE).clamp(1, 100) as i64)
+            .build()
+            .query_scalar()
+            .fetch_all(pool.0)
+            .await?;
+
+        let firms: Vec<Firm> = QueryBuilder::select(TABLE_FIRM, FIRM_COLUMNS)
+            .and_where(Cond::is_in("id", ids.iter().copied()))
+            .build()
+            .query_as()
+            .fetch_all(pool.0)
+            .await?;
+        let mut firms: HashMap<i32, VoFirm> = self
+            .with_details(pool, firms)
+            .await?
+            .into_iter()
+            .map(|f| (f.id, f))
+            .collect();
+        let snippet = |field: &str, locale: Option<&str>, text: &str| {
+            Some(VoSnippet {
+                field: field.to_string(),
+                locale: locale.map(str::to_string),
+                text: highlight(text, &terms)?,
+            })
+        };
+        Ok(ids
+            .into_iter()
+            .filter_map(|id| {
+                let firm = firms.remove(&id)?;
+                let snippets = [
+                    snippet("version_name", None, &firm.version_name),
+                    snippet("desc", None, &firm.desc),
+                ]
+                .into_iter()
+                .chain(
+                    firm.notes
+                        .iter()
+                        .map(|n| snippet("note", Some(&n.locale), &n.text)),
+                )
+                .flatten()
+                .collect();
+                Some(VoSearchHit { firm, snippets })
+            })
+            .collect())
+    }
+
+    pub async fn firms_by_device(
+        &self,
+        pool: &Data<&DbPool>,
+        hard_version: i32,
+        channel: Option<&str>,
+    ) -> Result<Vec<VoFirm>, CustomError> {
+        let mut query = QueryBuilder::select(TABLE_FIRM, FIRM_COLUMNS)
+            .and_where(Cond::eq("hard_version", hard_version));
+        if let Some(channel) = channel {
+            query = query.and_where(in_channel(channel));
+        }
+        let mut data: Vec<Firm> = query.build().query_as().fetch_all(pool.0).await?;
+        sort_by_version(&mut data);
+        self.with_details(pool, data).await
+    }
+
+    /// 为设备挑选可升级的最新固件
+    pub async fn check_update(
+        &self,
+        pool: &Data<&DbPool>,
+        data: VoCheckUpdate,
+    ) -> Result<VoUpdateCheck, CustomError> {
+        let current = Version::parse_device(&data.current)
+            .ok_or_else(|| CustomError::InvalidParam("current".to_string()))?;
+        let device = match data.device_id.as_deref() {
+            Some(id) => SYS_DEVICE_SERVICE.find(pool, id).await?,
+            None => None,
+        };
+        let mut installed = match (data.installed.as_deref(), &device) {
+            (Some(installed), _) => parse_installed(installed)?,
+            // 未上报时使用登记的各模块版本
+            (None, Some(device)) => running_versions(&device.firmware),
+            (None, None) => HashMap::new(),
+        };
+        installed.insert(data.version_type, current.clone());
+        // 登记的设备组优先于设备上报的
+        let group = device
+            .and_then(|d| d.group_name)
+            .or_else(|| data.group.clone());
+
+        let hard = QueryBuilder::select(TABLE_HARD, "id")
+            .and_where(Cond::eq("hard_version", &data.hard_version));
+        let firms: Vec<Firm> = QueryBuilder::select(TABLE_FIRM, FIRM_COLUMNS)
+            .and_where(Cond::in_query("hard_version", hard))
+            .and_where(Cond::eq("version_type", data.version_type))
+            .and_where(Cond::eq("finger_level", data.finger_level))
+            .build()
+            .query_as()
+            .fetch_all(pool.0)
+            .await?;
+        let channels = SYS_CHANNEL_SERVICE
+            .visible_channels(pool, data.device_id.as_deref(), group.as_deref())
+            .await?;
+
+        let now = Utc::now();
+        // 当前版本被撤回时, 允许回退到更低的已发布版本
+        let revoked = firms.iter().any(|f| {
+            VoFirmStatus::from(f.status) == VoFirmStatus::Revoked
+                && firm_version(f).is_some_and(|v| v == current)
+        });
+        let mut firms: Vec<Firm> = firms
+            .into_iter()
+            .filter(|f| VoFirmStatus::from(f.status) == VoFirmStatus::Published)
+            // 文件与登记的摘要不一致或已无法获取时不下发
+            .filter(|f| {
+                !matches!(
+                    VoChecksumState::from(f.checksum_state),
+                    VoChecksumState::Mismatch | VoChecksumState::Missing
+                )
+            })
+            .filter(|f| channels.contains(&f.channel_id))
+            .filter(|f| firm_version(f).is_some_and(|v| v > current || (revoked && v != current)))
+            .filter(|f| rely_satisfied(f, &installed))
+            .filter(|f| rollout_eligible(f, data.device_id.as_deref(), now))
+            .collect();
+        sort_by_version(&mut firms);
+        let firm = match firms.into_iter().next() {
+            Some(f) => {
+                let mut firm = VoFirm::from(f);
+                let mut notes = self.notes(pool, &[firm.id]).await?;
+                firm.notes = notes.remove(&firm.id).unwrap_or_default();
+                firm
+            }
+            None => {
+                return Ok(VoUpdateCheck {
+                    has_update: false,
+                    revoked,
+                    firm: None,
+                    manifest: None,
+                })
+            }
+        };
+        let manifest = sign_manifest(&data.hard_version, &firm, revoked)?;
+        Ok(VoUpdateCheck {
+            has_update: true,
+            revoked,
+            firm: Some(firm),
+            manifest,
+        })
+    }
+
+    /// 变更固件发布状态
+    pub async fn transition(
+        &self,
+        pool: &Data<&DbPool>,
+        id: i32,
+        to: VoFirmStatus,
+    ) -> Result<(), CustomError> {
+        let status: i32 = QueryBuilder::select(TABLE_FIRM, "status")
+            .and_where(Cond::eq("id", id))
+            .build()
+            .query_scalar()
+            .fetch_one(pool.0)
+            .await?;
+        let from = VoFirmStatus::from(status);
+        if !from.can_transition_to(to) {
+            return Err(CustomError::InvalidState(format!("{:?} -> {:?}", from, to)));
+        }
+        let rows_affected = QueryBuilder::update(TABLE_FIRM)
+            .set("status", i32::from(to))
+            .set("status_time", Utc::now())
+            .and_where(Cond::eq("id", id))
+            .and_where(Cond::eq("status", status))
+            .build()
+            .query()
+            .execute(pool.0)
+            .await?
+            .rows_affected();
+        if rows_affected > 0 {
+            Ok(())
+        } else {
+            Err(CustomError::InvalidState(format!("{:?} -> {:?}", from, to)))
+        }
+    }
+
+    /// 设置分阶段发布策略
+    pub async fn set_rollout(
+        &self,
+        pool: &Data<&DbPool>,
+        id: i32,
+        data: VoUpdateRollout,
+    ) -> Result<(), CustomError> {
+        let allowlist = serde_json::to_string(&data.allowlist)
+            .map_err(|e| CustomError::Internal(e.to_string()))?;
+        let start = match data.start_time {
+            Some(t) => Some(timestamp(t, "start_time")?),
+            None => None,
+        };
+        let rows_affected = QueryBuilder::update(TABLE_FIRM)
+            .set("rollout_percent", data.percent)
+            .set("rollout_allowlist", allowlist)
+            .set("rollout_start", start)
+            .and_where(Cond::eq("id", id))
+            .build()
+            .query()
+            .execute(pool.0)
+            .await?
+            .rows_affected();
+        if rows_affected > 0 {
+            Ok(())
+        } else {
+            Err(CustomError::DataNotFound)
+        }
+    }
+
+    /// 暂停或恢复发布
+    pub async fn pause_rollout(
+        &self,
+        pool: &Data<&DbPool>,
+        id: i32,
+        paused: bool,
+    ) -> Result<(), CustomError> {
+        let rows_affected = QueryBuilder::update(TABLE_FIRM)
+            .set("rollout_paused", paused)
+            .and_where(Cond::eq("id", id))
+            .build()
+            .query()
+            .execute(pool.0)
+            .await?
+            .rows_affected();
+        if rows_affected > 0 {
+            Ok(())
+        } else {
+            Err(CustomError::DataNotFound)
+        }
+    }
+
+    /// 扩大 (`advance`) 或缩小发布比例
+    pub async fn step_rollout(
+        &self,
+        pool: &Data<&DbPool>,
+        id: i32,
+        percent: i32,
+        advance: bool,
+    ) -> Result<(), CustomError> {
+        let current: i32 = QueryBuilder::select(TABLE_FIRM, "rollout_percent")
+            .and_where(Cond::eq("id", id))
+            .build()
+            .query_scalar()
+            .fetch_one(pool.0)
+            .await?;
+        if (advance && percent <= current) || (!advance && percent >= current) {
+            return Err(CustomError::InvalidState(format!(
+                "rollout {} -> {}",
+                current, percent
+            )));
+        }
+        let rows_affected = QueryBuilder::update(TABLE_FIRM)
+            .set("rollout_percent", percent)
+            .and_where(Cond::eq("id", id))
+            .and_where(Cond::eq("rollout_percent", current))
+            .build()
+            .query()
+            .execute(pool.0)
+            .await?
+            .rows_affected();
+        if rows_affected > 0 {
+            Ok(())
+        } else {
+            // 并发调整时比例已被修改
+            Err(CustomError::InvalidState(format!(
+                "rollout {} -> {}",
+                current, percent
+            )))
+        }
+    }
+
+    /// 记录设备上报的安装进度, 安装成功时同步登记设备的当前版本
+    pub async fn report(
+        &self,
+        pool: &Data<&DbPool>,
+        data: VoAddInstallReport,
+    ) -> Result<(), CustomError> {
+        let device_id = data.device_id.trim();
+        if device_id.is_empty() {
+            return Err(CustomError::InvalidParam("device_id".to_string()));
+        }
+        if data.event == VoInstallEvent::InstallFailed && data.error_code.is_none() {
+            return Err(CustomError::InvalidParam("error_code".to_string()));
+        }
+        let version_type: i32 = QueryBuilder::select(TABLE_FIRM, "version_type")
+            .and_where(Cond::eq("id", data.firm_id))
+            .build()
+            .query_scalar()
+            .fetch_one(pool.0)
+            .await?;
+        QueryBuilder::insert(TABLE_INSTALL_REPORT)
+            .value("firm_id", data.firm_id)
+            .value("device_id", device_id)
+            .value("event", i32::from(data.event))
+            .value("error_code", data.error_code)
+            .value("running_version", data.running_version.as_ref())
+            .value("report_time", Utc::now())
+            .build()
+            .query()
+            .execute(pool.0)
+            .await?;
+        let running = data.running_version.as_deref();
+        if let (VoInstallEvent::InstallSucceeded, Some(running)) = (data.event, running) {
+            SYS_DEVICE_SERVICE
+                .check_in(pool, device_id, version_type, running)
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// 固件的安装上报, 最新的在前
+    pub async fn reports(
+        &self,
+        pool: &Data<&DbPool>,
+        id: i32,
+    ) -> Result<Vec<VoInstallReport>, CustomError> {
+        let data: Vec<InstallReport> =
+            QueryBuilder::select(TABLE_INSTALL_REPORT, INSTALL_REPORT_COLUMNS)
+                .and_where(Cond::eq("firm_id", id))
+                .order_desc("id")
+                .build()
+                .query_as()
+                .fetch_all(pool.0)
+                .await?;
+        Ok(data.into_iter().map(VoInstallReport::from).collect())
+    }
+
+    /// 指定固件的安装统计
+    async fn install_stats(
+        &self,
+        pool: &Data<&DbPool>,
+        ids: &[i32],
+    ) -> Result<HashMap<i32, VoInstallStats>, CustomError> {
+        let downloads: Vec<(i32, i32)> =
+            QueryBuilder::select(TABLE_INSTALL_REPORT, "firm_id, COUNT(DISTINCT device_id)")
+                .and_where(Cond::eq("event", 1))
+                .and_where(Cond::is_in("firm_id", ids.iter().copied()))
+                .group_by("firm_id")
+                .build()
+                .query_as()
+                .fetch_all(pool.0)
+                .await?;
+        // 每台设备只计最后一次安装结果
+        let outcomes: Vec<(i32, i32, i32)> = QueryBuilder::select(
+            "install_report r",
+            "r.firm_id, SUM(r.event = 2), SUM(r.event = 3)",
+        )
+        .and_where(Cond::is_in("r.event", [2, 3]))
+        .and_where(Cond::is_in("r.firm_id", ids.iter().copied()))
+        .and_where(Cond::raw(
+            "r.id = (SELECT MAX(id) FROM install_report \
+             WHERE firm_id = r.firm_id AND device_id = r.device_id AND event IN (2, 3))",
+            vec![],
+        ))
+        .group_by("r.firm_id")
+        .build()
+        .query_as()
+        .fetch_all(pool.0)
+        .await?;
+        let mut counts: HashMap<i32, (i32, i32, i32)> = HashMap::new();
+        for (id, n) in downloads {
+            counts.entry(id).or_default().0 = n;
+        }
+        for (id, ok, failed) in outcomes {
+            let c = counts.entry(id).or_default();
+            c.1 = ok;
+            c.2 = failed;
+        }
+        Ok(counts
+            .into_iter()
+            .map(|(id, (d, ok, failed))| (id, VoInstallStats::new(d, ok, failed)))
+            .collect())
+    }
+
+    /// 修改固件所属渠道
+    pub async fn set_channel(
+        &self,
+        pool: &Data<&DbPool>,
+        id: i32,
+        channel_id: i32,
+    ) -> Result<(), CustomError> {
+        SYS_CHANNEL_SERVICE.channel(pool, channel_id).await?;
+        let rows_affected = QueryBuilder::update(TABLE_FIRM)
+            .set("channel_id", channel_id)
+            .and_where(Cond::eq("id", id))
+            .build()
+            .query()
+            .execute(pool.0)
+            .await?
+            .rows_affected();
+        if rows_affected > 0 {
+            Ok(())
+        } else {
+            Err(CustomError::DataNotFound)
+        }
+    }
+
+    /// 只能删除未发布过的固件, 已发布的固件需撤回
+    pub async fn delete_firm(&self, pool: &Data<&DbPool>, id: i32) -> Result<(), CustomError> {
+        let status: i32 = QueryBuilder::select(TABLE_FIRM, "status")
+            .and_where(Cond::eq("id", id))
+            .build()
+            .query_scalar()
+            .fetch_one(pool.0)
+            .await?;
+        if !matches!(
+            VoFirmStatus::from(status),
+            VoFirmStatus::Draft | VoFirmStatus::Testing
+        ) {
+            return Err(CustomError::InvalidState(format!(
+                "{:?}",
+                VoFirmStatus::from(status)
+            )));
+        }
+        let mut tx = pool.0.begin().await?;
+        let rows_affected = QueryBuilder::delete(TABLE_FIRM)
+            .and_where(Cond::eq("id", id))
+            .build()
+            .query()
+            .execute(&mut tx)
+            .await?
+            .rows_affected();
+        if rows_affected == 0 {
+            return Err(CustomError::DataNotFound);
+        }
+        QueryBuilder::delete(TABLE_FIRM_NOTE)
+            .and_where(Cond::eq("firm_id", id))
+            .build()
+            .query()
+            .execute(&mut tx)
+            .await?;
+        unindex_firm(&mut tx, id).await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// 保存上传的固件文件
+    pub async fn upload(
+        &self,
+        pool: &Data<&DbPool>,
+        store: &Data<&SharedBlobStore>,
+        data: VoUploadFirm,
+    ) -> Result<VoBlob, CustomError> {
+        let file_name = data.file.file_name().unwrap_or_default().to_string();
+        let content_type = data
+            .file
+            .content_type()
+            .unwrap_or("application/octet-stream")
+            .to_string();
+        // 多读一个字节以判断是否超过上限, 避免整个请求读入内存
+        let max = config().storage.max_file_size;
+        let mut content = Vec::new();
+        data.file
+            .into_async_read()
+            .take(max + 1)
+            .read_to_end(&mut content)
+            .await?;
+        if content.len() as u64 > max {
+            return Err(CustomError::InvalidParam(format!(
+                "file larger than {} bytes",
+                max
+            )));
+        }
+        let key = format!(
+            "{:x}{:04x}.bin",
+            Utc::now().timestamp_nanos(),
+            BLOB_SEQ.fetch_add(1, Ordering::Relaxed) & 0xffff
+        );
+        let digests = Digests::of(&content);
+        store.put(&key, &content).await?;
+        let inserted = QueryBuilder::insert(TABLE_BLOB)
+            .value("key", &key)
+            .value("file_name", &file_name)
+            .value("size", content.len() as i64)
+            .value("content_type", &content_type)
+            .value("sha256", &digests.sha256)
+            .value("crc32", &digests.crc32)
+            .value("md5", &digests.md5)
+            .build()
+            .query()
+            .execute(pool.0)
+            .await;
+        if let Err(e) = inserted {
+            // 未登记的文件不会被引用, 直接删除
+            if let Err(e) = store.delete(&key).await {
+                tracing::warn!(key = %key, error = %e, "failed to delete orphaned blob");
+            }
+            return Err(e.into());
+        }
+        Ok(VoBlob {
+            key,
+            file_name,
+            size: content.len() as i64,
+            content_type,
+            sha256: digests.sha256,
+            crc32: digests.crc32,
+            md5: digests.md5,
+        })
+    }
+
+    async fn blob(
+        &self,
+        pool: &Data<&DbPool>,
+        key: Option<&str>,
+    ) -> Result<Option<Blob>, CustomError> {
+        let key = match key.map(str::trim).filter(|k| !k.is_empty()) {
+            Some(key) => key,
+            None => return Ok(None),
+        };
+        QueryBuilder::select(TABLE_BLOB, BLOB_COLUMNS)
+            .and_where(Cond::eq("key", key))
+            .build()
+            .query_as()
+            .fetch_optional(pool.0)
+            .await?
+            .map(Some)
+            .ok_or_else(|| CustomError::InvalidParam("blob_key".to_string()))
+    }
+
+    /// 固件文件摘要: 已上传的文件使用上传时的摘要, 外部地址下载后计算
+    async fn digests(
+        &self,
+        blob: Option<&Blob>,
+        url: Option<&str>,
+    ) -> Result<Digests, CustomError> {
+        match (blob, url) {
+            (Some(b), _) => Ok(Digests {
+                sha256: b.sha256.clone(),
+                crc32: b.crc32.clone(),
+                md5: b.md5.clone(),
+            }),
+            (None, Some(url)) => fetch_digests(url)
+                .await
+                .map_err(|e| CustomError::InvalidParam(format!("url: {}", e))),
+            (None, None) => Err(CustomError::InvalidParam("url or blob_key".to_string())),
+        }
+    }
+
+    /// 重新校验所有固件文件, 内容与记录的摘要不一致时标记
+    pub async fn verify_checksums(
         &self,
         pool: &Data<&DbPool>,
-        hard_version: i32,
-    ) -> Result<Vec<VoFirm>, CustomError> {
-        let sql = SqlHelper::query(TABLE_FIRM, FIRM_COLUMNS)
-            .and_where_eq("hard_version")
-            .order_desc("update_time")
-            .sql();
-        let data: Vec<Firm> = sqlx::query_as(&sql)
-            .bind(&hard_version)
+        store: &Data<&SharedBlobStore>,
+    ) -> Result<(), CustomError> {
+        let firms: Vec<Firm> = QueryBuilder::select(TABLE_FIRM, FIRM_COLUMNS)
+            .build()
+            .query_as()
             .fetch_all(pool.0)
             .await?;
-        let mut firms = Vec::with_capacity(data.len());
-        for f in data {
-            firms.push(f.into())
+        for f in firms {
+            let digests = match &f.blob_key {
+                Some(key) => blob_digests(store.0, key).await.map_err(|e| e.to_string()),
+                None => fetch_digests(&f.url).await,
+            };
+            let state = match digests {
+                Ok(digests) => match &f.sha256 {
+                    Some(sha256) if *sha256 == digests.sha256 => VoChecksumState::Valid,
+                    Some(_) => VoChecksumState::Mismatch,
+                    None => {
+                        QueryBuilder::update(TABLE_FIRM)
+                            .set("sha256", digests.sha256)
+                            .set("crc32", digests.crc32)
+                            .set("md5", digests.md5)
+                            .and_where(Cond::eq("id", f.id))
+                            .build()
+                            .query()
+                            .execute(pool.0)
+                            .await?;
+                        VoChecksumState::Valid
+                    }
+                },
+                Err(e) => {
+                    tracing::warn!(firm = f.id, error = %e, "firm content unavailable");
+                    VoChecksumState::Missing
+                }
+            };
+            if state == VoChecksumState::Mismatch {
+                tracing::warn!(firm = f.id, "firm checksum mismatch");
+            }
+            if state == VoChecksumState::Valid && f.signature.is_none() {
+                self.sign_firm(pool, f.id).await?;
+            }
+            QueryBuilder::update(TABLE_FIRM)
+                .set("checksum_state", i32::from(state))
+                .set("verify_time", Utc::now())
+                .and_where(Cond::eq("id", f.id))
+                .build()
+                .query()
+                .execute(pool.0)
+                .await?;
         }
-        Ok(firms)
+        Ok(())
     }
 
-    pub async fn delete_firm(&self, pool: &Data<&DbPool>, id: i32) -> Result<(), CustomError> {
-        let sql = SqlHelper::delete(TABLE_FIRM).and_where_eq("id").sql();
-        let rows_affected = sqlx::query(&sql)
-            .bind(id)
-            .execute(pool.0)
-            .await?
-            .rows_affected();
-        if rows_affected > 0 {
-            Ok(())
-        } else {
-            Err(CustomError::DataNotFound)
+    /// 为缺少签名的固件补充签名
+    async fn sign_firm(&self, pool: &Data<&DbPool>, id: i32) -> Result<(), CustomError> {
+        let sha256: Option<String> = QueryBuilder::select(TABLE_FIRM, "sha256")
+            .and_where(Cond::eq("id", id))
+            .build()
+            .query_scalar()
+            .fetch_one(pool.0)
+            .await?;
+        if let Some(signature) = sha256.as_deref().and_then(sign_digest) {
+            QueryBuilder::update(TABLE_FIRM)
+                .set("signature", signature.signature)
+                .set("signature_key_id", signature.key_id)
+                .and_where(Cond::eq("id", id))
+                .build()
+                .query()
+                .execute(pool.0)
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// 固件对应的已上传文件, 已撤回的固件不再提供下载
+    pub async fn firm_blob(
+        &self,
+        pool: &Data<&DbPool>,
+        id: i32,
+        device_id: Option<&str>,
+    ) -> Result<Blob, CustomError> {
+        let firm: Firm = QueryBuilder::select(TABLE_FIRM, FIRM_COLUMNS)
+            .and_where(Cond::eq("id", id))
+            .build()
+            .query_as()
+            .fetch_one(pool.0)
+            .await?;
+        if !downloadable(&firm, device_id) {
+            return Err(CustomError::DataNotFound);
         }
+        QueryBuilder::select(TABLE_BLOB, BLOB_COLUMNS)
+            .and_where(Cond::eq("key", firm.blob_key))
+            .build()
+            .query_as()
+            .fetch_one(pool.0)
+            .await
+            .map_err(CustomError::from)
     }
 
     pub async fn add_firms(
         &self,
         pool: &Data<&DbPool>,
         data: VoAddFirm,
-    ) -> Result<(), CustomError> {
-        let sql = SqlHelper::insert(TABLE_FIRM, FIRM_ADD_COLUMNS).sql();
+    ) -> Result<i32, CustomError> {
+        data.validate()?;
         let data = data.check_data();
-        sqlx::query(&sql)
-            .bind(data.hard_version)
-            .bind(data.version_name)
-            .bind(data.version_format)
-            .bind(data.version_type)
-            .bind(data.finger_level)
-            .bind(data.url)
-            .bind(data.desc)
-            .bind(DateTime::<Utc>::from_utc(
-                NaiveDateTime::from_timestamp(data.update_time, 0),
-                Utc,
-            ))
-            .bind(data.rely_version_type)
-            .bind(data.min)
-            .bind(data.max)
-            .bind(data.des_en)
-            .bind(data.des_ko)
-            .bind(data.des_sp)
-            .execute(pool.0)
+        let blob = self.blob(pool, data.blob_key.as_deref()).await?;
+        let digests = self.digests(blob.as_ref(), data.url.as_deref()).await?;
+        let signature = sign_digest(&digests.sha256);
+        let (version_name, desc) = (data.version_name.clone(), data.desc.clone());
+        let notes = normalize_notes(data.notes);
+        let mut tx = pool.0.begin().await?;
+        let id = QueryBuilder::insert(TABLE_FIRM)
+            .value("hard_version", data.hard_version)
+            .value(
+                "version_key",
+                version_key(&data.version_format, &data.version_name),
+            )
+            .value("version_name", data.version_name)
+            .value("version_format", data.version_format)
+            .value("version_type", data.version_type)
+            .value("finger_level", data.finger_level)
+            .value("url", data.url.unwrap_or_default())
+            .value("desc", data.desc)
+            .value(
+                "update_time",
+                DateTime::<Utc>::from_utc(NaiveDateTime::from_timestamp(data.update_time, 0), Utc),
+            )
+            .value("rely_version_type", data.rely_version_type)
+            .value("min", data.min)
+            .value("max", data.max)
+            .value("size", blob.as_ref().map(|b| b.size))
+            .value(
+                "content_type",
+                blob.as_ref().map(|b| b.content_type.clone()),
+            )
+            .value("blob_key", blob.map(|b| b.key))
+            .value("sha256", digests.sha256)
+            .value("crc32", digests.crc32)
+            .value("md5", digests.md5)
+            .value("checksum_state", i32::from(VoChecksumState::Valid))
+            .value("verify_time", Utc::now())
+            .value("signature", signature.as_ref().map(|s| s.signature.clone()))
+            .value("signature_key_id", signature.map(|s| s.key_id))
+            .value("status", i32::from(VoFirmStatus::Draft))
+            .returning("id")
+            .build()
+            .fetch_returning(&mut tx)
             .await?;
-        Ok(())
+        save_notes(&mut tx, id, &notes).await?;
+        index_firm(&mut tx, id, &version_name, &desc, &notes).await?;
+        tx.commit().await?;
+        Ok(id)
     }
 
     pub async fn update_firms(
@@ -294,47 +2389,784 @@ impl FirmService {
         pool: &Data<&DbPool>,
         data: VoUpdateFirm,
     ) -> Result<(), CustomError> {
-        let sql = SqlHelper::update(TABLE_FIRM, FIRM_ADD_COLUMNS)
-            .and_where_eq(" id ")
-            .sql();
+        data.validate()?;
         let data = data.check_data();
-        let rows_affected = sqlx::query(&sql)
-            .bind(data.hard_version)
-            .bind(data.version_name)
-            .bind(data.version_format)
-            .bind(data.version_type)
-            .bind(data.finger_level)
-            .bind(data.url)
-            .bind(data.desc)
-            .bind(DateTime::<Utc>::from_utc(
-                NaiveDateTime::from_timestamp(data.update_time, 0),
-                Utc,
-            ))
-            .bind(data.rely_version_type)
-            .bind(data.min)
-            .bind(data.max)
-            .bind(data.des_en)
-            .bind(data.des_ko)
-            .bind(data.des_sp)
-            .bind(data.id)
-            .execute(pool.0)
+        let status: i32 = QueryBuilder::select(TABLE_FIRM, "status")
+            .and_where(Cond::eq("id", data.id))
+            .build()
+            .query_scalar()
+            .fetch_optional(pool.0)
+            .await?
+            .ok_or(CustomError::DataNotFound)?;
+        let current = VoFirmStatus::from(status);
+        if !current.editable() {
+            return Err(CustomError::InvalidState(format!(
+                "{:?} is not editable",
+                current
+            )));
+        }
+        let blob = self.blob(pool, data.blob_key.as_deref()).await?;
+        let digests = self.digests(blob.as_ref(), data.url.as_deref()).await?;
+        let signature = sign_digest(&digests.sha256);
+        let (version_name, desc) = (data.version_name.clone(), data.desc.clone());
+        let notes = normalize_notes(data.notes);
+        let mut tx = pool.0.begin().await?;
+        let rows_affected = QueryBuilder::update(TABLE_FIRM)
+            .set("hard_version", data.hard_version)
+            .set(
+                "version_key",
+                version_key(&data.version_format, &data.version_name),
+            )
+            .set("version_name", data.version_name)
+            .set("version_format", data.version_format)
+            .set("version_type", data.version_type)
+            .set("finger_level", data.finger_level)
+            .set("url", data.url.unwrap_or_default())
+            .set("desc", data.desc)
+            .set(
+                "update_time",
+                DateTime::<Utc>::from_utc(NaiveDateTime::from_timestamp(data.update_time, 0), Utc),
+            )
+            .set("rely_version_type", data.rely_version_type)
+            .set("min", data.min)
+            .set("max", data.max)
+            .set("size", blob.as_ref().map(|b| b.size))
+            .set(
+                "content_type",
+                blob.as_ref().map(|b| b.content_type.clone()),
+            )
+            .set("blob_key", blob.map(|b| b.key))
+            .set("sha256", digests.sha256)
+            .set("crc32", digests.crc32)
+            .set("md5", digests.md5)
+            .set("checksum_state", i32::from(VoChecksumState::Valid))
+            .set("verify_time", Utc::now())
+            .set("signature", signature.as_ref().map(|s| s.signature.clone()))
+            .set("signature_key_id", signature.map(|s| s.key_id))
+            .and_where(Cond::eq("id", data.id))
+            .and_where(Cond::eq("status", status))
+            .build()
+            .query()
+            .execute(&mut tx)
             .await?
             .rows_affected();
-        if rows_affected > 0 {
-            Ok(())
-        } else {
-            Err(CustomError::DataNotFound)
+        if rows_affected == 0 {
+            return Err(CustomError::InvalidState(format!(
+                "{:?} is not editable",
+                current
+            )));
+        }
+        save_notes(&mut tx, data.id, &notes).await?;
+        index_firm(&mut tx, data.id, &version_name, &desc, &notes).await?;
+        tx.commit().await?;
+        Ok(())
+    }
+}
+
+pub struct ApiKeyService;
+
+/// 通过 API key 认证的调用方, 权限为 key 的范围与所属用户角色的交集
+pub struct ApiKeyOwner {
+    pub user: VoUser,
+    pub scopes: Vec<VoApiScope>,
+}
+
+const TABLE_API_KEY: &str = "api_key";
+const API_KEY_COLUMNS: &str =
+    "id, user_id, name, prefix, key_hash, scopes, create_time, last_used_time, revoked";
+/// 审计中记录的 API key 字段, 不含摘要
+const API_KEY_AUDIT_COLUMNS: &str = "id, user_id, name, prefix, scopes, create_time, revoked";
+const API_KEY_PREFIX: &str = "fk_";
+impl ApiKeyService {
+    /// 用户自己的 API key
+    pub async fn keys(
+        &self,
+        pool: &Data<&DbPool>,
+        user_id: i32,
+    ) -> Result<Vec<VoApiKey>, CustomError> {
+        let keys: Vec<ApiKey> = QueryBuilder::select(TABLE_API_KEY, API_KEY_COLUMNS)
+            .and_where(Cond::eq("user_id", user_id))
+            .build()
+            .query_as()
+            .fetch_all(pool.0)
+            .await?;
+        Ok(keys.into_iter().map(VoApiKey::from).collect())
+    }
+
+    /// 创建 API key, 权限范围不能超过用户角色
+    pub async fn add_key(
+        &self,
+        pool: &Data<&DbPool>,
+        user: &VoUser,
+        data: VoAddApiKey,
+    ) -> Result<VoNewApiKey, CustomError> {
+        let name = data.name.trim();
+        if name.is_empty() {
+            return Err(CustomError::InvalidParam("name".to_string()));
+        }
+        let mut scopes: Vec<VoApiScope> = Vec::new();
+        for scope in data.scopes {
+            if !scopes.contains(&scope) {
+                scopes.push(scope);
+            }
+        }
+        if scopes.is_empty() {
+            return Err(CustomError::InvalidParam("scopes".to_string()));
+        }
+        if scopes.iter().any(|s| s.role() > user.role) {
+            return Err(CustomError::PermissionDenied);
+        }
+        let key = format!("{}{}", API_KEY_PREFIX, gen_token_id());
+        let id = QueryBuilder::insert(TABLE_API_KEY)
+            .value("user_id", user.id)
+            .value("name", name)
+            .value("prefix", &key[..API_KEY_PREFIX.len() + 8])
+            .value("key_hash", token_hash(&key))
+            .value("scopes", serde_json::to_string(&scopes).unwrap_or_default())
+            .value("create_time", Utc::now())
+            .returning("id")
+            .build()
+            .fetch_returning(pool.0)
+            .await?;
+        Ok(VoNewApiKey { id, key })
+    }
+
+    /// 吊销 API key, 管理员可吊销任意用户的 key
+    pub async fn revoke_key(
+        &self,
+        pool: &Data<&DbPool>,
+        user: &VoUser,
+        id: i32,
+    ) -> Result<(), CustomError> {
+        let mut query = QueryBuilder::update(TABLE_API_KEY)
+            .set("revoked", true)
+            .and_where(Cond::eq("id", id));
+        if user.role < VoRole::Admin {
+            query = query.and_where(Cond::eq("user_id", user.id));
+        }
+        let rows = query.build().query().execute(pool.0).await?.rows_affected();
+        if rows == 0 {
+            return Err(CustomError::DataNotFound);
+        }
+        Ok(())
+    }
+
+    /// 校验 API key 并记录使用时间
+    pub async fn check(&self, pool: &Data<&DbPool>, key: &str) -> Result<ApiKeyOwner, CustomError> {
+        if !key.starts_with(API_KEY_PREFIX) {
+            return Err(CustomError::TokenError);
+        }
+        let api_key: ApiKey = QueryBuilder::select(TABLE_API_KEY, API_KEY_COLUMNS)
+            .and_where(Cond::eq("key_hash", token_hash(key)))
+            .build()
+            .query_as()
+            .fetch_optional(pool.0)
+            .await?
+            .filter(|k: &ApiKey| !k.revoked)
+            .ok_or(CustomError::TokenError)?;
+        let user = SYS_USER_SERVICE.user(pool, api_key.user_id).await?;
+        if user.disabled {
+            return Err(CustomError::AccountDisabled);
+        }
+        QueryBuilder::update(TABLE_API_KEY)
+            .set("last_used_time", Utc::now())
+            .and_where(Cond::eq("id", api_key.id))
+            .build()
+            .query()
+            .execute(pool.0)
+            .await?;
+        Ok(ApiKeyOwner {
+            user: user.into(),
+            scopes: VoApiKey::from(api_key).scopes,
+        })
+    }
+}
+
+pub struct AuditService;
+
+/// 操作人及来源, 由 controller 构造
+pub struct AuditContext {
+    pub user_id: Option<i32>,
+    pub ip: Option<String>,
+}
+
+const TABLE_AUDIT: &str = "audit";
+const AUDIT_COLUMNS: &str =
+    "id, user_id, action, entity, entity_id, before, after, ip, create_time";
+/// 审计中记录的用户字段, 不含密码
+const USER_AUDIT_COLUMNS: &str =
+    "id, name, mail, update_time, role, disabled, invite_expire, totp_enabled";
+const AUDIT_PAGE_SIZE: i32 = 20;
+impl AuditService {
+    /// 记录修改前后的数据, `f` 失败时不记录
+    pub async fn track<T, F>(
+        &self,
+        pool: &Data<&DbPool>,
+        ctx: &AuditContext,
+        action: &str,
+        entity: VoAuditEntity,
+        id: impl ToString,
+        f: F,
+    ) -> Result<T, CustomError>
+    where
+        F: Future<Output = Result<T, CustomError>>,
+    {
+        let id = id.to_string();
+        let before = self.snapshot(pool, entity, &id).await?;
+        let result = f.await?;
+        let after = self.snapshot(pool, entity, &id).await?;
+        self.record(pool, ctx, action, entity, Some(id), before, after)
+            .await?;
+        Ok(result)
+    }
+
+    /// 记录新建的数据
+    pub async fn created(
+        &self,
+        pool: &Data<&DbPool>,
+        ctx: &AuditContext,
+        action: &str,
+        entity: VoAuditEntity,
+        id: impl ToString,
+    ) -> Result<(), CustomError> {
+        let id = id.to_string();
+        let after = self.snapshot(pool, entity, &id).await?;
+        self.record(pool, ctx, action, entity, Some(id), None, after)
+            .await
+    }
+
+    /// 以 json 保存当前数据, 不存在时返回 `None`
+    pub async fn snapshot(
+        &self,
+        pool: &Data<&DbPool>,
+        entity: VoAuditEntity,
+        id: &str,
+    ) -> Result<Option<String>, CustomError> {
+        let (table, columns, key) = match entity {
+            VoAuditEntity::Firm => (TABLE_FIRM, FIRM_COLUMNS, "id"),
+            VoAuditEntity::Blob => (TABLE_BLOB, BLOB_COLUMNS, "key"),
+            VoAuditEntity::DeviceType => (TABLE_HARD, HARD_COLUMNS, "id"),
+            VoAuditEntity::VersionType => (TABLE_SOFT, SOFT_COLUMNS, "id"),
+            VoAuditEntity::Channel => (TABLE_CHANNEL, CHANNEL_COLUMNS, "id"),
+            VoAuditEntity::ChannelAssign => (TABLE_CHANNEL_ASSIGN, CHANNEL_ASSIGN_COLUMNS, "id"),
+            VoAuditEntity::Device => (TABLE_DEVICE, DEVICE_COLUMNS, "id"),
+            VoAuditEntity::User => (TABLE_USER, USER_AUDIT_COLUMNS, "id"),
+            VoAuditEntity::ApiKey => (TABLE_API_KEY, API_KEY_AUDIT_COLUMNS, "id"),
+        };
+        let mut fields: Vec<String> = columns
+            .split(',')
+            .map(|c| format!("'{0}', \"{0}\"", c.trim()))
+            .collect();
+        if let VoAuditEntity::Firm = entity {
+            // 更新说明存在 firm_note, 一并记录以便只改说明时也能看出差异
+            fields.push(format!(
+                "'notes', json((SELECT json_group_object(\"locale\", \"text\") FROM \
+                 (SELECT \"locale\", \"text\" FROM \"{0}\" WHERE \"firm_id\" = \"{1}\".\"id\" ORDER BY \"locale\")))",
+                TABLE_FIRM_NOTE, TABLE_FIRM
+            ));
+        }
+        QueryBuilder::select(table, &format!("json_object({})", fields.join(", ")))
+            .and_where(Cond::eq(format!("\"{}\"", key), id))
+            .build()
+            .query_scalar()
+            .fetch_optional(pool.0)
+            .await
+            .map_err(CustomError::from)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub async fn record(
+        &self,
+        pool: &Data<&DbPool>,
+        ctx: &AuditContext,
+        action: &str,
+        entity: VoAuditEntity,
+        entity_id: Option<String>,
+        before: Option<String>,
+        after: Option<String>,
+    ) -> Result<(), CustomError> {
+        QueryBuilder::insert(TABLE_AUDIT)
+            .value("user_id", ctx.user_id)
+            .value("action", action)
+            .value("entity", i32::from(entity))
+            .value("entity_id", entity_id)
+            .value("before", before)
+            .value("after", after)
+            .value("ip", ctx.ip.as_ref())
+            .value("create_time", Utc::now())
+            .build()
+            .query()
+            .execute(pool.0)
+            .await?;
+        Ok(())
+    }
+
+    /// 分页查询, 最新的在前
+    pub async fn audits(
+        &self,
+        pool: &Data<&DbPool>,
+        entity: Option<VoAuditEntity>,
+        entity_id: Option<&str>,
+        user_id: Option<i32>,
+        page: Option<i32>,
+        page_size: Option<i32>,
+    ) -> Result<VoAuditPage, CustomError> {
+        let page = page.unwrap_or(1).max(1);
+        let page_size = page_size.unwrap_or(AUDIT_PAGE_SIZE).clamp(1, 100);
+        let mut query = QueryBuilder::select(TABLE_AUDIT, AUDIT_COLUMNS);
+        if let Some(entity) = entity {
+            query = query.and_where(Cond::eq("entity", i32::from(entity)));
+        }
+        if let Some(entity_id) = entity_id {
+            query = query.and_where(Cond::eq("entity_id", entity_id));
+        }
+        if let Some(user_id) = user_id {
+            query = query.and_where(Cond::eq("user_id", user_id));
+        }
+        let total: i64 = query
+            .to_count()
+            .build()
+            .query_scalar()
+            .fetch_one(pool.0)
+            .await?;
+        let items: Vec<Audit> = query
+            .order_desc("id")
+            .page(page, page_size)
+            .build()
+            .query_as()
+            .fetch_all(pool.0)
+            .await?;
+        Ok(VoAuditPage {
+            total,
+            page,
+            page_size,
+            items: items.into_iter().map(VoAudit::from).collect(),
+        })
+    }
+}
+
+/// 审计中保存的请求数据
+pub fn audit_json(data: &impl serde::Serialize) -> Option<String> {
+    serde_json::to_string(data).ok()
+}
+
+/// 对固件 sha256 摘要签名
+fn sign_digest(sha256: &str) -> Option<Signature> {
+    keystore().sign(&from_hex(sha256)?)
+}
+
+/// 生成签名的升级清单, 未配置密钥时返回 `None`
+fn sign_manifest(
+    hard_version: &str,
+    firm: &VoFirm,
+    revoked_current: bool,
+) -> Result<Option<VoSignedManifest>, CustomError> {
+    let key_id = match keystore().active_key_id() {
+        Some(id) => id.to_string(),
+        None => return Ok(None),
+    };
+    let manifest = Manifest {
+        firm_id: firm.id,
+        hard_version: hard_version.to_string(),
+        version_name: firm.version_name.clone(),
+        version_format: firm.version_format.clone(),
+        version_type: firm.version_type,
+        url: firm.url.clone(),
+        size: firm.size,
+        sha256: firm.sha256.clone(),
+        image_signature: firm.signature.clone(),
+        image_key_id: firm.signature_key_id.clone(),
+        rely_version_type: firm.rely_version_type,
+        min: firm.min.clone(),
+        max: firm.max.clone(),
+        revoked_current,
+        key_id,
+        issued_at: Utc::now().timestamp(),
+    };
+    let payload =
+        serde_json::to_string(&manifest).map_err(|e| CustomError::Internal(e.to_string()))?;
+    Ok(keystore()
+        .sign(payload.as_bytes())
+        .map(|s| VoSignedManifest {
+            payload,
+            key_id: s.key_id,
+            signature: s.signature,
+        }))
+}
+
+/// 设备在发布比例中的分桶 (0-99), 同一设备对同一固件结果固定
+fn rollout_bucket(firm_id: i32, device_id: &str) -> i32 {
+    let mut hasher = Sha256::new();
+    hasher.input_str(&format!("{}:{}", firm_id, device_id));
+    let mut hash = [0u8; 32];
+    hasher.result(&mut hash);
+    (u32::from_be_bytes([hash[0], hash[1], hash[2], hash[3]]) % 100) as i32
+}
+
+/// 设备是否在固件的发布范围内
+fn rollout_eligible(firm: &Firm, device_id: Option<&str>, now: DateTime<Utc>) -> bool {
+    if firm.rollout_paused || firm.rollout_start.is_some_and(|t| t > now) {
+        return false;
+    }
+    if firm.rollout_percent >= 100 {
+        return true;
+    }
+    let device_id = match device_id {
+        Some(d) if !d.is_empty() => d,
+        _ => return false,
+    };
+    in_allowlist(firm, device_id) || rollout_bucket(firm.id, device_id) < firm.rollout_percent
+}
+
+fn in_allowlist(firm: &Firm, device_id: &str) -> bool {
+    let allowlist: Vec<String> = serde_json::from_str(&firm.rollout_allowlist).unwrap_or_default();
+    allowlist.iter().any(|d| d == device_id)
+}
+
+/// 设备可下载已发布的固件, 测试中的固件只对白名单内的设备开放
+fn downloadable(firm: &Firm, device_id: Option<&str>) -> bool {
+    match VoFirmStatus::from(firm.status) {
+        VoFirmStatus::Published => true,
+        VoFirmStatus::Testing => device_id.is_some_and(|d| in_allowlist(firm, d)),
+        _ => false,
+    }
+}
+
+/// 下载外部地址的固件文件并计算摘要, 超过 `storage.max_file_size` 时放弃
+async fn fetch_digests(url: &str) -> Result<Digests, String> {
+    let max = config().storage.max_file_size;
+    let too_large = || format!("file larger than {} bytes", max);
+    let mut resp = FETCH_CLIENT
+        .get(url)
+        .send()
+        .await
+        .and_then(|r| r.error_for_status())
+        .map_err(|e| e.to_string())?;
+    if resp.content_length().is_some_and(|len| len > max) {
+        return Err(too_large());
+    }
+    let mut hasher = DigestHasher::new();
+    let mut size = 0;
+    while let Some(chunk) = resp.chunk().await.map_err(|e| e.to_string())? {
+        size += chunk.len() as u64;
+        if size > max {
+            return Err(too_large());
+        }
+        hasher.update(&chunk);
+    }
+    Ok(hasher.finish())
+}
+
+/// 分段读取已上传的文件并计算摘要
+async fn blob_digests(store: &SharedBlobStore, key: &str) -> std::io::Result<Digests> {
+    let size = store.size(key).await?;
+    let mut hasher = DigestHasher::new();
+    let mut offset = 0;
+    while offset < size {
+        let chunk = store
+            .read(key, offset, BLOB_READ_CHUNK.min(size - offset))
+            .await?;
+        if chunk.is_empty() {
+            break;
+        }
+        offset += chunk.len() as u64;
+        hasher.update(&chunk);
+    }
+    Ok(hasher.finish())
+}
+
+/// 固件版本以 `version_format` 为准, 无法解析时退回 `version_name`
+fn firm_version(firm: &Firm) -> Option<Version> {
+    parse_firm_version(&firm.version_format, &firm.version_name)
+}
+
+fn parse_firm_version(version_format: &str, version_name: &str) -> Option<Version> {
+    Version::parse(version_format).or_else(|| Version::parse_device(version_name))
+}
+
+/// 写入 `firm.version_key` 的排序键, 无法解析时为空字符串
+fn version_key(version_format: &str, version_name: &str) -> String {
+    parse_firm_version(version_format, version_name)
+        .map(|v| v.sort_key())
+        .unwrap_or_default()
+}
+
+/// 固件列表的游标: 上一页最后一行的 `id:排序列的值`
+fn firm_cursor(column: &str, firm: &Firm) -> String {
+    let value = match column {
+        "version_key" => version_key(&firm.version_format, &firm.version_name),
+        "update_time" => firm.update_time.to_rfc3339(),
+        "version_name" => firm.version_name.clone(),
+        _ => firm.id.to_string(),
+    };
+    format!("{}:{}", firm.id, value)
+}
+
+fn parse_firm_cursor(column: &str, cursor: &str) -> Option<(i32, SqlValue)> {
+    let (id, value) = cursor.split_once(':')?;
+    let id = id.parse().ok()?;
+    let value = match column {
+        "update_time" => DateTime::parse_from_rfc3339(value)
+            .ok()?
+            .with_timezone(&Utc)
+            .into(),
+        "id" => SqlValue::Int(value.parse().ok()?),
+        _ => value.into(),
+    };
+    Some((id, value))
+}
+
+/// 秒级时间戳, 超出范围时返回参数错误
+fn timestamp(t: i64, name: &str) -> Result<DateTime<Utc>, CustomError> {
+    Utc.timestamp_opt(t, 0)
+        .single()
+        .ok_or_else(|| CustomError::InvalidParam(name.to_string()))
+}
+
+/// 搜索片段中标记关键词的标签
+const MARK_OPEN: &str = "<mark>";
+const MARK_CLOSE: &str = "</mark>";
+/// 片段最多包含的字符数, 不含标签
+const SNIPPET_CHARS: usize = 64;
+
+fn fold(c: char) -> char {
+    c.to_lowercase().next().unwrap_or(c)
+}
+
+/// 转义 HTML 特殊字符, 片段中只有 `<mark>` 是标签
+fn push_escaped(out: &mut String, c: char) {
+    match c {
+        '&' => out.push_str("&amp;"),
+        '<' => out.push_str("&lt;"),
+        '>' => out.push_str("&gt;"),
+        '"' => out.push_str("&quot;"),
+        '\'' => out.push_str("&#39;"),
+        _ => out.push(c),
+    }
+}
+
+/// 截取首个命中词附近的片段, 转义后标出片段内所有命中词, 不区分大小写, 未命中返回 `None`
+fn highlight(text: &str, terms: &[&str]) -> Option<String> {
+    let chars: Vec<char> = text.chars().collect();
+    let folded: Vec<char> = chars.iter().map(|c| fold(*c)).collect();
+    let terms: Vec<Vec<char>> = terms
+        .iter()
+        .map(|t| t.chars().map(fold).collect())
+        .collect();
+    // 从 i 开始命中的最长词的长度
+    let hit = |i: usize| {
+        terms
+            .iter()
+            .filter(|t| folded[i..].starts_with(t))
+            .map(Vec::len)
+            .max()
+    };
+    let first = (0..chars.len()).find(|i| hit(*i).is_some())?;
+    let start = first.saturating_sub(SNIPPET_CHARS / 4);
+    let mut end = chars.len().min(start + SNIPPET_CHARS);
+
+    let mut snippet = String::new();
+    if start > 0 {
+        snippet.push('…');
+    }
+    let mut i = start;
+    while i < end {
+        match hit(i) {
+            Some(len) => {
+                snippet.push_str(MARK_OPEN);
+                chars[i..i + len]
+                    .iter()
+                    .for_each(|c| push_escaped(&mut snippet, *c));
+                snippet.push_str(MARK_CLOSE);
+                i += len;
+                end = end.max(i);
+            }
+            None => {
+                push_escaped(&mut snippet, chars[i]);
+                i += 1;
+            }
         }
     }
+    if end < chars.len() {
+        snippet.push('…');
+    }
+    Some(snippet)
+}
+
+/// 规范化语言标签, 丢弃空白的说明
+fn normalize_notes(notes: Vec<VoFirmNote>) -> Vec<VoFirmNote> {
+    notes
+        .into_iter()
+        .filter(|n| !n.text.trim().is_empty())
+        .filter_map(|n| {
+            Some(VoFirmNote {
+                locale: locale::normalize(&n.locale)?,
+                text: n.text,
+            })
+        })
+        .collect()
+}
+
+/// 更新用户的 ticker 使已签发的访问令牌失效, 并撤销所有刷新令牌
+async fn revoke_tokens(tx: &mut Transaction<'_, Sqlite>, id: i32) -> Result<(), CustomError> {
+    let update_time: DateTime<Utc> = QueryBuilder::select(TABLE_USER, "update_time")
+        .and_where(Cond::eq("id", id))
+        .build()
+        .query_scalar()
+        .fetch_one(&mut *tx)
+        .await?;
+    // ticker 精确到秒, 保证与旧令牌不同
+    let ticker = Utc::now().max(update_time + chrono::Duration::seconds(1));
+    QueryBuilder::update(TABLE_USER)
+        .set("update_time", ticker)
+        .and_where(Cond::eq("id", id))
+        .build()
+        .query()
+        .execute(&mut *tx)
+        .await?;
+    QueryBuilder::update(TABLE_REFRESH_TOKEN)
+        .set("revoked", true)
+        .and_where(Cond::eq("user_id", id))
+        .build()
+        .query()
+        .execute(&mut *tx)
+        .await?;
+    Ok(())
+}
+
+/// 以 `notes` 替换固件原有的多语言说明
+async fn save_notes(
+    tx: &mut Transaction<'_, Sqlite>,
+    id: i32,
+    notes: &[VoFirmNote],
+) -> Result<(), CustomError> {
+    QueryBuilder::delete(TABLE_FIRM_NOTE)
+        .and_where(Cond::eq("firm_id", id))
+        .build()
+        .query()
+        .execute(&mut *tx)
+        .await?;
+    for note in notes {
+        QueryBuilder::insert(TABLE_FIRM_NOTE)
+            .value("firm_id", id)
+            .value("locale", &note.locale)
+            .value("text", &note.text)
+            .build()
+            .query()
+            .execute(&mut *tx)
+            .await?;
+    }
+    Ok(())
+}
+
+/// 重建固件的全文索引, 各语言说明按行拼接
+async fn index_firm(
+    tx: &mut Transaction<'_, Sqlite>,
+    id: i32,
+    version_name: &str,
+    desc: &str,
+    notes: &[VoFirmNote],
+) -> Result<(), CustomError> {
+    unindex_firm(tx, id).await?;
+    let notes: Vec<&str> = notes.iter().map(|n| n.text.as_str()).collect();
+    let mut insert = QueryBuilder::insert(TABLE_FIRM_FTS).value("rowid", id);
+    for (column, text) in FIRM_FTS_COLUMNS
+        .iter()
+        .zip([version_name, desc, &notes.join("\n")])
+    {
+        insert = insert.value(column, text);
+    }
+    insert.build().query().execute(tx).await?;
+    Ok(())
+}
+
+async fn unindex_firm(tx: &mut Transaction<'_, Sqlite>, id: i32) -> Result<(), CustomError> {
+    QueryBuilder::delete(TABLE_FIRM_FTS)
+        .and_where(Cond::eq("rowid", id))
+        .build()
+        .query()
+        .execute(tx)
+        .await?;
+    Ok(())
+}
+
+/// 属于指定名称渠道的固件
+fn in_channel(channel: &str) -> Cond {
+    let channel = QueryBuilder::select(TABLE_CHANNEL, "id").and_where(Cond::eq("name", channel));
+    Cond::in_query("channel_id", channel)
+}
+
+/// 按版本号从新到旧排序, 版本相同时按更新时间
+fn sort_by_version(firms: &mut [Firm]) {
+    firms.sort_by_cached_key(|f| (Reverse(firm_version(f)), Reverse(f.update_time)));
+}
+
+/// 解析 `type:version,type:version`
+fn parse_installed(installed: &str) -> Result<HashMap<i32, Version>, CustomError> {
+    let mut map = HashMap::new();
+    for item in installed.split(',').filter(|i| !i.trim().is_empty()) {
+        let parsed = item.split_once(':').and_then(|(t, v)| {
+            let t = t.trim().parse::<i32>().ok()?;
+            Some((t, Version::parse_device(v)?))
+        });
+        match parsed {
+            Some((t, v)) => map.insert(t, v),
+            None => return Err(CustomError::InvalidParam("installed".to_string())),
+        };
+    }
+    Ok(map)
+}
+
+fn running_versions(firmware: &str) -> HashMap<i32, Version> {
+    let running: Vec<VoRunning> = serde_json::from_str(firmware).unwrap_or_default();
+    running
+        .into_iter()
+        .filter_map(|r| Some((r.version_type, Version::parse_device(&r.version)?)))
+        .collect()
+}
+
+/// 检查固件的升级依赖 (`rely_version_type` 的版本需在 `min`..=`max` 之间)
+fn rely_satisfied(firm: &Firm, installed: &HashMap<i32, Version>) -> bool {
+    if firm.rely_version_type.is_none() && firm.min.is_none() && firm.max.is_none() {
+        return true;
+    }
+    let rely_type = firm.rely_version_type.unwrap_or(firm.version_type);
+    let version = match installed.get(&rely_type) {
+        Some(v) => v,
+        None => return false,
+    };
+    let in_bound = |bound: &Option<String>, ok: fn(&Version, &Version) -> bool| match bound
+        .as_deref()
+        .filter(|b| !b.trim().is_empty())
+    {
+        Some(b) => Version::parse_device(b).is_some_and(|b| ok(version, &b)),
+        None => true,
+    };
+    in_bound(&firm.min, |v, b| v >= b) && in_bound(&firm.max, |v, b| v <= b)
 }
 
 #[cfg(test)]
 mod tests {
 
     // use bcrypt::{DEFAULT_COST, hash, verify};
+    use chrono::Utc;
     use crypto::digest::Digest;
     use crypto::sha1::Sha1;
 
+    use poem::web::Data;
+
+    use super::{
+        downloadable, highlight, parse_device_csv, parse_installed, rely_satisfied, rollout_bucket,
+        rollout_eligible, SYS_AUDIT_SERVICE, SYS_FIRM_SERVICE,
+    };
+    use crate::domain::{
+        dto::Firm,
+        vo::{
+            CustomError, VoAssignTarget, VoAuditEntity, VoCheckUpdate, VoChecksumState, VoFirm,
+            VoFirmQuery, VoFirmSort, VoFirmStatus, VoUpdateFirm,
+        },
+    };
+
     #[test]
     fn test_bcrypt() {
         let ret = bcrypt::verify(
@@ -352,4 +3184,367 @@ mod tests {
         let hex = hasher.result_str();
         assert_eq!(hex, "2aae6c35c94fcfb415dbe95f408b9ce91ee846ed");
     }
+
+    /// 已发布的固件, 测试中按需覆盖字段
+    fn firm() -> Firm {
+        Firm {
+            id: 1,
+            hard_version: 1,
+            version_name: "000001".to_string(),
+            version_format: "1.0.0".to_string(),
+            version_type: 1,
+            finger_level: 0,
+            url: String::new(),
+            desc: String::new(),
+            update_time: Utc::now(),
+            rely_version_type: None,
+            min: None,
+            max: None,
+            size: None,
+            content_type: None,
+            blob_key: None,
+            sha256: None,
+            crc32: None,
+            md5: None,
+            checksum_state: 0,
+            verify_time: None,
+            signature: None,
+            signature_key_id: None,
+            status: i32::from(VoFirmStatus::Published),
+            status_time: None,
+            rollout_percent: 100,
+            rollout_allowlist: "[]".to_string(),
+            rollout_start: None,
+            rollout_paused: false,
+            channel_id: 1,
+        }
+    }
+
+    #[test]
+    fn test_rely_window() {
+        let firm = Firm {
+            version_name: "020001".to_string(),
+            version_format: "1.0.2".to_string(),
+            rely_version_type: Some(2),
+            min: Some("0001".to_string()),
+            max: Some("0002".to_string()),
+            ..firm()
+        };
+        assert!(rely_satisfied(&firm, &parse_installed("2:0501").unwrap()));
+        assert!(!rely_satisfied(&firm, &parse_installed("2:0502").unwrap()));
+        assert!(!rely_satisfied(&firm, &parse_installed("3:0501").unwrap()));
+        assert!(parse_installed("2-0501").is_err());
+    }
+
+    #[test]
+    fn test_rollout() {
+        let firm = Firm {
+            rollout_percent: 10,
+            rollout_allowlist: r#"["lock-a"]"#.to_string(),
+            ..firm()
+        };
+        let now = Utc::now();
+        assert!(rollout_eligible(&firm, Some("lock-a"), now));
+        assert!(!rollout_eligible(&firm, None, now));
+        let inside = (0..1000)
+            .filter(|i| rollout_eligible(&firm, Some(&format!("lock-{}", i)), now))
+            .count();
+        assert!((50..150).contains(&inside));
+        assert_eq!(rollout_bucket(1, "lock-7"), rollout_bucket(1, "lock-7"));
+    }
+
+    #[test]
+    fn test_downloadable() {
+        let firm = Firm {
+            rollout_percent: 10,
+            rollout_allowlist: r#"["lock-a"]"#.to_string(),
+            ..firm()
+        };
+        assert!(downloadable(&firm, None));
+        let firm = Firm {
+            status: i32::from(VoFirmStatus::Testing),
+            ..firm
+        };
+        assert!(downloadable(&firm, Some("lock-a")));
+        assert!(!downloadable(&firm, Some("lock-7")));
+        assert!(!downloadable(&firm, None));
+        let firm = Firm {
+            status: i32::from(VoFirmStatus::Revoked),
+            ..firm
+        };
+        assert!(!downloadable(&firm, Some("lock-a")));
+    }
+
+    #[tokio::test]
+    async fn test_check_update() {
+        let pool = sqlx::sqlite::SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        crate::migrate::migrate(&pool).await.unwrap();
+        sqlx::query(
+            "INSERT INTO device_type (hard_version, name, category) VALUES ('HW1', 'lock', 1); \
+             INSERT INTO channel_assign (target_type, target, channel_id) VALUES (?, 'lab', 2)",
+        )
+        .bind(i32::from(VoAssignTarget::Group))
+        .execute(&pool)
+        .await
+        .unwrap();
+        let published = VoFirmStatus::Published;
+        for (version, status, channel, checksum) in [
+            ("1.0.0", published, 1, VoChecksumState::Valid),
+            ("1.0.1", published, 1, VoChecksumState::Unverified),
+            ("1.0.2", published, 2, VoChecksumState::Valid),
+            ("1.0.3", VoFirmStatus::Testing, 1, VoChecksumState::Valid),
+            ("1.0.4", published, 1, VoChecksumState::Mismatch),
+            ("1.0.5", VoFirmStatus::Revoked, 1, VoChecksumState::Valid),
+        ] {
+            sqlx::query(
+                "INSERT INTO firm (hard_version, version_name, version_format, version_type, url, \
+                 status, channel_id, checksum_state) VALUES (1, ?, ?, 1, 'http://a/b.bin', ?, ?, ?)",
+            )
+            .bind(version)
+            .bind(version)
+            .bind(i32::from(status))
+            .bind(channel)
+            .bind(i32::from(checksum))
+            .execute(&pool)
+            .await
+            .unwrap();
+        }
+        let pool = Data(&pool);
+        let latest = |current: &str, group: Option<&str>| {
+            let data = VoCheckUpdate {
+                device_id: None,
+                group: group.map(String::from),
+                hard_version: "HW1".to_string(),
+                version_type: 1,
+                current: current.to_string(),
+                finger_level: 0,
+                installed: None,
+            };
+            let pool = &pool;
+            async move {
+                let ret = SYS_FIRM_SERVICE.check_update(pool, data).await.unwrap();
+                (ret.firm.map(|f| f.version_format), ret.revoked)
+            }
+        };
+        // 测试中、校验不一致和不可见渠道的固件都不下发
+        assert_eq!(
+            latest("1.0.0", None).await,
+            (Some("1.0.1".to_string()), false)
+        );
+        assert_eq!(
+            latest("1.0.0", Some("lab")).await,
+            (Some("1.0.2".to_string()), false)
+        );
+        assert_eq!(latest("1.0.2", None).await, (None, false));
+        // 当前版本已撤回时回退到更低的已发布版本
+        assert_eq!(
+            latest("1.0.5", None).await,
+            (Some("1.0.1".to_string()), true)
+        );
+        assert_eq!(
+            latest("1.0.5", Some("lab")).await,
+            (Some("1.0.2".to_string()), true)
+        );
+    }
+
+    #[test]
+    fn test_device_csv() {
+        let csv = "serial,mac,hard_version,group\nSN1, aa-bb-cc-dd-ee-ff ,HW1,lab\n\nSN2,,HW1\nSN1,,HW1\nSN3,HW1\n";
+        let (rows, errors) = parse_device_csv(csv);
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0].mac.as_deref(), Some("AA:BB:CC:DD:EE:FF"));
+        assert_eq!(rows[0].group_name.as_deref(), Some("lab"));
+        assert_eq!(rows[1].line, 4);
+        assert!(rows[1].mac.is_none() && rows[1].group_name.is_none());
+        assert_eq!(errors.len(), 2);
+        assert!(errors[0].starts_with("line 5"));
+    }
+
+    #[tokio::test]
+    async fn test_update_firm_status() {
+        let pool = sqlx::sqlite::SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        crate::migrate::migrate(&pool).await.unwrap();
+        for status in [VoFirmStatus::Published, VoFirmStatus::Revoked] {
+            let id: i32 = sqlx::query_scalar(
+                "INSERT INTO firm (hard_version, version_name, version_format, version_type, url, status) \
+                 VALUES (1, '1.0.0', '1.0.0', 1, 'http://a/b.bin', ?) RETURNING id",
+            )
+            .bind(i32::from(status))
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+            let ret = SYS_FIRM_SERVICE
+                .update_firms(&Data(&pool), update(id))
+                .await;
+            assert!(matches!(ret, Err(CustomError::InvalidState(_))));
+        }
+        let ret = SYS_FIRM_SERVICE
+            .update_firms(&Data(&pool), update(999))
+            .await;
+        assert!(matches!(ret, Err(CustomError::DataNotFound)));
+    }
+
+    #[tokio::test]
+    async fn test_firm_page() {
+        let pool = sqlx::sqlite::SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        crate::migrate::migrate(&pool).await.unwrap();
+        for version in ["1.9.0", "1.10.0", "1.0.0-rc.1", "1.2", "1.0.0", "beta"] {
+            sqlx::query(
+                "INSERT INTO firm (hard_version, version_name, version_format, version_type, url) \
+                 VALUES (1, ?, ?, 1, 'http://a/b.bin')",
+            )
+            .bind(version)
+            .bind(version)
+            .execute(&pool)
+            .await
+            .unwrap();
+        }
+        let pool = Data(&pool);
+        assert_eq!(SYS_FIRM_SERVICE.fill_version_keys(&pool).await.unwrap(), 6);
+        let by_cursor = page_all(&pool, VoFirmSort::Version, true).await;
+        let versions: Vec<&str> = by_cursor
+            .iter()
+            .map(|f| f.version_format.as_str())
+            .collect();
+        assert_eq!(
+            versions,
+            ["1.10.0", "1.9.0", "1.2", "1.0.0", "1.0.0-rc.1", "beta"]
+        );
+        for sort in [
+            VoFirmSort::Version,
+            VoFirmSort::UpdateTime,
+            VoFirmSort::UpdateTimeDesc,
+            VoFirmSort::VersionName,
+            VoFirmSort::VersionNameDesc,
+            VoFirmSort::Id,
+            VoFirmSort::IdDesc,
+        ] {
+            let by_cursor: Vec<i32> = page_all(&pool, sort, true)
+                .await
+                .iter()
+                .map(|f| f.id)
+                .collect();
+            let by_page: Vec<i32> = page_all(&pool, sort, false)
+                .await
+                .iter()
+                .map(|f| f.id)
+                .collect();
+            assert_eq!(by_cursor.len(), 6);
+            assert_eq!(by_cursor, by_page);
+        }
+        let query = VoFirmQuery {
+            cursor: Some("1".to_string()),
+            ..Default::default()
+        };
+        let ret = SYS_FIRM_SERVICE.firm_page(&pool, query).await;
+        assert!(matches!(ret, Err(CustomError::InvalidParam(_))));
+    }
+
+    /// 按游标或页码翻完所有页
+    async fn page_all(
+        pool: &Data<&crate::DbPool>,
+        sort: VoFirmSort,
+        by_cursor: bool,
+    ) -> Vec<VoFirm> {
+        let mut items = Vec::new();
+        let (mut page, mut cursor) = (1, None);
+        loop {
+            let query = VoFirmQuery {
+                sort: Some(sort),
+                page: (!by_cursor).then_some(page),
+                page_size: Some(4),
+                cursor: cursor.take(),
+                ..Default::default()
+            };
+            let ret = SYS_FIRM_SERVICE.firm_page(pool, query).await.unwrap();
+            assert_eq!((ret.total, ret.page), (6, page));
+            items.extend(ret.items);
+            match ret.next_cursor {
+                Some(next) => cursor = by_cursor.then_some(next),
+                None => return items,
+            }
+            page += 1;
+        }
+    }
+
+    #[tokio::test]
+    async fn test_firm_snapshot_notes() {
+        let pool = sqlx::sqlite::SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        crate::migrate::migrate(&pool).await.unwrap();
+        sqlx::query(
+            "INSERT INTO firm (id, hard_version, version_name, version_format, version_type, url) \
+             VALUES (1, 1, '1.0.0', '1.0.0', 1, 'http://a/b.bin'); \
+             INSERT INTO firm_note (firm_id, locale, text) VALUES (1, 'en', 'old')",
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        let pool = Data(&pool);
+        let snapshot = || SYS_AUDIT_SERVICE.snapshot(&pool, VoAuditEntity::Firm, "1");
+        let before = snapshot().await.unwrap().unwrap();
+        let json: serde_json::Value = serde_json::from_str(&before).unwrap();
+        assert_eq!(json["notes"]["en"], "old");
+        sqlx::query("UPDATE firm_note SET text = 'new' WHERE firm_id = 1")
+            .execute(pool.0)
+            .await
+            .unwrap();
+        assert_ne!(snapshot().await.unwrap().unwrap(), before);
+    }
+
+    fn update(id: i32) -> VoUpdateFirm {
+        VoUpdateFirm {
+            id,
+            hard_version: 1,
+            version_name: "1.0.1".to_string(),
+            version_format: "1.0.1".to_string(),
+            version_type: 1,
+            finger_level: 0,
+            url: Some("http://a/c.bin".to_string()),
+            blob_key: None,
+            desc: String::new(),
+            update_time: 0,
+            rely_version_type: None,
+            min: None,
+            max: None,
+            notes: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_highlight() {
+        assert_eq!(
+            highlight("修复指纹超时", &["指纹"]).as_deref(),
+            Some("修复<mark>指纹</mark>超时")
+        );
+        assert_eq!(
+            highlight("Fixed Fingerprint timeout", &["finger", "TIMEOUT"]).as_deref(),
+            Some("Fixed <mark>Finger</mark>print <mark>timeout</mark>")
+        );
+        assert_eq!(highlight("initial release", &["finger"]), None);
+        assert_eq!(
+            highlight("<img src=x onerror=\"a&b\"> fix", &["fix", "<img"]).as_deref(),
+            Some("<mark>&lt;img</mark> src=x onerror=&quot;a&amp;b&quot;&gt; <mark>fix</mark>")
+        );
+
+        let long = format!("{}finger{}", "a".repeat(100), "b".repeat(100));
+        let snippet = highlight(&long, &["finger"]).unwrap();
+        assert!(snippet.starts_with('…') && snippet.ends_with('…'));
+        assert!(snippet.contains("<mark>finger</mark>"));
+    }
 }
@@ -0,0 +1,118 @@
+use std::{
+    io::{Error, ErrorKind, Result, SeekFrom},
+    path::PathBuf,
+    sync::Arc,
+};
+
+use poem::async_trait;
+use tokio::{
+    fs,
+    io::{AsyncReadExt, AsyncSeekExt},
+};
+
+/// 固件文件存储
+#[async_trait]
+pub trait BlobStore: Send + Sync {
+    async fn put(&self, key: &str, data: &[u8]) -> Result<()>;
+
+    /// 读取 `offset` 开始的 `len` 个字节
+    async fn read(&self, key: &str, offset: u64, len: u64) -> Result<Vec<u8>>;
+
+    async fn size(&self, key: &str) -> Result<u64>;
+
+    async fn delete(&self, key: &str) -> Result<()>;
+}
+
+pub type SharedBlobStore = Arc<dyn BlobStore>;
+
+/// 本地文件系统存储
+pub struct LocalBlobStore {
+    root: PathBuf,
+}
+
+impl LocalBlobStore {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        LocalBlobStore { root: root.into() }
+    }
+
+    fn path(&self, key: &str) -> Result<PathBuf> {
+        let valid = !key.is_empty()
+            && key
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_' || b == b'.')
+            && !key.starts_with('.');
+        if valid {
+            Ok(self.root.join(key))
+        } else {
+            Err(Error::new(ErrorKind::InvalidInput, "invalid blob key"))
+        }
+    }
+}
+
+#[async_trait]
+impl BlobStore for LocalBlobStore {
+    async fn put(&self, key: &str, data: &[u8]) -> Result<()> {
+        let path = self.path(key)?;
+        fs::create_dir_all(&self.root).await?;
+        let tmp = path.with_extension("part");
+        fs::write(&tmp, data).await?;
+        fs::rename(&tmp, &path).await
+    }
+
+    async fn read(&self, key: &str, offset: u64, len: u64) -> Result<Vec<u8>> {
+        let mut file = fs::File::open(self.path(key)?).await?;
+        file.seek(SeekFrom::Start(offset)).await?;
+        let mut data = Vec::with_capacity(len as usize);
+        file.take(len).read_to_end(&mut data).await?;
+        Ok(data)
+    }
+
+    async fn size(&self, key: &str) -> Result<u64> {
+        Ok(fs::metadata(self.path(key)?).await?.len())
+    }
+
+    async fn delete(&self, key: &str) -> Result<()> {
+        fs::remove_file(self.path(key)?).await
+    }
+}
+
+/// 解析单段 `Range: bytes=start-end`, 返回 `(offset, len)`; `None` 表示范围无法满足
+pub fn parse_range(range: &str, size: u64) -> Option<(u64, u64)> {
+    let spec = range.trim().strip_prefix("bytes=")?;
+    if spec.contains(',') {
+        return None;
+    }
+    let (start, end) = spec.split_once('-')?;
+    let last = size.checked_sub(1)?;
+    let (start, end) = match (start.trim(), end.trim()) {
+        ("", "") => return None,
+        ("", suffix) => match suffix.parse::<u64>().ok()? {
+            0 => return None,
+            suffix => (size.saturating_sub(suffix), last),
+        },
+        (start, "") => (start.parse().ok()?, last),
+        (start, end) => (start.parse().ok()?, end.parse::<u64>().ok()?.min(last)),
+    };
+    if start > end {
+        return None;
+    }
+    Some((start, end - start + 1))
+}
+
+#[cfg(test)]
+mod test {
+    use super::parse_range;
+
+    #[test]
+    pub fn test_range() {
+        assert_eq!(parse_range("bytes=0-99", 1000), Some((0, 100)));
+        assert_eq!(parse_range("bytes=900-", 1000), Some((900, 100)));
+        assert_eq!(parse_range("bytes=-100", 1000), Some((900, 100)));
+        assert_eq!(parse_range("bytes=990-2000", 1000), Some((990, 10)));
+        assert_eq!(parse_range("bytes=-2000", 1000), Some((0, 1000)));
+        assert!(parse_range("bytes=1000-", 1000).is_none());
+        assert!(parse_range("bytes=5-1", 1000).is_none());
+        assert!(parse_range("bytes=0-1,5-9", 1000).is_none());
+        assert!(parse_range("items=0-1", 1000).is_none());
+    }
+}
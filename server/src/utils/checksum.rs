@@ -0,0 +1,106 @@
+use crypto::{digest::Digest, md5::Md5, sha2::Sha256};
+
+/// 固件文件摘要, 均为小写十六进制
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Digests {
+    pub sha256: String,
+    /// 旧版 bootloader 使用
+    pub crc32: String,
+    pub md5: String,
+}
+
+impl Digests {
+    pub fn of(data: &[u8]) -> Digests {
+        let mut hasher = DigestHasher::new();
+        hasher.update(data);
+        hasher.finish()
+    }
+}
+
+/// 分段计算摘要, 文件无需一次读入内存
+pub struct DigestHasher {
+    sha256: Sha256,
+    md5: Md5,
+    crc32: u32,
+}
+
+impl DigestHasher {
+    pub fn new() -> Self {
+        DigestHasher {
+            sha256: Sha256::new(),
+            md5: Md5::new(),
+            crc32: !0,
+        }
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        self.sha256.input(data);
+        self.md5.input(data);
+        self.crc32 = crc32_update(self.crc32, data);
+    }
+
+    pub fn finish(mut self) -> Digests {
+        Digests {
+            sha256: self.sha256.result_str(),
+            crc32: format!("{:08x}", !self.crc32),
+            md5: self.md5.result_str(),
+        }
+    }
+}
+
+impl Default for DigestHasher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+lazy_static! {
+    static ref CRC32_TABLE: [u32; 256] = {
+        let mut table = [0u32; 256];
+        for (i, entry) in table.iter_mut().enumerate() {
+            let mut c = i as u32;
+            for _ in 0..8 {
+                c = if c & 1 == 1 {
+                    0xedb8_8320 ^ (c >> 1)
+                } else {
+                    c >> 1
+                };
+            }
+            *entry = c;
+        }
+        table
+    };
+}
+
+/// CRC-32 (IEEE 802.3)
+pub fn crc32(data: &[u8]) -> u32 {
+    !crc32_update(!0, data)
+}
+
+fn crc32_update(crc: u32, data: &[u8]) -> u32 {
+    data.iter().fold(crc, |crc, b| {
+        CRC32_TABLE[((crc ^ *b as u32) & 0xff) as usize] ^ (crc >> 8)
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::{DigestHasher, Digests};
+
+    #[test]
+    pub fn test_digests() {
+        let d = Digests::of(b"hello world");
+        assert_eq!(
+            d.sha256,
+            "b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9"
+        );
+        assert_eq!(d.crc32, "0d4a1185");
+        assert_eq!(d.md5, "5eb63bbbe01eeed093cb22bb8f5acdc3");
+        assert_eq!(Digests::of(b"").crc32, "00000000");
+
+        let mut hasher = DigestHasher::new();
+        hasher.update(b"hello");
+        hasher.update(b" world");
+        assert_eq!(hasher.finish(), d);
+    }
+}
@@ -1,41 +1,106 @@
 use chrono::Utc;
 use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, Validation};
+use rand::{distributions::Alphanumeric, Rng};
 use serde::{Deserialize, Serialize};
 
-use crate::domain::vo::{VoUser, Token};
+use crate::{
+    config::config,
+    domain::vo::{Token, VoUser},
+};
 
-const TOKEN_KEY: &[u8] = "token_key".as_bytes();
+fn token_key() -> &'static [u8] {
+    config().auth.secret.as_bytes()
+}
 
 #[derive(Deserialize, Serialize)]
 struct Claims {
     pub user: VoUser,
     pub is_refresh: bool,
     pub exp: i64,
+    /// 刷新令牌的 id 及所属令牌族
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub jti: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub family: Option<String>,
+}
+
+/// 两步登录中, 密码校验通过后签发的挑战令牌
+#[derive(Deserialize, Serialize)]
+struct ChallengeClaims {
+    pub mfa: i32,
+    pub exp: i64,
 }
 
-fn gen_token(user: &VoUser, is_refresh: bool) -> String {
+/// 挑战令牌有效期, 秒
+pub const CHALLENGE_TTL: i64 = 300;
+
+/// 已校验签名的刷新令牌
+pub struct RefreshClaims {
+    pub user: VoUser,
+    pub jti: String,
+    pub family: String,
+}
+
+fn gen_token(user: &VoUser, refresh: Option<(&str, &str)>) -> String {
     let now = Utc::now().timestamp();
     let claims = Claims {
         user: user.clone(),
-        is_refresh,
-        exp: if is_refresh {
-            now + 60 * 60 * 24 * 7
+        is_refresh: refresh.is_some(),
+        exp: if refresh.is_some() {
+            now + config().auth.refresh_ttl
         } else {
-            now + 7200
+            now + config().auth.access_ttl
         },
+        jti: refresh.map(|(jti, _)| jti.to_string()),
+        family: refresh.map(|(_, family)| family.to_string()),
+    };
+    encode(
+        &Header::default(),
+        &claims,
+        &EncodingKey::from_secret(token_key()),
+    )
+    .unwrap()
+}
+
+/// 随机 id, 用于刷新令牌的 `jti` 和令牌族
+pub fn gen_token_id() -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(32)
+        .map(char::from)
+        .collect()
+}
+
+/// 签发访问令牌和刷新令牌, 刷新令牌需由调用方登记
+pub fn gen_user_token(user: VoUser, jti: &str, family: &str) -> Token {
+    let access_token = gen_token(&user, None);
+    let refresh_token = gen_token(&user, Some((jti, family)));
+    Token::new(access_token, refresh_token, user)
+}
+
+/// 签发挑战令牌, 只能用于换取正式令牌
+pub fn gen_challenge_token(user_id: i32) -> String {
+    let claims = ChallengeClaims {
+        mfa: user_id,
+        exp: Utc::now().timestamp() + CHALLENGE_TTL,
     };
     encode(
         &Header::default(),
         &claims,
-        &EncodingKey::from_secret(TOKEN_KEY),
+        &EncodingKey::from_secret(token_key()),
     )
     .unwrap()
 }
 
-pub fn gen_user_token(user: VoUser) -> Token {
-    let access_token = gen_token(&user, false);
-    // let refresh_token = gen_token(&user, true);
-    Token::new(access_token, user)
+/// 校验挑战令牌, 返回用户 id
+pub fn validate_challenge_token(token: &str) -> Option<i32> {
+    decode::<ChallengeClaims>(
+        token,
+        &DecodingKey::from_secret(token_key()),
+        &Validation::default(),
+    )
+    .ok()
+    .map(|c| c.claims.mfa)
 }
 
 pub fn validate_token(token: &str) -> Option<VoUser> {
@@ -44,7 +109,7 @@ pub fn validate_token(token: &str) -> Option<VoUser> {
     }
     match decode::<Claims>(
         token,
-        &DecodingKey::from_secret(TOKEN_KEY),
+        &DecodingKey::from_secret(token_key()),
         &Validation::default(),
     ) {
         Ok(c) if !c.claims.is_refresh => Some(c.claims.user),
@@ -52,13 +117,50 @@ pub fn validate_token(token: &str) -> Option<VoUser> {
     }
 }
 
-pub fn validate_refresh_token(token: &str) -> Option<VoUser> {
+pub fn validate_refresh_token(token: &str) -> Option<RefreshClaims> {
     match decode::<Claims>(
         token,
-        &DecodingKey::from_secret(TOKEN_KEY),
+        &DecodingKey::from_secret(token_key()),
         &Validation::default(),
     ) {
-        Ok(c) if c.claims.is_refresh => Some(c.claims.user),
+        Ok(c) if c.claims.is_refresh => Some(RefreshClaims {
+            user: c.claims.user,
+            jti: c.claims.jti?,
+            family: c.claims.family?,
+        }),
         _ => None,
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::{
+        gen_challenge_token, gen_user_token, validate_challenge_token, validate_refresh_token,
+        validate_token,
+    };
+    use crate::domain::vo::{VoRole, VoUser};
+
+    #[test]
+    pub fn test_refresh_token() {
+        let user = VoUser {
+            id: 1,
+            name: "admin".to_string(),
+            ticker: 0,
+            role: VoRole::Admin,
+        };
+        let token = gen_user_token(user, "jti", "family");
+        let claims = validate_refresh_token(&token.refresh_token).unwrap();
+        assert_eq!(
+            (claims.jti.as_str(), claims.family.as_str()),
+            ("jti", "family")
+        );
+        assert!(validate_token(&token.refresh_token).is_none());
+        assert!(validate_refresh_token(&token.access_token).is_none());
+        assert_eq!(validate_token(&token.access_token).unwrap().id, 1);
+
+        let challenge = gen_challenge_token(1);
+        assert_eq!(validate_challenge_token(&challenge), Some(1));
+        assert!(validate_token(&challenge).is_none());
+        assert!(validate_challenge_token(&token.access_token).is_none());
+    }
+}
@@ -0,0 +1,153 @@
+use std::cmp::Ordering;
+
+/// 规范化 BCP-47 语言标签的大小写, 如 `zh_hant_tw` 转为 `zh-Hant-TW`, 格式错误返回 `None`
+pub fn normalize(tag: &str) -> Option<String> {
+    let mut subtags = tag.trim().split(['-', '_']);
+    let language = subtags.next()?;
+    if !(2..=3).contains(&language.len()) || !language.chars().all(|c| c.is_ascii_alphabetic()) {
+        return None;
+    }
+    let mut out = language.to_ascii_lowercase();
+    // 扩展和私有标签 (单字符之后) 全部小写
+    let mut extension = false;
+    for subtag in subtags {
+        if subtag.is_empty()
+            || subtag.len() > 8
+            || !subtag.chars().all(|c| c.is_ascii_alphanumeric())
+        {
+            return None;
+        }
+        out.push('-');
+        let alpha = subtag.chars().all(|c| c.is_ascii_alphabetic());
+        if subtag.len() == 1 {
+            extension = true;
+            out.push_str(&subtag.to_ascii_lowercase());
+        } else if !extension && alpha && subtag.len() == 4 {
+            out.push_str(&subtag[..1].to_ascii_uppercase());
+            out.push_str(&subtag[1..].to_ascii_lowercase());
+        } else if !extension && alpha && subtag.len() == 2 {
+            out.push_str(&subtag.to_ascii_uppercase());
+        } else {
+            out.push_str(&subtag.to_ascii_lowercase());
+        }
+    }
+    Some(out)
+}
+
+/// 解析 `Accept-Language`, 按权重从高到低返回语言范围, 忽略 `q=0` 和格式错误的项
+pub fn accept_language(header: &str) -> Vec<String> {
+    let mut ranges: Vec<(String, f32)> = header
+        .split(',')
+        .filter_map(|item| {
+            let mut parts = item.split(';');
+            let range = parts.next()?.trim();
+            let q = match parts.find_map(|p| p.trim().strip_prefix("q=")) {
+                Some(q) => q.trim().parse::<f32>().ok()?,
+                None => 1.0,
+            };
+            if q <= 0.0 {
+                return None;
+            }
+            let range = match range {
+                "*" => range.to_string(),
+                _ => normalize(range)?,
+            };
+            Some((range, q))
+        })
+        .collect();
+    // 稳定排序, 权重相同时保持原顺序
+    ranges.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(Ordering::Equal));
+    ranges.into_iter().map(|(range, _)| range).collect()
+}
+
+/// 在 `available` 中为 `ranges` 挑选语言, 返回下标
+///
+/// 按 RFC 4647 lookup 依次尝试每个范围及其逐级截短的前缀 (`zh-Hant-TW` → `zh-Hant` → `zh`),
+/// 仍不匹配时取同一主语言的任意标签 (`en` 匹配 `en-US`), 再尝试下一个范围
+pub fn negotiate<S: AsRef<str>>(ranges: &[String], available: &[S]) -> Option<usize> {
+    let find = |tag: &str| {
+        available
+            .iter()
+            .position(|a| a.as_ref().eq_ignore_ascii_case(tag))
+    };
+    for range in ranges {
+        if range == "*" {
+            if !available.is_empty() {
+                return Some(0);
+            }
+            continue;
+        }
+        let mut tag = range.as_str();
+        loop {
+            if let Some(i) = find(tag) {
+                return Some(i);
+            }
+            match tag.rfind('-') {
+                Some(end) => tag = &tag[..end],
+                None => break,
+            }
+            // 截短后以单字符扩展结尾时一并去掉
+            if tag.len() >= 2 && tag.as_bytes()[tag.len() - 2] == b'-' {
+                tag = &tag[..tag.len() - 2];
+            }
+        }
+        let primary = primary_language(range);
+        if let Some(i) = available
+            .iter()
+            .position(|a| primary_language(a.as_ref()).eq_ignore_ascii_case(primary))
+        {
+            return Some(i);
+        }
+    }
+    None
+}
+
+fn primary_language(tag: &str) -> &str {
+    tag.split('-').next().unwrap_or(tag)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{accept_language, negotiate, normalize};
+
+    #[test]
+    pub fn test_normalize() {
+        assert_eq!(normalize("zh_hant_tw").as_deref(), Some("zh-Hant-TW"));
+        assert_eq!(normalize("EN-us").as_deref(), Some("en-US"));
+        assert_eq!(normalize("es-419").as_deref(), Some("es-419"));
+        assert_eq!(
+            normalize("de-CH-x-Phonebk").as_deref(),
+            Some("de-CH-x-phonebk")
+        );
+        assert_eq!(normalize("ja").as_deref(), Some("ja"));
+        assert_eq!(normalize(""), None);
+        assert_eq!(normalize("english"), None);
+        assert_eq!(normalize("en--US"), None);
+        assert_eq!(normalize("en-US!"), None);
+    }
+
+    #[test]
+    pub fn test_accept_language() {
+        assert_eq!(
+            accept_language("fr-CH, fr;q=0.9, en;q=0.8, de;q=0.7, *;q=0.5"),
+            vec!["fr-CH", "fr", "en", "de", "*"]
+        );
+        assert_eq!(accept_language("en;q=0.5, ko, ja;q=0"), vec!["ko", "en"]);
+        assert_eq!(accept_language("zh_cn, bad tag, en;q=x"), vec!["zh-CN"]);
+        assert!(accept_language("").is_empty());
+    }
+
+    #[test]
+    pub fn test_negotiate() {
+        let available = ["en", "ko", "es", "zh-Hant"];
+        let ranges = |h: &str| accept_language(h);
+        assert_eq!(negotiate(&ranges("ko-KR, en;q=0.5"), &available), Some(1));
+        assert_eq!(negotiate(&ranges("zh-Hant-TW"), &available), Some(3));
+        assert_eq!(negotiate(&ranges("zh-CN"), &available), Some(3));
+        assert_eq!(negotiate(&ranges("ja, es-MX;q=0.8"), &available), Some(2));
+        assert_eq!(negotiate(&ranges("ja, *;q=0.1"), &available), Some(0));
+        assert_eq!(negotiate(&ranges("ja"), &available), None);
+        assert_eq!(negotiate(&ranges("en-US"), &["en-GB", "en"]), Some(1));
+        assert_eq!(negotiate(&ranges("en"), &["de", "en-GB"]), Some(1));
+    }
+}
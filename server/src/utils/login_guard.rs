@@ -0,0 +1,129 @@
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use crate::config::LockoutConfig;
+
+/// 连续登录失败
+struct Failures {
+    count: u32,
+    last: Instant,
+    blocked_until: Option<Instant>,
+    locked: bool,
+}
+
+/// 登录被拒绝, `locked` 表示已达到锁定次数而非退避中
+#[derive(Debug, PartialEq, Eq)]
+pub struct Blocked {
+    pub retry_after: Duration,
+    pub locked: bool,
+}
+
+/// 进程内的登录失败计数, 按账号或 IP 分别退避和锁定
+#[derive(Default)]
+pub struct LoginGuard {
+    entries: Mutex<HashMap<String, Failures>>,
+}
+
+impl Failures {
+    fn blocked(&self, now: Instant) -> Option<Blocked> {
+        match self.blocked_until {
+            Some(until) if until > now => Some(Blocked {
+                retry_after: until - now,
+                locked: self.locked,
+            }),
+            _ => None,
+        }
+    }
+}
+
+impl LoginGuard {
+    /// 检查是否允许尝试登录
+    pub fn check(&self, key: &str) -> Result<(), Blocked> {
+        let entries = self.entries.lock().unwrap();
+        match entries.get(key).and_then(|f| f.blocked(Instant::now())) {
+            Some(blocked) => Err(blocked),
+            None => Ok(()),
+        }
+    }
+
+    /// 记录一次失败, 达到 `max_failures` 时锁定, 返回新的限制
+    pub fn fail(&self, policy: &LockoutConfig, key: &str, max_failures: u32) -> Option<Blocked> {
+        let now = Instant::now();
+        let reset_after = Duration::from_secs(policy.reset_after);
+        let mut entries = self.entries.lock().unwrap();
+        entries.retain(|_, f| f.blocked(now).is_some() || now - f.last < reset_after);
+        let failures = entries.entry(key.to_string()).or_insert(Failures {
+            count: 0,
+            last: now,
+            blocked_until: None,
+            locked: false,
+        });
+        failures.count += 1;
+        failures.last = now;
+        if failures.count >= max_failures {
+            failures.locked = true;
+            failures.blocked_until = Some(now + Duration::from_secs(policy.lock_duration));
+        } else if failures.count > policy.free_attempts {
+            let exp = (failures.count - policy.free_attempts - 1).min(31);
+            let delay = policy
+                .base_delay
+                .saturating_mul(1 << exp)
+                .min(policy.max_delay);
+            failures.blocked_until = Some(now + Duration::from_secs(delay));
+        }
+        failures.blocked(now)
+    }
+
+    /// 登录成功或管理员解锁时清除计数
+    pub fn reset(&self, key: &str) {
+        self.entries.lock().unwrap().remove(key);
+    }
+
+    pub fn is_locked(&self, key: &str) -> bool {
+        matches!(self.check(key), Err(Blocked { locked: true, .. }))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::LoginGuard;
+    use crate::config::LockoutConfig;
+
+    #[test]
+    pub fn test_login_guard() {
+        let policy = LockoutConfig {
+            free_attempts: 2,
+            base_delay: 10,
+            max_delay: 15,
+            max_failures: 5,
+            ..LockoutConfig::default()
+        };
+        let guard = LoginGuard::default();
+        assert!(guard.fail(&policy, "a", 5).is_none());
+        assert!(guard.fail(&policy, "a", 5).is_none());
+        assert!(guard.check("a").is_ok());
+
+        let blocked = guard.fail(&policy, "a", 5).unwrap();
+        assert!(!blocked.locked);
+        assert!(blocked.retry_after <= Duration::from_secs(10));
+        assert!(guard.check("a").is_err());
+        assert!(guard.check("b").is_ok());
+
+        let blocked = guard.fail(&policy, "a", 5).unwrap();
+        assert!(blocked.retry_after > Duration::from_secs(10));
+        assert!(blocked.retry_after <= Duration::from_secs(15));
+        assert!(!guard.is_locked("a"));
+
+        let blocked = guard.fail(&policy, "a", 5).unwrap();
+        assert!(blocked.locked);
+        assert!(guard.is_locked("a"));
+
+        guard.reset("a");
+        assert!(guard.check("a").is_ok());
+    }
+}
@@ -1,2 +1,11 @@
+pub mod blob_store;
+pub mod checksum;
 pub mod jwt;
-pub mod sql_helper;
\ No newline at end of file
+pub mod locale;
+pub mod login_guard;
+pub mod password_policy;
+pub mod query_builder;
+pub mod signing;
+pub mod totp;
+pub mod ttl_cache;
+pub mod version;
@@ -0,0 +1,124 @@
+use std::{collections::HashSet, fs, io, path::Path};
+
+use crypto::{digest::Digest, sha1::Sha1};
+use once_cell::sync::OnceCell;
+
+use crate::domain::vo::VoPasswordRule;
+
+/// bcrypt 只使用前 72 字节
+const MAX_LENGTH: usize = 72;
+
+/// 密码策略
+///
+/// 网页端提交的是密码的 sha1 摘要, 黑名单同时保存原文和 sha1, 两种提交方式都能匹配
+#[derive(Default)]
+pub struct PasswordPolicy {
+    min_length: usize,
+    denylist: HashSet<String>,
+    denylist_len: usize,
+}
+
+static POLICY: OnceCell<PasswordPolicy> = OnceCell::new();
+
+/// 启动时设置全局密码策略
+pub fn init_password_policy(policy: PasswordPolicy) {
+    let _ = POLICY.set(policy);
+}
+
+pub fn password_policy() -> &'static PasswordPolicy {
+    POLICY.get_or_init(PasswordPolicy::default)
+}
+
+fn sha1_hex(s: &str) -> String {
+    let mut sha1 = Sha1::new();
+    sha1.input_str(s);
+    sha1.result_str()
+}
+
+impl PasswordPolicy {
+    pub fn new<I: IntoIterator<Item = String>>(min_length: usize, denylist: I) -> Self {
+        let mut set = HashSet::new();
+        let mut len = 0;
+        for password in denylist {
+            set.insert(sha1_hex(&password));
+            set.insert(password.to_lowercase());
+            len += 1;
+        }
+        PasswordPolicy {
+            min_length,
+            denylist: set,
+            denylist_len: len,
+        }
+    }
+
+    /// 读取黑名单文件, 每行一个密码, 忽略空行和 `#` 开头的行
+    pub fn load(min_length: usize, denylist: Option<&Path>) -> io::Result<Self> {
+        let content = match denylist {
+            Some(path) => fs::read_to_string(path)?,
+            None => String::new(),
+        };
+        let passwords = content
+            .lines()
+            .map(str::trim)
+            .filter(|l| !l.is_empty() && !l.starts_with('#'))
+            .map(String::from);
+        Ok(PasswordPolicy::new(min_length, passwords))
+    }
+
+    pub fn denylist_len(&self) -> usize {
+        self.denylist_len
+    }
+
+    /// 检查新密码, 返回违反的规则, `old_hash` 为旧密码的 bcrypt 摘要
+    pub fn check(&self, password: &str, old_hash: Option<&str>) -> Vec<VoPasswordRule> {
+        let mut rules = Vec::new();
+        let len = password.chars().count();
+        if len < self.min_length.max(1) {
+            rules.push(VoPasswordRule::TooShort);
+        }
+        if password.len() > MAX_LENGTH {
+            rules.push(VoPasswordRule::TooLong);
+        }
+        if self.denylist.contains(&password.to_lowercase()) {
+            rules.push(VoPasswordRule::Common);
+        }
+        if let Some(old_hash) = old_hash {
+            if matches!(bcrypt::verify(password, old_hash), Ok(true)) {
+                rules.push(VoPasswordRule::SameAsOld);
+            }
+        }
+        rules
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{sha1_hex, PasswordPolicy};
+    use crate::domain::vo::VoPasswordRule;
+
+    #[test]
+    pub fn test_password_policy() {
+        let policy = PasswordPolicy::new(8, vec!["password1".to_string()]);
+        assert_eq!(policy.denylist_len(), 1);
+        assert_eq!(policy.check("short", None), vec![VoPasswordRule::TooShort]);
+        assert_eq!(
+            policy.check("Password1", None),
+            vec![VoPasswordRule::Common]
+        );
+        assert_eq!(
+            policy.check(&sha1_hex("password1"), None),
+            vec![VoPasswordRule::Common]
+        );
+        assert_eq!(
+            policy.check(&"a".repeat(73), None),
+            vec![VoPasswordRule::TooLong]
+        );
+
+        let old = bcrypt::hash("correct horse", 4).unwrap();
+        assert_eq!(
+            policy.check("correct horse", Some(&old)),
+            vec![VoPasswordRule::SameAsOld]
+        );
+        assert!(policy.check("battery staple", Some(&old)).is_empty());
+    }
+}
@@ -0,0 +1,858 @@
+use std::fmt::Write;
+
+use chrono::{DateTime, Utc};
+use sqlx::{
+    query::{Query, QueryAs, QueryScalar},
+    sqlite::{Sqlite, SqliteArguments, SqliteRow},
+    Arguments, Executor, FromRow,
+};
+
+/// 绑定参数
+#[derive(Debug, Clone, PartialEq)]
+pub enum SqlValue {
+    Null,
+    Bool(bool),
+    Int(i64),
+    Real(f64),
+    Text(String),
+    Time(DateTime<Utc>),
+}
+
+impl SqlValue {
+    fn add_to(&self, args: &mut SqliteArguments<'_>) {
+        match self {
+            SqlValue::Null => args.add(Option::<i64>::None),
+            SqlValue::Bool(v) => args.add(*v),
+            SqlValue::Int(v) => args.add(*v),
+            SqlValue::Real(v) => args.add(*v),
+            SqlValue::Text(v) => args.add(v.clone()),
+            SqlValue::Time(v) => args.add(*v),
+        }
+    }
+}
+
+impl From<bool> for SqlValue {
+    fn from(v: bool) -> Self {
+        SqlValue::Bool(v)
+    }
+}
+
+impl From<i32> for SqlValue {
+    fn from(v: i32) -> Self {
+        SqlValue::Int(v as i64)
+    }
+}
+
+impl From<i64> for SqlValue {
+    fn from(v: i64) -> Self {
+        SqlValue::Int(v)
+    }
+}
+
+impl From<f64> for SqlValue {
+    fn from(v: f64) -> Self {
+        SqlValue::Real(v)
+    }
+}
+
+impl From<&str> for SqlValue {
+    fn from(v: &str) -> Self {
+        SqlValue::Text(v.to_string())
+    }
+}
+
+impl From<String> for SqlValue {
+    fn from(v: String) -> Self {
+        SqlValue::Text(v)
+    }
+}
+
+impl From<&String> for SqlValue {
+    fn from(v: &String) -> Self {
+        SqlValue::Text(v.clone())
+    }
+}
+
+impl From<DateTime<Utc>> for SqlValue {
+    fn from(v: DateTime<Utc>) -> Self {
+        SqlValue::Time(v)
+    }
+}
+
+impl<T: Into<SqlValue>> From<Option<T>> for SqlValue {
+    fn from(v: Option<T>) -> Self {
+        v.map_or(SqlValue::Null, Into::into)
+    }
+}
+
+/// WHERE 条件, `and` / `or` 可任意嵌套
+#[derive(Debug, Clone)]
+pub enum Cond {
+    Cmp(String, &'static str, SqlValue),
+    In(String, Vec<SqlValue>, bool),
+    InQuery(String, Box<QueryBuilder>),
+    Null(String, bool),
+    And(Vec<Cond>),
+    Or(Vec<Cond>),
+    Raw(String, Vec<SqlValue>),
+}
+
+impl Cond {
+    pub fn eq(column: impl Into<String>, value: impl Into<SqlValue>) -> Self {
+        Cond::Cmp(column.into(), "=", value.into())
+    }
+
+    pub fn ne(column: impl Into<String>, value: impl Into<SqlValue>) -> Self {
+        Cond::Cmp(column.into(), "<>", value.into())
+    }
+
+    pub fn lt(column: impl Into<String>, value: impl Into<SqlValue>) -> Self {
+        Cond::Cmp(column.into(), "<", value.into())
+    }
+
+    pub fn le(column: impl Into<String>, value: impl Into<SqlValue>) -> Self {
+        Cond::Cmp(column.into(), "<=", value.into())
+    }
+
+    pub fn gt(column: impl Into<String>, value: impl Into<SqlValue>) -> Self {
+        Cond::Cmp(column.into(), ">", value.into())
+    }
+
+    pub fn ge(column: impl Into<String>, value: impl Into<SqlValue>) -> Self {
+        Cond::Cmp(column.into(), ">=", value.into())
+    }
+
+    /// `pattern` 原样绑定, 由调用方写入 `%` 和 `_`
+    pub fn like(column: impl Into<String>, pattern: impl Into<String>) -> Self {
+        Cond::Cmp(column.into(), "LIKE", SqlValue::Text(pattern.into()))
+    }
+
+    /// 包含 `text`, 其中的 `%` `_` 按普通字符匹配
+    pub fn contains(column: impl Into<String>, text: &str) -> Self {
+        let mut pattern = String::with_capacity(text.len() + 2);
+        pattern.push('%');
+        for c in text.chars() {
+            if matches!(c, '%' | '_' | '\\') {
+                pattern.push('\\');
+            }
+            pattern.push(c);
+        }
+        pattern.push('%');
+        Cond::Raw(
+            format!("{} LIKE ? ESCAPE '\\'", column.into()),
+            vec![SqlValue::Text(pattern)],
+        )
+    }
+
+    /// 列表为空时恒为假
+    pub fn is_in<V: Into<SqlValue>>(
+        column: impl Into<String>,
+        values: impl IntoIterator<Item = V>,
+    ) -> Self {
+        Cond::In(
+            column.into(),
+            values.into_iter().map(Into::into).collect(),
+            false,
+        )
+    }
+
+    /// 列表为空时恒为真
+    pub fn not_in<V: Into<SqlValue>>(
+        column: impl Into<String>,
+        values: impl IntoIterator<Item = V>,
+    ) -> Self {
+        Cond::In(
+            column.into(),
+            values.into_iter().map(Into::into).collect(),
+            true,
+        )
+    }
+
+    /// `column IN (子查询)`
+    pub fn in_query(column: impl Into<String>, query: QueryBuilder) -> Self {
+        Cond::InQuery(column.into(), Box::new(query))
+    }
+
+    pub fn is_null(column: impl Into<String>) -> Self {
+        Cond::Null(column.into(), false)
+    }
+
+    pub fn not_null(column: impl Into<String>) -> Self {
+        Cond::Null(column.into(), true)
+    }
+
+    /// 空列表恒为真
+    pub fn and(conds: impl IntoIterator<Item = Cond>) -> Self {
+        Cond::And(conds.into_iter().collect())
+    }
+
+    /// 空列表恒为假
+    pub fn or(conds: impl IntoIterator<Item = Cond>) -> Self {
+        Cond::Or(conds.into_iter().collect())
+    }
+
+    /// 任意表达式, `?` 与 `values` 按顺序对应
+    pub fn raw(sql: impl Into<String>, values: Vec<SqlValue>) -> Self {
+        Cond::Raw(sql.into(), values)
+    }
+
+    fn write(&self, sql: &mut String, values: &mut Vec<SqlValue>) {
+        match self {
+            Cond::Cmp(column, op, value) => {
+                let _ = write!(sql, "{} {} ?", column, op);
+                values.push(value.clone());
+            }
+            Cond::In(_, list, negated) if list.is_empty() => {
+                sql.push_str(if *negated { "1 = 1" } else { "1 = 0" });
+            }
+            Cond::In(column, list, negated) => {
+                let marks = vec!["?"; list.len()].join(", ");
+                let not = if *negated { "NOT " } else { "" };
+                let _ = write!(sql, "{} {}IN ({})", column, not, marks);
+                values.extend(list.iter().cloned());
+            }
+            Cond::InQuery(column, query) => {
+                let _ = write!(sql, "{} IN (", column);
+                query.write(sql, values);
+                sql.push(')');
+            }
+            Cond::Null(column, negated) => {
+                let not = if *negated { "NOT " } else { "" };
+                let _ = write!(sql, "{} IS {}NULL", column, not);
+            }
+            Cond::And(conds) => write_group(sql, values, conds, " AND ", "1 = 1"),
+            Cond::Or(conds) => write_group(sql, values, conds, " OR ", "1 = 0"),
+            Cond::Raw(raw, list) => {
+                let _ = write!(sql, "({})", raw);
+                values.extend(list.iter().cloned());
+            }
+        }
+    }
+}
+
+fn write_group(
+    sql: &mut String,
+    values: &mut Vec<SqlValue>,
+    conds: &[Cond],
+    sep: &str,
+    empty: &str,
+) {
+    match conds {
+        [] => sql.push_str(empty),
+        [cond] => cond.write(sql, values),
+        _ => {
+            sql.push('(');
+            for (i, cond) in conds.iter().enumerate() {
+                if i > 0 {
+                    sql.push_str(sep);
+                }
+                cond.write(sql, values);
+            }
+            sql.push(')');
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Kind {
+    Select(String),
+    Insert,
+    Update,
+    Delete,
+}
+
+/// SQL 构造器, 参数与语句一起保存, 由 `build` 生成 [`Statement`]
+#[derive(Debug, Clone)]
+pub struct QueryBuilder {
+    kind: Kind,
+    table: String,
+    /// 表为子查询时的参数
+    table_values: Vec<SqlValue>,
+    /// INSERT 的列值或 UPDATE 的 SET
+    sets: Vec<(String, SqlValue)>,
+    conflict: Option<(String, Vec<String>)>,
+    conds: Vec<Cond>,
+    group_by: Option<String>,
+    order_by: Vec<String>,
+    limit: Option<i64>,
+    offset: Option<i64>,
+    returning: Option<String>,
+}
+
+impl QueryBuilder {
+    fn new(kind: Kind, table: &str) -> Self {
+        QueryBuilder {
+            kind,
+            table: table.to_string(),
+            table_values: Vec::new(),
+            sets: Vec::new(),
+            conflict: None,
+            conds: Vec::new(),
+            group_by: None,
+            order_by: Vec::new(),
+            limit: None,
+            offset: None,
+            returning: None,
+        }
+    }
+
+    pub fn select(table: &str, columns: &str) -> Self {
+        QueryBuilder::new(Kind::Select(columns.trim().to_string()), table)
+    }
+
+    /// `SELECT COUNT(*)`
+    pub fn count(table: &str) -> Self {
+        QueryBuilder::select(table, "COUNT(*)")
+    }
+
+    /// `SELECT COUNT(DISTINCT column)`
+    pub fn count_distinct(table: &str, column: &str) -> Self {
+        QueryBuilder::select(table, &format!("COUNT(DISTINCT {})", column))
+    }
+
+    pub fn insert(table: &str) -> Self {
+        QueryBuilder::new(Kind::Insert, table)
+    }
+
+    pub fn update(table: &str) -> Self {
+        QueryBuilder::new(Kind::Update, table)
+    }
+
+    pub fn delete(table: &str) -> Self {
+        QueryBuilder::new(Kind::Delete, table)
+    }
+
+    /// 同样条件下的总行数, 忽略排序和分页; 有 GROUP BY 时统计分组数
+    pub fn to_count(&self) -> Self {
+        let mut query = self.clone();
+        query.order_by.clear();
+        query.limit = None;
+        query.offset = None;
+        if query.group_by.is_none() {
+            query.kind = Kind::Select("COUNT(*)".to_string());
+            return query;
+        }
+        let inner = query.build();
+        let mut count = QueryBuilder::count(&format!("({})", inner.sql));
+        count.table_values = inner.values;
+        count
+    }
+
+    /// INSERT 的列值
+    pub fn value(mut self, column: &str, value: impl Into<SqlValue>) -> Self {
+        self.sets.push((column.to_string(), value.into()));
+        self
+    }
+
+    /// UPDATE 的 SET
+    pub fn set(self, column: &str, value: impl Into<SqlValue>) -> Self {
+        self.value(column, value)
+    }
+
+    /// 冲突时以新值更新 `columns`
+    pub fn on_conflict(mut self, target: &str, columns: &[&str]) -> Self {
+        self.conflict = Some((
+            target.to_string(),
+            columns.iter().map(|c| c.to_string()).collect(),
+        ));
+        self
+    }
+
+    /// 多次调用以 AND 连接
+    pub fn and_where(mut self, cond: Cond) -> Self {
+        self.conds.push(cond);
+        self
+    }
+
+    /// 与已有条件以 OR 连接
+    pub fn or_where(mut self, cond: Cond) -> Self {
+        let left = Cond::And(std::mem::take(&mut self.conds));
+        self.conds.push(Cond::Or(vec![left, cond]));
+        self
+    }
+
+    pub fn group_by(mut self, columns: &str) -> Self {
+        self.group_by = Some(columns.to_string());
+        self
+    }
+
+    pub fn order_asc(mut self, column: &str) -> Self {
+        self.order_by.push(format!("{} ASC", column));
+        self
+    }
+
+    pub fn order_desc(mut self, column: &str) -> Self {
+        self.order_by.push(format!("{} DESC", column));
+        self
+    }
+
+    pub fn limit(mut self, limit: i64) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+
+    pub fn offset(mut self, offset: i64) -> Self {
+        self.offset = Some(offset);
+        self
+    }
+
+    /// 分页, `page` 从 1 开始
+    pub fn page(self, page: i32, page_size: i32) -> Self {
+        let offset = (page.max(1) - 1) as i64 * page_size as i64;
+        self.limit(page_size as i64).offset(offset)
+    }
+
+    pub fn returning(mut self, columns: &str) -> Self {
+        self.returning = Some(columns.to_string());
+        self
+    }
+
+    pub fn build(&self) -> Statement {
+        let mut sql = String::new();
+        let mut values = Vec::new();
+        self.write(&mut sql, &mut values);
+        Statement { sql, values }
+    }
+
+    fn write(&self, sql: &mut String, values: &mut Vec<SqlValue>) {
+        match &self.kind {
+            Kind::Select(columns) => {
+                let _ = write!(sql, "SELECT {} FROM {}", columns, self.table);
+                values.extend(self.table_values.iter().cloned());
+            }
+            Kind::Insert => {
+                let columns: Vec<&str> = self.sets.iter().map(|(c, _)| c.as_str()).collect();
+                let marks = vec!["?"; columns.len()].join(", ");
+                let _ = write!(
+                    sql,
+                    "INSERT INTO {} ({}) VALUES ({})",
+                    self.table,
+                    columns.join(", "),
+                    marks
+                );
+                values.extend(self.sets.iter().map(|(_, v)| v.clone()));
+                if let Some((target, columns)) = &self.conflict {
+                    let updates: Vec<String> = columns
+                        .iter()
+                        .map(|c| format!("{0} = excluded.{0}", c))
+                        .collect();
+                    let _ = write!(
+                        sql,
+                        " ON CONFLICT({}) DO UPDATE SET {}",
+                        target,
+                        updates.join(", ")
+                    );
+                }
+            }
+            Kind::Update => {
+                let sets: Vec<String> = self
+                    .sets
+                    .iter()
+                    .map(|(c, _)| format!("{} = ?", c))
+                    .collect();
+                let _ = write!(sql, "UPDATE {} SET {}", self.table, sets.join(", "));
+                values.extend(self.sets.iter().map(|(_, v)| v.clone()));
+            }
+            Kind::Delete => {
+                let _ = write!(sql, "DELETE FROM {}", self.table);
+            }
+        }
+        if !self.conds.is_empty() {
+            sql.push_str(" WHERE ");
+            for (i, cond) in self.conds.iter().enumerate() {
+                if i > 0 {
+                    sql.push_str(" AND ");
+                }
+                cond.write(sql, values);
+            }
+        }
+        if let Some(group_by) = &self.group_by {
+            let _ = write!(sql, " GROUP BY {}", group_by);
+        }
+        if !self.order_by.is_empty() {
+            let _ = write!(sql, " ORDER BY {}", self.order_by.join(", "));
+        }
+        if let Some(limit) = self.limit {
+            sql.push_str(" LIMIT ?");
+            values.push(SqlValue::Int(limit));
+        }
+        if let Some(offset) = self.offset {
+            if self.limit.is_none() {
+                sql.push_str(" LIMIT -1");
+            }
+            sql.push_str(" OFFSET ?");
+            values.push(SqlValue::Int(offset));
+        }
+        if let Some(returning) = &self.returning {
+            let _ = write!(sql, " RETURNING {}", returning);
+        }
+    }
+}
+
+/// 生成的语句和按顺序排列的参数
+#[derive(Debug, Clone, PartialEq)]
+pub struct Statement {
+    pub sql: String,
+    pub values: Vec<SqlValue>,
+}
+
+impl Statement {
+    fn arguments(&self) -> SqliteArguments<'_> {
+        let mut args = SqliteArguments::default();
+        for value in &self.values {
+            value.add_to(&mut args);
+        }
+        args
+    }
+
+    pub fn query(&self) -> Query<'_, Sqlite, SqliteArguments<'_>> {
+        sqlx::query_with(&self.sql, self.arguments())
+    }
+
+    pub fn query_as<T>(&self) -> QueryAs<'_, Sqlite, T, SqliteArguments<'_>>
+    where
+        T: for<'r> FromRow<'r, SqliteRow>,
+    {
+        sqlx::query_as_with(&self.sql, self.arguments())
+    }
+
+    pub fn query_scalar<T>(&self) -> QueryScalar<'_, Sqlite, T, SqliteArguments<'_>>
+    where
+        (T,): for<'r> FromRow<'r, SqliteRow>,
+    {
+        sqlx::query_scalar_with(&self.sql, self.arguments())
+    }
+
+    /// 执行 `RETURNING` 语句, 返回第一行的值
+    ///
+    /// `fetch_one` 读到第一行后语句不会执行完, 自动提交的事务要等语句重置后才提交,
+    /// 在此之前其它连接读不到新数据
+    pub async fn fetch_returning<'e, 'c: 'e, T, E>(&'e self, executor: E) -> Result<T, sqlx::Error>
+    where
+        (T,): for<'r> FromRow<'r, SqliteRow>,
+        T: Send + Unpin + 'e,
+        E: 'e + Executor<'c, Database = Sqlite>,
+    {
+        self.query_scalar()
+            .fetch_all(executor)
+            .await?
+            .into_iter()
+            .next()
+            .ok_or(sqlx::Error::RowNotFound)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Cond, QueryBuilder, SqlValue, Statement};
+
+    fn stmt(sql: &str, values: Vec<SqlValue>) -> Statement {
+        Statement {
+            sql: sql.to_string(),
+            values,
+        }
+    }
+
+    #[test]
+    pub fn test_select() {
+        let query = QueryBuilder::select("user", "id, name").build();
+        assert_eq!(query, stmt("SELECT id, name FROM user", vec![]));
+
+        let query = QueryBuilder::select("user", "id")
+            .and_where(Cond::eq("mail", "a@b.c"))
+            .and_where(Cond::ne("role", 1))
+            .and_where(Cond::ge("id", 10i64))
+            .build();
+        assert_eq!(
+            query,
+            stmt(
+                "SELECT id FROM user WHERE mail = ? AND role <> ? AND id >= ?",
+                vec!["a@b.c".into(), SqlValue::Int(1), SqlValue::Int(10)]
+            )
+        );
+    }
+
+    #[test]
+    pub fn test_and_or() {
+        let query = QueryBuilder::select("t", "id")
+            .and_where(Cond::or([
+                Cond::and([Cond::eq("a", 1), Cond::lt("b", 2)]),
+                Cond::gt("c", 3),
+            ]))
+            .and_where(Cond::le("d", 4))
+            .build();
+        assert_eq!(
+            query.sql,
+            "SELECT id FROM t WHERE ((a = ? AND b < ?) OR c > ?) AND d <= ?"
+        );
+        assert_eq!(query.values, vec![1.into(), 2.into(), 3.into(), 4.into()]);
+
+        let query = QueryBuilder::select("t", "id")
+            .and_where(Cond::eq("a", 1))
+            .and_where(Cond::eq("b", 2))
+            .or_where(Cond::eq("c", 3))
+            .build();
+        assert_eq!(
+            query.sql,
+            "SELECT id FROM t WHERE ((a = ? AND b = ?) OR c = ?)"
+        );
+
+        let query = QueryBuilder::select("t", "id")
+            .and_where(Cond::and([]))
+            .and_where(Cond::or([]))
+            .and_where(Cond::or([Cond::eq("a", 1)]))
+            .build();
+        assert_eq!(
+            query.sql,
+            "SELECT id FROM t WHERE 1 = 1 AND 1 = 0 AND a = ?"
+        );
+    }
+
+    #[test]
+    pub fn test_in() {
+        let query = QueryBuilder::select("t", "id")
+            .and_where(Cond::is_in("id", [1, 2, 3]))
+            .and_where(Cond::not_in("name", ["x"]))
+            .build();
+        assert_eq!(
+            query.sql,
+            "SELECT id FROM t WHERE id IN (?, ?, ?) AND name NOT IN (?)"
+        );
+        assert_eq!(query.values, vec![1.into(), 2.into(), 3.into(), "x".into()]);
+
+        let empty: [i32; 0] = [];
+        let query = QueryBuilder::select("t", "id")
+            .and_where(Cond::is_in("id", empty))
+            .and_where(Cond::not_in("id", empty))
+            .build();
+        assert_eq!(
+            query,
+            stmt("SELECT id FROM t WHERE 1 = 0 AND 1 = 1", vec![])
+        );
+
+        let sub = QueryBuilder::select("channel", "id").and_where(Cond::eq("name", "beta"));
+        let query = QueryBuilder::select("firm", "id")
+            .and_where(Cond::eq("hard_version", 1))
+            .and_where(Cond::in_query("channel_id", sub))
+            .build();
+        assert_eq!(
+            query,
+            stmt(
+                "SELECT id FROM firm WHERE hard_version = ? AND channel_id IN (SELECT id FROM channel WHERE name = ?)",
+                vec![1.into(), "beta".into()]
+            )
+        );
+    }
+
+    #[test]
+    pub fn test_null() {
+        let query = QueryBuilder::select("t", "id")
+            .and_where(Cond::is_null("a"))
+            .and_where(Cond::not_null("b"))
+            .and_where(Cond::eq("c", None::<i32>))
+            .build();
+        assert_eq!(
+            query.sql,
+            "SELECT id FROM t WHERE a IS NULL AND b IS NOT NULL AND c = ?"
+        );
+        assert_eq!(query.values, vec![SqlValue::Null]);
+    }
+
+    #[test]
+    pub fn test_like() {
+        let query = QueryBuilder::select("t", "id")
+            .and_where(Cond::like("name", "a%"))
+            .and_where(Cond::contains("desc", "50%_off"))
+            .build();
+        assert_eq!(
+            query.sql,
+            "SELECT id FROM t WHERE name LIKE ? AND (desc LIKE ? ESCAPE '\\')"
+        );
+        assert_eq!(query.values, vec!["a%".into(), "%50\\%\\_off%".into()]);
+
+        let query = QueryBuilder::select("t", "id")
+            .and_where(Cond::raw("a + b > ?", vec![1.into()]))
+            .build();
+        assert_eq!(
+            query,
+            stmt("SELECT id FROM t WHERE (a + b > ?)", vec![1.into()])
+        );
+    }
+
+    #[test]
+    pub fn test_order_page() {
+        let query = QueryBuilder::select("t", "id")
+            .and_where(Cond::eq("a", 1))
+            .order_desc("update_time")
+            .order_asc("id")
+            .page(3, 20)
+            .build();
+        assert_eq!(
+            query,
+            stmt(
+                "SELECT id FROM t WHERE a = ? ORDER BY update_time DESC, id ASC LIMIT ? OFFSET ?",
+                vec![1.into(), SqlValue::Int(20), SqlValue::Int(40)]
+            )
+        );
+        let query = QueryBuilder::select("t", "id").offset(5).build();
+        assert_eq!(query.sql, "SELECT id FROM t LIMIT -1 OFFSET ?");
+        let query = QueryBuilder::select("t", "id").page(0, 10).build();
+        assert_eq!(query.values, vec![SqlValue::Int(10), SqlValue::Int(0)]);
+    }
+
+    #[test]
+    pub fn test_count() {
+        let query = QueryBuilder::count("t").and_where(Cond::eq("a", 1)).build();
+        assert_eq!(query.sql, "SELECT COUNT(*) FROM t WHERE a = ?");
+        let query = QueryBuilder::count_distinct("t", "device_id").build();
+        assert_eq!(query.sql, "SELECT COUNT(DISTINCT device_id) FROM t");
+
+        let list = QueryBuilder::select("t", "id, name")
+            .and_where(Cond::eq("a", 1))
+            .order_desc("id")
+            .page(2, 10);
+        assert_eq!(
+            list.to_count().build(),
+            stmt("SELECT COUNT(*) FROM t WHERE a = ?", vec![1.into()])
+        );
+        let grouped = QueryBuilder::select("t", "firm_id")
+            .and_where(Cond::eq("a", 1))
+            .group_by("firm_id");
+        assert_eq!(
+            grouped.build().sql,
+            "SELECT firm_id FROM t WHERE a = ? GROUP BY firm_id"
+        );
+        assert_eq!(
+            grouped.to_count().build(),
+            stmt(
+                "SELECT COUNT(*) FROM (SELECT firm_id FROM t WHERE a = ? GROUP BY firm_id)",
+                vec![1.into()]
+            )
+        );
+    }
+
+    #[test]
+    pub fn test_insert() {
+        let query = QueryBuilder::insert("t")
+            .value("id", 1)
+            .value("name", "a")
+            .value("desc", None::<String>)
+            .build();
+        assert_eq!(
+            query,
+            stmt(
+                "INSERT INTO t (id, name, desc) VALUES (?, ?, ?)",
+                vec![1.into(), "a".into(), SqlValue::Null]
+            )
+        );
+        let query = QueryBuilder::insert("t")
+            .value("name", "a")
+            .returning("id")
+            .build();
+        assert_eq!(query.sql, "INSERT INTO t (name) VALUES (?) RETURNING id");
+        let query = QueryBuilder::insert("t")
+            .value("serial", "s")
+            .value("mac", "m")
+            .value("group_name", "g")
+            .on_conflict("serial", &["mac", "group_name"])
+            .build();
+        assert_eq!(
+            query.sql,
+            "INSERT INTO t (serial, mac, group_name) VALUES (?, ?, ?) \
+             ON CONFLICT(serial) DO UPDATE SET mac = excluded.mac, group_name = excluded.group_name"
+        );
+    }
+
+    #[test]
+    pub fn test_update() {
+        let query = QueryBuilder::update("t")
+            .set("name", "a")
+            .set("disabled", true)
+            .and_where(Cond::eq("id", 1))
+            .build();
+        assert_eq!(
+            query,
+            stmt(
+                "UPDATE t SET name = ?, disabled = ? WHERE id = ?",
+                vec!["a".into(), true.into(), 1.into()]
+            )
+        );
+        let query = QueryBuilder::update("t")
+            .set("step", 5)
+            .and_where(Cond::eq("id", 1))
+            .and_where(Cond::lt("step", 5))
+            .returning("id, step")
+            .build();
+        assert_eq!(
+            query.sql,
+            "UPDATE t SET step = ? WHERE id = ? AND step < ? RETURNING id, step"
+        );
+    }
+
+    #[test]
+    pub fn test_delete() {
+        let query = QueryBuilder::delete("t").build();
+        assert_eq!(query.sql, "DELETE FROM t");
+        let query = QueryBuilder::delete("t")
+            .and_where(Cond::eq("id", 1))
+            .and_where(Cond::is_null("owner"))
+            .build();
+        assert_eq!(
+            query,
+            stmt(
+                "DELETE FROM t WHERE id = ? AND owner IS NULL",
+                vec![1.into()]
+            )
+        );
+    }
+
+    #[tokio::test]
+    pub async fn test_bind() {
+        let pool = sqlx::SqlitePool::connect("sqlite::memory:").await.unwrap();
+        sqlx::query("CREATE TABLE t (id INTEGER PRIMARY KEY, name TEXT, flag INTEGER, note TEXT)")
+            .execute(&pool)
+            .await
+            .unwrap();
+        for (name, flag) in [("a", true), ("b", false), ("c", true)] {
+            let id: i32 = QueryBuilder::insert("t")
+                .value("name", name)
+                .value("flag", flag)
+                .value("note", (name == "b").then_some("x"))
+                .returning("id")
+                .build()
+                .query_scalar()
+                .fetch_one(&pool)
+                .await
+                .unwrap();
+            assert!(id > 0);
+        }
+        let names: Vec<String> = QueryBuilder::select("t", "name")
+            .and_where(Cond::or([Cond::eq("flag", true), Cond::not_null("note")]))
+            .and_where(Cond::not_in("name", ["c"]))
+            .order_desc("name")
+            .build()
+            .query_scalar()
+            .fetch_all(&pool)
+            .await
+            .unwrap();
+        assert_eq!(names, vec!["b", "a"]);
+        let rows = QueryBuilder::update("t")
+            .set("note", None::<String>)
+            .and_where(Cond::eq("name", "b"))
+            .build()
+            .query()
+            .execute(&pool)
+            .await
+            .unwrap()
+            .rows_affected();
+        assert_eq!(rows, 1);
+        let total: i64 = QueryBuilder::count("t")
+            .and_where(Cond::is_null("note"))
+            .build()
+            .query_scalar()
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(total, 3);
+    }
+}
@@ -0,0 +1,178 @@
+use std::{
+    fs,
+    io::{Error, ErrorKind, Result},
+    path::Path,
+};
+
+use crypto::ed25519;
+use once_cell::sync::OnceCell;
+use serde::Deserialize;
+
+/// 密钥文件格式:
+///
+/// ```json
+/// { "active": "2022-04", "keys": [{ "id": "2022-04", "seed": "<32 字节十六进制>" }] }
+/// ```
+///
+/// 轮换密钥时追加新密钥并修改 `active`, 旧密钥保留用于校验历史签名
+#[derive(Deserialize)]
+struct KeyFile {
+    active: String,
+    keys: Vec<KeyEntry>,
+}
+
+#[derive(Deserialize)]
+struct KeyEntry {
+    id: String,
+    seed: String,
+}
+
+struct SigningKey {
+    id: String,
+    secret: [u8; 64],
+    public: [u8; 32],
+}
+
+/// Ed25519 签名密钥
+#[derive(Default)]
+pub struct KeyStore {
+    active: Option<String>,
+    keys: Vec<SigningKey>,
+}
+
+/// 签名结果
+pub struct Signature {
+    pub key_id: String,
+    pub signature: String,
+}
+
+static KEYSTORE: OnceCell<KeyStore> = OnceCell::new();
+
+/// 启动时设置全局密钥
+pub fn init_keystore(store: KeyStore) {
+    let _ = KEYSTORE.set(store);
+}
+
+pub fn keystore() -> &'static KeyStore {
+    KEYSTORE.get_or_init(KeyStore::default)
+}
+
+impl KeyStore {
+    /// 读取密钥文件, 文件不存在时返回空的密钥库
+    pub fn load(path: impl AsRef<Path>) -> Result<KeyStore> {
+        let content = match fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(KeyStore::default()),
+            Err(e) => return Err(e),
+        };
+        let file: KeyFile =
+            serde_json::from_str(&content).map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+        let mut keys = Vec::with_capacity(file.keys.len());
+        for k in file.keys {
+            let seed = from_hex(&k.seed).filter(|s| s.len() == 32).ok_or_else(|| {
+                Error::new(ErrorKind::InvalidData, format!("invalid seed: {}", k.id))
+            })?;
+            keys.push(SigningKey::from_seed(k.id, &seed));
+        }
+        if !keys.iter().any(|k| k.id == file.active) {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("active key not found: {}", file.active),
+            ));
+        }
+        Ok(KeyStore {
+            active: Some(file.active),
+            keys,
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.active.is_none()
+    }
+
+    pub fn active_key_id(&self) -> Option<&str> {
+        self.active.as_deref()
+    }
+
+    fn key(&self, id: &str) -> Option<&SigningKey> {
+        self.keys.iter().find(|k| k.id == id)
+    }
+
+    /// 使用当前密钥签名
+    pub fn sign(&self, message: &[u8]) -> Option<Signature> {
+        let key = self.key(self.active.as_deref()?)?;
+        Some(Signature {
+            key_id: key.id.clone(),
+            signature: to_hex(&ed25519::signature(message, &key.secret)),
+        })
+    }
+
+    pub fn verify(&self, key_id: &str, message: &[u8], signature: &str) -> bool {
+        match (self.key(key_id), from_hex(signature)) {
+            (Some(key), Some(sig)) if sig.len() == 64 => {
+                ed25519::verify(message, &key.public, &sig)
+            }
+            _ => false,
+        }
+    }
+
+    /// 所有公钥 `(key_id, 十六进制公钥)`
+    pub fn public_keys(&self) -> Vec<(String, String)> {
+        self.keys
+            .iter()
+            .map(|k| (k.id.clone(), to_hex(&k.public)))
+            .collect()
+    }
+}
+
+impl SigningKey {
+    fn from_seed(id: String, seed: &[u8]) -> SigningKey {
+        let (secret, public) = ed25519::keypair(seed);
+        SigningKey { id, secret, public }
+    }
+}
+
+pub fn to_hex(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+pub fn from_hex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{from_hex, KeyStore, SigningKey};
+
+    const SEED: &str = "9d61b19deffd5a60ba844af492ec2cc44449c5697b326919703bac031cae7f60";
+
+    #[test]
+    pub fn test_sign() {
+        let old = SigningKey::from_seed("old".to_string(), &from_hex(SEED).unwrap());
+        let new = SigningKey::from_seed("new".to_string(), &[7u8; 32]);
+        let mut store = KeyStore {
+            active: Some("old".to_string()),
+            keys: vec![old, new],
+        };
+        assert_eq!(
+            store.public_keys()[0].1,
+            "d75a980182b10ab7d54bfed3c964073a0ee172f3daa62325af021a68f707511a"
+        );
+        let sig = store.sign(b"").unwrap();
+        assert_eq!(sig.signature, "e5564300c360ac729086e2cc806e828a84877f1eb8e5d974d873e065224901555fb8821590a33bacc61e39701cf9b46bd25bf5f0595bbe24655141438e7a100b");
+
+        store.active = Some("new".to_string());
+        let rotated = store.sign(b"firm").unwrap();
+        assert_eq!(rotated.key_id, "new");
+        assert!(store.verify("new", b"firm", &rotated.signature));
+        assert!(store.verify("old", b"", &sig.signature));
+        assert!(!store.verify("old", b"firm", &rotated.signature));
+        assert!(KeyStore::default().sign(b"firm").is_none());
+    }
+}
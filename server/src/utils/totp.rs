@@ -0,0 +1,146 @@
+use crypto::{hmac::Hmac, mac::Mac, sha1::Sha1};
+use rand::{seq::SliceRandom, RngCore};
+
+/// 时间步长, 秒
+pub const STEP: i64 = 30;
+const DIGITS: u32 = 6;
+const BASE32: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+/// 恢复码字符, 去掉了易混淆的字符
+const RECOVERY_CHARS: &[u8] = b"abcdefghjkmnpqrstuvwxyz23456789";
+
+/// 生成 160 位随机密钥, base32 编码
+pub fn gen_secret() -> String {
+    let mut key = [0u8; 20];
+    rand::thread_rng().fill_bytes(&mut key);
+    base32_encode(&key)
+}
+
+/// 生成 `xxxx-xxxx` 格式的恢复码
+pub fn gen_recovery_code() -> String {
+    let mut rng = rand::thread_rng();
+    let mut code: String = (0..8)
+        .map(|_| *RECOVERY_CHARS.choose(&mut rng).unwrap() as char)
+        .collect();
+    code.insert(4, '-');
+    code
+}
+
+/// 恢复码统一为小写且去掉分隔符
+pub fn normalize_recovery_code(code: &str) -> String {
+    code.chars()
+        .filter(char::is_ascii_alphanumeric)
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
+
+fn base32_encode(data: &[u8]) -> String {
+    let mut out = String::with_capacity((data.len() * 8).div_ceil(5));
+    let (mut buffer, mut bits) = (0u32, 0);
+    for b in data {
+        buffer = (buffer << 8) | *b as u32;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            out.push(BASE32[((buffer >> bits) & 0x1f) as usize] as char);
+        }
+    }
+    if bits > 0 {
+        out.push(BASE32[((buffer << (5 - bits)) & 0x1f) as usize] as char);
+    }
+    out
+}
+
+fn base32_decode(s: &str) -> Option<Vec<u8>> {
+    let mut out = Vec::with_capacity(s.len() * 5 / 8);
+    let (mut buffer, mut bits) = (0u32, 0);
+    for c in s.bytes().filter(|c| *c != b'=' && *c != b' ') {
+        let v = BASE32.iter().position(|b| *b == c.to_ascii_uppercase())? as u32;
+        buffer = (buffer << 5) | v;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((buffer >> bits) as u8);
+        }
+    }
+    Some(out)
+}
+
+/// RFC 4226 HOTP
+fn hotp(key: &[u8], counter: u64) -> u32 {
+    let mut mac = Hmac::new(Sha1::new(), key);
+    mac.input(&counter.to_be_bytes());
+    let hash = mac.result();
+    let hash = hash.code();
+    let offset = (hash[hash.len() - 1] & 0xf) as usize;
+    let bin = u32::from_be_bytes([
+        hash[offset] & 0x7f,
+        hash[offset + 1],
+        hash[offset + 2],
+        hash[offset + 3],
+    ]);
+    bin % 10u32.pow(DIGITS)
+}
+
+/// 校验验证码, 允许前后各一个时间步的偏差, 返回匹配的时间步
+pub fn verify(secret: &str, code: &str, time: i64) -> Option<i64> {
+    let code = code.trim();
+    if code.len() != DIGITS as usize || !code.bytes().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let code: u32 = code.parse().ok()?;
+    let key = base32_decode(secret)?;
+    let step = time / STEP;
+    (step - 1..=step + 1).find(|s| *s >= 0 && hotp(&key, *s as u64) == code)
+}
+
+/// 供认证器扫码的 otpauth URI
+pub fn otpauth_uri(issuer: &str, account: &str, secret: &str) -> String {
+    format!(
+        "otpauth://totp/{0}:{1}?secret={2}&issuer={0}&algorithm=SHA1&digits={3}&period={4}",
+        uri_encode(issuer),
+        uri_encode(account),
+        secret,
+        DIGITS,
+        STEP
+    )
+}
+
+fn uri_encode(s: &str) -> String {
+    s.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{base32_decode, base32_encode, hotp, otpauth_uri, verify};
+
+    #[test]
+    pub fn test_totp() {
+        // RFC 6238 附录 B 的 SHA1 测试向量
+        let secret = base32_encode(b"12345678901234567890");
+        assert_eq!(secret, "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ");
+        assert_eq!(base32_decode(&secret).unwrap(), b"12345678901234567890");
+        let key = b"12345678901234567890";
+        assert_eq!(hotp(key, 59 / 30), 287082);
+        assert_eq!(hotp(key, 1111111109 / 30), 81804);
+        assert_eq!(hotp(key, 1234567890 / 30), 5924);
+
+        assert_eq!(verify(&secret, "081804", 1111111109), Some(1111111109 / 30));
+        assert_eq!(
+            verify(&secret, "081804", 1111111109 + 30),
+            Some(1111111109 / 30)
+        );
+        assert_eq!(verify(&secret, "081804", 1111111109 + 90), None);
+        assert_eq!(verify(&secret, "81804", 1111111109), None);
+        assert_eq!(
+            otpauth_uri("firm", "a@b.c", "ABC"),
+            "otpauth://totp/firm:a%40b.c?secret=ABC&issuer=firm&algorithm=SHA1&digits=6&period=30"
+        );
+    }
+}
@@ -0,0 +1,63 @@
+use std::{
+    collections::HashMap,
+    hash::Hash,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// 简单的进程内过期缓存
+pub struct TtlCache<K, V> {
+    ttl: Duration,
+    entries: Mutex<HashMap<K, (V, Instant)>>,
+}
+
+impl<K: Eq + Hash, V: Clone> TtlCache<K, V> {
+    pub fn new(ttl: Duration) -> Self {
+        TtlCache {
+            ttl,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn get(&self, key: &K) -> Option<V> {
+        let mut entries = self.entries.lock().unwrap();
+        match entries.get(key) {
+            Some((v, at)) if at.elapsed() < self.ttl => Some(v.clone()),
+            Some(_) => {
+                entries.remove(key);
+                None
+            }
+            None => None,
+        }
+    }
+
+    pub fn insert(&self, key: K, value: V) {
+        let mut entries = self.entries.lock().unwrap();
+        let ttl = self.ttl;
+        entries.retain(|_, (_, at)| at.elapsed() < ttl);
+        entries.insert(key, (value, Instant::now()));
+    }
+
+    pub fn remove(&self, key: &K) {
+        self.entries.lock().unwrap().remove(key);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::TtlCache;
+
+    #[test]
+    pub fn test_ttl() {
+        let cache = TtlCache::new(Duration::from_millis(50));
+        cache.insert(1, "a");
+        assert_eq!(cache.get(&1), Some("a"));
+        cache.remove(&1);
+        assert_eq!(cache.get(&1), None);
+        cache.insert(2, "b");
+        std::thread::sleep(Duration::from_millis(60));
+        assert_eq!(cache.get(&2), None);
+    }
+}
@@ -0,0 +1,293 @@
+use std::{
+    cmp::Ordering,
+    fmt::{self, Write},
+};
+
+/// 版本号格式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VersionFormat {
+    /// `1.2.3`, `1.2.3-beta.1+build`
+    Semver,
+    /// `1.0`, `1.0.2.7`
+    Dotted,
+    /// `20220401`, `20220401.3`, `20220401-3`
+    Date,
+}
+
+/// 可比较的固件版本号
+#[derive(Debug, Clone)]
+pub struct Version {
+    pub format: VersionFormat,
+    parts: Vec<u64>,
+    pre: Vec<String>,
+}
+
+impl Version {
+    /// 解析 `version_format` 中保存的版本号
+    pub fn parse(s: &str) -> Option<Version> {
+        let s = s.trim();
+        if s.is_empty() {
+            return None;
+        }
+        Self::parse_date(s).or_else(|| Self::parse_numeric(s))
+    }
+
+    /// 解析设备上报的十六进制版本号 (`version_name`), 每个字节一段, 低位在前
+    pub fn from_hex(s: &str) -> Option<Version> {
+        let s = s.trim();
+        if s.is_empty() || !s.len().is_multiple_of(2) || !s.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return None;
+        }
+        let mut parts = Vec::with_capacity(s.len() / 2);
+        for i in (0..s.len()).step_by(2).rev() {
+            parts.push(u64::from_str_radix(&s[i..i + 2], 16).ok()?);
+        }
+        Some(Version {
+            format: VersionFormat::Dotted,
+            parts,
+            pre: Vec::new(),
+        })
+    }
+
+    /// 解析设备端或升级限制 (`min`/`max`) 中的版本号, 纯十六进制按 `from_hex` 处理
+    pub fn parse_device(s: &str) -> Option<Version> {
+        let s = s.trim();
+        if Self::parse_date(s).is_none() {
+            if let Some(v) = Self::from_hex(s) {
+                return Some(v);
+            }
+        }
+        Self::parse(s)
+    }
+
+    /// 按字节比较时与 `Ord` 顺序一致的排序键, 用于在 SQL 中排序
+    pub fn sort_key(&self) -> String {
+        // 末尾的 0 不影响比较, `1.0` 与 `1.0.0` 的键相同
+        let len = self
+            .parts
+            .iter()
+            .rposition(|p| *p != 0)
+            .map_or(0, |i| i + 1);
+        let mut key = String::with_capacity(len * 16 + 1);
+        for p in &self.parts[..len] {
+            let _ = write!(key, "{:016x}", p);
+        }
+        // `-` < `.` < 数字: 预发布版在正式版之前, 两者都在更长的版本号之前
+        if self.pre.is_empty() {
+            key.push('.');
+            return key;
+        }
+        key.push('-');
+        for (i, id) in self.pre.iter().enumerate() {
+            if i > 0 {
+                key.push('.');
+            }
+            let _ = match id.parse::<u64>() {
+                Ok(n) => write!(key, "0{:016x}", n),
+                Err(_) => write!(key, "1{}", id),
+            };
+        }
+        key
+    }
+
+    fn parse_date(s: &str) -> Option<Version> {
+        if s.len() < 8 || !s.is_char_boundary(8) {
+            return None;
+        }
+        let (date, rest) = s.split_at(8);
+        if !date.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        let year: u64 = date[0..4].parse().ok()?;
+        let month: u64 = date[4..6].parse().ok()?;
+        let day: u64 = date[6..8].parse().ok()?;
+        if !(1970..=2999).contains(&year) || !(1..=12).contains(&month) || !(1..=31).contains(&day)
+        {
+            return None;
+        }
+        let build = match rest.chars().next() {
+            None => 0,
+            Some('.' | '-' | '_') => rest[1..].parse().ok()?,
+            Some(_) => return None,
+        };
+        Some(Version {
+            format: VersionFormat::Date,
+            parts: vec![year * 10000 + month * 100 + day, build],
+            pre: Vec::new(),
+        })
+    }
+
+    fn parse_numeric(s: &str) -> Option<Version> {
+        let s = s.strip_prefix(['v', 'V']).unwrap_or(s);
+        let core = s.split('+').next()?;
+        let (core, pre) = match core.split_once('-') {
+            Some((core, pre)) => (core, Some(pre)),
+            None => (core, None),
+        };
+        let mut parts = Vec::new();
+        for p in core.split('.') {
+            if p.is_empty() || !p.bytes().all(|b| b.is_ascii_digit()) {
+                return None;
+            }
+            parts.push(p.parse().ok()?);
+        }
+        let pre: Vec<String> = match pre {
+            Some(pre) => {
+                let ids: Vec<String> = pre.split('.').map(String::from).collect();
+                if ids.iter().any(|i| i.is_empty()) {
+                    return None;
+                }
+                ids
+            }
+            None => Vec::new(),
+        };
+        let format = if parts.len() == 3 || !pre.is_empty() || s.contains('+') {
+            VersionFormat::Semver
+        } else {
+            VersionFormat::Dotted
+        };
+        if format == VersionFormat::Semver && parts.len() != 3 {
+            return None;
+        }
+        Some(Version { format, parts, pre })
+    }
+}
+
+fn cmp_pre(a: &[String], b: &[String]) -> Ordering {
+    match (a.is_empty(), b.is_empty()) {
+        (true, true) => return Ordering::Equal,
+        (true, false) => return Ordering::Greater,
+        (false, true) => return Ordering::Less,
+        _ => {}
+    }
+    for (x, y) in a.iter().zip(b) {
+        let ord = match (x.parse::<u64>(), y.parse::<u64>()) {
+            (Ok(x), Ok(y)) => x.cmp(&y),
+            (Ok(_), Err(_)) => Ordering::Less,
+            (Err(_), Ok(_)) => Ordering::Greater,
+            (Err(_), Err(_)) => x.cmp(y),
+        };
+        if ord != Ordering::Equal {
+            return ord;
+        }
+    }
+    a.len().cmp(&b.len())
+}
+
+impl Ord for Version {
+    fn cmp(&self, other: &Self) -> Ordering {
+        let len = self.parts.len().max(other.parts.len());
+        for i in 0..len {
+            let a = self.parts.get(i).copied().unwrap_or(0);
+            let b = other.parts.get(i).copied().unwrap_or(0);
+            match a.cmp(&b) {
+                Ordering::Equal => continue,
+                ord => return ord,
+            }
+        }
+        cmp_pre(&self.pre, &other.pre)
+    }
+}
+
+impl PartialOrd for Version {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Version {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Version {}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.format == VersionFormat::Date {
+            return write!(f, "{}.{}", self.parts[0], self.parts[1]);
+        }
+        let parts: Vec<String> = self.parts.iter().map(u64::to_string).collect();
+        write!(f, "{}", parts.join("."))?;
+        if !self.pre.is_empty() {
+            write!(f, "-{}", self.pre.join("."))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Version, VersionFormat};
+
+    fn v(s: &str) -> Version {
+        Version::parse(s).unwrap()
+    }
+
+    #[test]
+    pub fn test_format() {
+        assert_eq!(v("1.2.3").format, VersionFormat::Semver);
+        assert_eq!(v("1.2.3-rc.1+42").format, VersionFormat::Semver);
+        assert_eq!(v("1.2").format, VersionFormat::Dotted);
+        assert_eq!(v("1.0.2.7").format, VersionFormat::Dotted);
+        assert_eq!(v("20220401").format, VersionFormat::Date);
+        assert_eq!(v("20220401-3").format, VersionFormat::Date);
+        assert!(Version::parse("1.2-rc").is_none());
+        assert!(Version::parse("1..2").is_none());
+        assert!(Version::parse("abc").is_none());
+        assert!(Version::parse("20221301x").is_none());
+    }
+
+    #[test]
+    pub fn test_order() {
+        assert!(v("1.10.2") > v("1.9.0"));
+        assert!(v("1.0.0") > v("1.0.0-rc.2"));
+        assert!(v("1.0.0-rc.10") > v("1.0.0-rc.2"));
+        assert!(v("1.0.0-beta") > v("1.0.0-alpha.1"));
+        assert!(v("1.0") == v("1.0.0.0"));
+        assert!(v("20220401.2") > v("20220401"));
+        assert!(v("20220402") > v("20220401.9"));
+    }
+
+    #[test]
+    pub fn test_sort_key() {
+        let versions = [
+            "0.9",
+            "1.0.0-alpha",
+            "1.0.0-alpha.1",
+            "1.0.0-alpha.beta",
+            "1.0.0-beta.2",
+            "1.0.0-beta.11",
+            "1.0.0-rc.1",
+            "1.0",
+            "1.0.0.1",
+            "1.2.3",
+            "1.10.0",
+            "20220401",
+            "20220401.2",
+        ];
+        for (i, a) in versions.iter().enumerate() {
+            for b in &versions[i + 1..] {
+                assert!(v(a) < v(b), "{} < {}", a, b);
+                assert!(v(a).sort_key() < v(b).sort_key(), "{} < {}", a, b);
+            }
+        }
+        assert_eq!(v("1.0").sort_key(), v("1.0.0").sort_key());
+    }
+
+    #[test]
+    pub fn test_hex() {
+        let hex = Version::from_hex("030201").unwrap();
+        assert_eq!(hex.to_string(), "1.2.3");
+        assert!(hex == v("1.2.3"));
+        assert!(Version::from_hex("0102g0").is_none());
+        assert!(Version::from_hex("010").is_none());
+        assert_eq!(Version::parse_device("0a01").unwrap().to_string(), "1.10");
+        assert_eq!(
+            Version::parse_device("20220401").unwrap().format,
+            VersionFormat::Date
+        );
+        assert_eq!(Version::parse_device("1.2.3").unwrap().to_string(), "1.2.3");
+    }
+}